@@ -0,0 +1,119 @@
+//! Compares the fast paths of `display::Display` with the generic, pixel by pixel `DrawTarget` implementations
+//!
+//! Run with `cargo bench --bench display --no-default-features --features io --target <host target>`.
+
+use core::convert::Infallible;
+use core::hint::black_box;
+
+use std::time::{Duration, Instant};
+
+use embedded_graphics_core::pixelcolor::{Rgb888, RgbColor};
+use embedded_graphics_core::prelude::*;
+use embedded_graphics_core::primitives::Rectangle;
+
+use hal_sim::display::Display;
+use hal_sim::simulator::Simulator;
+
+const WIDTH: usize = 320;
+const HEIGHT: usize = 240;
+
+const ITERATIONS: u32 = 200;
+
+/// Forwards to `draw_iter` only, so that the default implementations of the other `DrawTarget` methods are used
+struct Generic<'a>(&'a mut Display<Rgb888>);
+
+impl Dimensions for Generic<'_> {
+    fn bounding_box(&self) -> Rectangle {
+        self.0.bounding_box()
+    }
+}
+
+impl DrawTarget for Generic<'_> {
+    type Color = Rgb888;
+
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        self.0.draw_iter(pixels)
+    }
+}
+
+fn bench(name: &str, mut f: impl FnMut(u32)) -> Duration {
+    // Warm up
+    f(0);
+
+    let start = Instant::now();
+
+    for iteration in 1..=ITERATIONS {
+        f(iteration);
+    }
+
+    let elapsed = start.elapsed() / ITERATIONS;
+
+    println!("{name:<40} {elapsed:>12.2?}");
+
+    elapsed
+}
+
+fn color(iteration: u32) -> Rgb888 {
+    Rgb888::new(iteration as u8, 0x80, !(iteration as u8))
+}
+
+fn report(name: &str, generic: Duration, fast: Duration) {
+    println!(
+        "{:<40} {:>11.1}x",
+        format!("{name} speedup"),
+        generic.as_secs_f64() / fast.as_secs_f64()
+    );
+}
+
+/// Runs `$f` with the generic and then with the fast implementations of `$display`
+macro_rules! compare {
+    ($name:expr, $display:expr, |$target:ident, $iteration:ident| $f:expr) => {{
+        let generic = bench(&format!("{} (generic)", $name), |$iteration| {
+            let $target = &mut Generic(&mut $display);
+            $f
+        });
+
+        let fast = bench(&format!("{} (fast)", $name), |$iteration| {
+            let $target = &mut $display;
+            $f
+        });
+
+        report($name, generic, fast);
+    }};
+}
+
+fn main() {
+    let simulator = Simulator::new();
+    let mut peripherals = simulator.take().unwrap();
+
+    let mut display = peripherals
+        .displays
+        .display("Bench", WIDTH, HEIGHT, |color: Rgb888| {
+            u32::from_be_bytes([0, color.r(), color.g(), color.b()])
+        });
+
+    let area = Rectangle::new(Point::new(40, 30), Size::new(200, 150));
+
+    compare!("clear", display, |target, iteration| {
+        target.clear(color(iteration)).unwrap()
+    });
+
+    compare!("fill_solid 200x150", display, |target, iteration| {
+        target.fill_solid(&area, color(iteration)).unwrap()
+    });
+
+    compare!("fill_contiguous 200x150", display, |target, iteration| {
+        let colors = (0..area.size.width * area.size.height)
+            .map(|index| color(iteration.wrapping_add(index)));
+
+        target.fill_contiguous(&area, colors).unwrap()
+    });
+
+    // Consume the accumulated changes, as a connected UI would
+    while black_box(simulator.fetch()).is_some() {}
+}
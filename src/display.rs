@@ -8,24 +8,32 @@ use log::trace;
 use std::sync::Mutex;
 
 use embedded_graphics_core::{
+    pixelcolor::{
+        Bgr555, Bgr565, Bgr666, Bgr888, BinaryColor, Gray2, Gray4, Gray8, GrayColor, Rgb555,
+        Rgb565, Rgb666, Rgb888, RgbColor,
+    },
     prelude::{Dimensions, DrawTarget, PixelColor, Point, Size},
-    primitives::Rectangle,
+    primitives::{PointsIter, Rectangle},
     Pixel,
 };
 
+use crate::touch::{SharedTouch, Touch, TouchPoints};
+
 pub use crate::dto::display::*;
 
-pub(crate) static DISPLAYS: Mutex<Vec<DisplayState>> = Mutex::new(Vec::new());
+pub type SharedDisplays = Arc<Mutex<Vec<DisplayState>>>;
 
 pub struct Displays {
     id_gen: u8,
+    displays: SharedDisplays,
     changed: DisplaysChangedCallback,
 }
 
 impl Displays {
-    pub(crate) fn new(changed: impl Fn() + 'static) -> Self {
+    pub(crate) fn new(displays: SharedDisplays, changed: impl Fn() + 'static) -> Self {
         Self {
             id_gen: 0,
+            displays,
             changed: Arc::new(changed),
         }
     }
@@ -37,27 +45,188 @@ impl Displays {
         height: usize,
         converter: impl Fn(C) -> u32 + 'static,
     ) -> Display<C>
+    where
+        C: Clone + Default,
+    {
+        self.create(name, width, height, None, converter)
+    }
+
+    /// Creates an e-paper display
+    ///
+    /// Everything drawn goes to a back buffer, which only becomes visible with `EpaperDisplay::refresh_full`
+    /// or `EpaperDisplay::refresh_partial`. The UI delays the refreshes by `latency`, and flashes the
+    /// display during full refreshes.
+    pub fn epaper<C>(
+        &mut self,
+        name: impl TryInto<DisplayName>,
+        width: usize,
+        height: usize,
+        latency: EpaperLatency,
+        converter: impl Fn(C) -> u32 + 'static,
+    ) -> EpaperDisplay<C>
+    where
+        C: Clone + Default,
+    {
+        EpaperDisplay(self.create(name, width, height, Some(latency), converter))
+    }
+
+    fn create<C>(
+        &mut self,
+        name: impl TryInto<DisplayName>,
+        width: usize,
+        height: usize,
+        epaper: Option<EpaperLatency>,
+        converter: impl Fn(C) -> u32 + 'static,
+    ) -> Display<C>
     where
         C: Clone + Default,
     {
         let id = self.id_gen;
         self.id_gen += 1;
 
-        let state = DisplayState::new(name.try_into().map_err(|_| ()).unwrap(), width, height);
+        let state = DisplayState::new(
+            name.try_into().map_err(|_| ()).unwrap(),
+            width,
+            height,
+            epaper,
+        );
 
         {
-            let mut states = DISPLAYS.lock().unwrap();
+            let mut states = self.displays.lock().unwrap();
             states.push(state);
         }
 
-        Display::new(id, self.changed.clone(), converter)
+        Display::new(id, self.displays.clone(), self.changed.clone(), converter)
+    }
+
+    /// Creates a display for the color format `C`, converting its colors to RGB with the default palette of `C`
+    pub fn display_for<C>(
+        &mut self,
+        name: impl TryInto<DisplayName>,
+        width: usize,
+        height: usize,
+    ) -> Display<C>
+    where
+        C: DisplayColor,
+    {
+        self.display_with_palette(name, width, height, C::Palette::default())
+    }
+
+    /// Same as `display_for`, but with a custom palette, e.g. `BinaryPalette::EPAPER` for `BinaryColor` displays
+    pub fn display_with_palette<C>(
+        &mut self,
+        name: impl TryInto<DisplayName>,
+        width: usize,
+        height: usize,
+        palette: C::Palette,
+    ) -> Display<C>
+    where
+        C: DisplayColor,
+    {
+        self.display(name, width, height, move |color: C| {
+            color.to_rgb888(&palette)
+        })
     }
 }
 
+/// Pixel colors which `Displays::display_for` knows how to convert to the RGB888 colors of the simulated displays
+pub trait DisplayColor: PixelColor + Default {
+    /// The configuration of the conversion; `()` for all but `BinaryColor`
+    type Palette: Default + 'static;
+
+    /// Converts the color to RGB888, as `0x00RRGGBB`
+    fn to_rgb888(self, palette: &Self::Palette) -> u32;
+}
+
+/// The colors of the two states of a `BinaryColor` display, as `0x00RRGGBB`
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct BinaryPalette {
+    pub on: u32,
+    pub off: u32,
+}
+
+impl BinaryPalette {
+    /// White pixels on black, as e.g. on most monochrome OLED displays
+    pub const WHITE: Self = Self {
+        on: 0xffffff,
+        off: 0x000000,
+    };
+
+    /// Blue pixels on black, as on blue OLED displays
+    pub const OLED_BLUE: Self = Self {
+        on: 0x40c0ff,
+        off: 0x000000,
+    };
+
+    /// Black ink on white paper, as on e-paper displays
+    pub const EPAPER: Self = Self {
+        on: 0x000000,
+        off: 0xf0f0f0,
+    };
+}
+
+impl Default for BinaryPalette {
+    fn default() -> Self {
+        Self::WHITE
+    }
+}
+
+impl DisplayColor for BinaryColor {
+    type Palette = BinaryPalette;
+
+    fn to_rgb888(self, palette: &Self::Palette) -> u32 {
+        match self {
+            Self::On => palette.on,
+            Self::Off => palette.off,
+        }
+    }
+}
+
+fn scale(value: u8, max: u8) -> u32 {
+    (value as u32 * 255 + max as u32 / 2) / max as u32
+}
+
+macro_rules! rgb_display_color {
+    ($($color:ty),*) => {
+        $(
+            impl DisplayColor for $color {
+                type Palette = ();
+
+                fn to_rgb888(self, _palette: &Self::Palette) -> u32 {
+                    scale(self.r(), Self::MAX_R) << 16
+                        | scale(self.g(), Self::MAX_G) << 8
+                        | scale(self.b(), Self::MAX_B)
+                }
+            }
+        )*
+    };
+}
+
+rgb_display_color!(Rgb555, Rgb565, Rgb666, Rgb888, Bgr555, Bgr565, Bgr666, Bgr888);
+
+macro_rules! gray_display_color {
+    ($($color:ty),*) => {
+        $(
+            impl DisplayColor for $color {
+                type Palette = ();
+
+                fn to_rgb888(self, _palette: &Self::Palette) -> u32 {
+                    let luma = scale(self.luma(), Self::WHITE.luma());
+
+                    luma << 16 | luma << 8 | luma
+                }
+            }
+        )*
+    };
+}
+
+gray_display_color!(Gray2, Gray4, Gray8);
+
 pub type DisplaysChangedCallback = Arc<dyn Fn()>;
 
 pub struct Display<C> {
     id: u8,
+    displays: SharedDisplays,
     changed: Arc<dyn Fn()>,
     converter: Box<dyn Fn(C) -> u32>,
 }
@@ -66,19 +235,68 @@ impl<C> Display<C>
 where
     C: Clone + Default,
 {
-    fn new(id: u8, changed: Arc<dyn Fn()>, converter: impl Fn(C) -> u32 + 'static) -> Self {
+    fn new(
+        id: u8,
+        displays: SharedDisplays,
+        changed: Arc<dyn Fn()>,
+        converter: impl Fn(C) -> u32 + 'static,
+    ) -> Self {
         Self {
             id,
+            displays,
             changed,
             converter: Box::new(converter),
         }
     }
 }
 
+impl<C> Display<C> {
+    /// Sets how the panel is mounted
+    ///
+    /// The firmware draws in the rotated orientation, and the UI shows the display rotated.
+    pub fn with_rotation(mut self, rotation: Rotation) -> Self {
+        self.update_meta(|meta| meta.rotation = rotation);
+        self
+    }
+
+    /// Mirrors the display horizontally
+    pub fn with_mirroring(mut self, mirrored: bool) -> Self {
+        self.update_meta(|meta| meta.mirrored = mirrored);
+        self
+    }
+
+    /// Sets the initial zoom factor of the display in the UI, e.g. for small panels
+    pub fn with_zoom(mut self, zoom: u8) -> Self {
+        self.update_meta(|meta| meta.zoom = zoom.max(1));
+        self
+    }
+
+    /// Renders the display as an LCD panel in the UI, with a pixel grid, backlight and gamma
+    pub fn with_lcd_effect(mut self, effect: LcdEffect) -> Self {
+        self.update_meta(|meta| meta.lcd = Some(effect));
+        self
+    }
+
+    /// The touch panel over the display
+    pub fn touch(&self) -> Touch {
+        Touch::new(self.id, self.displays.clone())
+    }
+
+    fn update_meta(&mut self, f: impl FnOnce(&mut DisplayMeta)) {
+        {
+            let mut guard = self.displays.lock().unwrap();
+
+            guard[self.id as usize].update_meta(f);
+        }
+
+        (self.changed)();
+    }
+}
+
 impl<C> Drop for Display<C> {
     fn drop(&mut self) {
         {
-            let mut guard = DISPLAYS.lock().unwrap();
+            let mut guard = self.displays.lock().unwrap();
             let state = &mut guard[self.id as usize];
 
             state.display.dropped = true;
@@ -102,7 +320,7 @@ where
         I: IntoIterator<Item = Pixel<Self::Color>>,
     {
         let changed = {
-            let mut guard = DISPLAYS.lock().unwrap();
+            let mut guard = self.displays.lock().unwrap();
 
             guard[self.id as usize].draw_iter(
                 pixels
@@ -117,21 +335,144 @@ where
 
         Ok(())
     }
+
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Self::Color>,
+    {
+        let changed = {
+            let mut guard = self.displays.lock().unwrap();
+
+            guard[self.id as usize].fill_contiguous(
+                area,
+                colors.into_iter().map(|color| (self.converter)(color)),
+            )
+        };
+
+        if changed {
+            (self.changed)();
+        }
+
+        Ok(())
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        let color = (self.converter)(color);
+
+        let changed = {
+            let mut guard = self.displays.lock().unwrap();
+
+            guard[self.id as usize].fill_solid(area, color)
+        };
+
+        if changed {
+            (self.changed)();
+        }
+
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.fill_solid(&self.bounding_box(), color)
+    }
 }
 
 impl<C> Dimensions for Display<C> {
     fn bounding_box(&self) -> Rectangle {
-        let guard = DISPLAYS.lock().unwrap();
+        let guard = self.displays.lock().unwrap();
 
-        let state = &guard[self.id as usize];
+        let (width, height) = guard[self.id as usize].display.meta.logical_size();
 
-        Rectangle::new(
-            Point::new(0, 0),
-            Size::new(
-                state.display.meta.width as _,
-                state.display.meta.height as _,
-            ),
-        )
+        Rectangle::new(Point::new(0, 0), Size::new(width as _, height as _))
+    }
+}
+
+/// An e-paper display, as created by `Displays::epaper`
+///
+/// Drawing only updates the back buffer of the display; the refreshes copy it to the visible front buffer.
+pub struct EpaperDisplay<C>(Display<C>);
+
+impl<C> EpaperDisplay<C> {
+    /// Same as `Display::with_rotation`
+    pub fn with_rotation(self, rotation: Rotation) -> Self {
+        Self(self.0.with_rotation(rotation))
+    }
+
+    /// Same as `Display::with_mirroring`
+    pub fn with_mirroring(self, mirrored: bool) -> Self {
+        Self(self.0.with_mirroring(mirrored))
+    }
+
+    /// Same as `Display::with_zoom`
+    pub fn with_zoom(self, zoom: u8) -> Self {
+        Self(self.0.with_zoom(zoom))
+    }
+
+    /// Same as `Display::with_lcd_effect`
+    pub fn with_lcd_effect(self, effect: LcdEffect) -> Self {
+        Self(self.0.with_lcd_effect(effect))
+    }
+
+    /// Same as `Display::touch`
+    pub fn touch(&self) -> Touch {
+        self.0.touch()
+    }
+
+    /// Refreshes the whole display, flashing it in the UI
+    pub fn refresh_full(&mut self) {
+        self.refresh(Refresh::Full);
+    }
+
+    /// Refreshes only the pixels which changed since the last refresh
+    pub fn refresh_partial(&mut self) {
+        self.refresh(Refresh::Partial);
+    }
+
+    fn refresh(&mut self, refresh: Refresh) {
+        {
+            let mut guard = self.0.displays.lock().unwrap();
+
+            guard[self.0.id as usize].refresh(refresh);
+        }
+
+        (self.0.changed)();
+    }
+}
+
+impl<C> DrawTarget for EpaperDisplay<C>
+where
+    C: PixelColor,
+{
+    type Color = C;
+
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        self.0.draw_iter(pixels)
+    }
+
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Self::Color>,
+    {
+        self.0.fill_contiguous(area, colors)
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        self.0.fill_solid(area, color)
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.0.clear(color)
+    }
+}
+
+impl<C> Dimensions for EpaperDisplay<C> {
+    fn bounding_box(&self) -> Rectangle {
+        self.0.bounding_box()
     }
 }
 
@@ -141,12 +482,13 @@ pub struct DisplayState {
 }
 
 impl DisplayState {
-    fn new(name: DisplayName, width: usize, height: usize) -> Self {
+    fn new(name: DisplayName, width: usize, height: usize, epaper: Option<EpaperLatency>) -> Self {
         Self {
-            display: SharedDisplay::new(name, width, height),
+            display: SharedDisplay::new(name, width, height, epaper),
             change: Change {
                 created: true,
                 dropped: false,
+                refresh: None,
                 screen_updates: Vec::new(),
             },
         }
@@ -170,24 +512,72 @@ impl DisplayState {
     {
         self.display.draw_iter(&mut self.change, pixels)
     }
+
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> bool
+    where
+        I: IntoIterator<Item = u32>,
+    {
+        self.display.fill_contiguous(&mut self.change, area, colors)
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: u32) -> bool {
+        self.display.fill_solid(&mut self.change, area, color)
+    }
+
+    fn refresh(&mut self, refresh: Refresh) {
+        self.display.refresh(&mut self.change, refresh)
+    }
+
+    /// Replaces the contacts on the touch panel of the display, dropping those outside of the display
+    pub(crate) fn update_touch(&mut self, mut points: TouchPoints) {
+        if !self.display.dropped {
+            let (width, height) = self.display.meta.logical_size();
+
+            points.retain(|point| (point.x as usize) < width && (point.y as usize) < height);
+
+            self.display.touch.update(points);
+        }
+    }
+
+    pub(crate) fn touch_mut(&mut self) -> &mut SharedTouch {
+        &mut self.display.touch
+    }
+
+    fn update_meta(&mut self, f: impl FnOnce(&mut DisplayMeta)) {
+        f(&mut self.display.meta);
+
+        // Resend the meta, and the pixels to UIs which recreate the framebuffer with it
+        self.change.created = true;
+
+        for row in 0..self.display.meta.height {
+            self.change.update_row(row, 0, self.display.meta.width);
+        }
+    }
 }
 
 pub struct SharedDisplay {
     meta: DisplayMeta,
     dropped: bool,
     buffer: Vec<u32>,
+    /// The buffer which is drawn to, for e-paper displays
+    back_buffer: Option<Vec<u32>>,
+    touch: SharedTouch,
 }
 
 impl SharedDisplay {
-    fn new(name: DisplayName, width: usize, height: usize) -> Self {
+    fn new(name: DisplayName, width: usize, height: usize, epaper: Option<EpaperLatency>) -> Self {
         Self {
             meta: DisplayMeta {
                 name,
                 width,
                 height,
+                epaper,
+                ..Default::default()
             },
             dropped: false,
             buffer: vec![0; width * height],
+            back_buffer: epaper.map(|_| vec![0; width * height]),
+            touch: SharedTouch::new(),
         }
     }
 
@@ -203,28 +593,31 @@ impl SharedDisplay {
         &self.buffer
     }
 
+    pub fn touch(&self) -> &SharedTouch {
+        &self.touch
+    }
+
     fn draw_iter<I>(&mut self, changed_state: &mut Change, pixels: I) -> bool
     where
         I: IntoIterator<Item = (Point, u32)>,
     {
         let mut changed = false;
 
-        for pixel in pixels {
-            if pixel.0.x >= 0
-                && pixel.0.x < self.meta.width as _
-                && pixel.0.y >= 0
-                && pixel.0.y < self.meta.height as _
-            {
-                let x = pixel.0.x as usize;
-                let y = pixel.0.y as usize;
+        // Drawing to the back buffer of an e-paper display does not change anything visible
+        let tracked = self.back_buffer.is_none();
+        let buffer = self.back_buffer.as_mut().unwrap_or(&mut self.buffer);
 
-                let cell = &mut self.buffer[y * self.meta.width + x];
+        for pixel in pixels {
+            if let Some((x, y)) = self.meta.to_framebuffer(pixel.0.x, pixel.0.y) {
+                let cell = &mut buffer[y * self.meta.width + x];
 
                 if *cell != pixel.1 {
                     *cell = pixel.1;
 
-                    changed_state.update_row(y, x, x + 1);
-                    changed = true;
+                    if tracked {
+                        changed_state.update_row(y, x, x + 1);
+                        changed = true;
+                    }
 
                     trace!("Updated pixel x={} y={}", x, y);
                 }
@@ -233,4 +626,458 @@ impl SharedDisplay {
 
         changed
     }
+
+    fn fill_contiguous<I>(
+        &mut self,
+        changed_state: &mut Change,
+        area: &Rectangle,
+        colors: I,
+    ) -> bool
+    where
+        I: IntoIterator<Item = u32>,
+    {
+        if self.meta.is_transformed() {
+            return self.draw_iter(changed_state, area.points().zip(colors));
+        }
+
+        let mut changed = false;
+        let mut colors = colors.into_iter();
+
+        let width = area.size.width as usize;
+
+        let tracked = self.back_buffer.is_none();
+        let buffer = self.back_buffer.as_mut().unwrap_or(&mut self.buffer);
+
+        for y in area.rows() {
+            let row_colors = colors.by_ref().take(width);
+
+            if y < 0 || y >= self.meta.height as _ {
+                row_colors.for_each(drop);
+                continue;
+            }
+
+            let y = y as usize;
+            let row = &mut buffer[y * self.meta.width..(y + 1) * self.meta.width];
+
+            let mut span = (usize::MAX, 0);
+
+            for (x, color) in area.columns().zip(row_colors) {
+                if x >= 0 && x < row.len() as _ {
+                    let x = x as usize;
+
+                    if row[x] != color {
+                        row[x] = color;
+
+                        span = (span.0.min(x), x + 1);
+                    }
+                }
+            }
+
+            if tracked && span.0 < span.1 {
+                changed_state.update_row(y, span.0, span.1);
+                changed = true;
+            }
+        }
+
+        changed
+    }
+
+    fn fill_solid(&mut self, changed_state: &mut Change, area: &Rectangle, color: u32) -> bool {
+        let (width, height) = self.meta.logical_size();
+
+        let area = area.intersection(&Rectangle::new(
+            Point::zero(),
+            Size::new(width as _, height as _),
+        ));
+
+        let Some(bottom_right) = area.bottom_right() else {
+            return false;
+        };
+
+        // A rectangle remains a rectangle in the framebuffer, whatever the rotation and mirroring
+        let corners = [area.top_left, bottom_right]
+            .map(|corner| self.meta.to_framebuffer(corner.x, corner.y).unwrap());
+
+        let (start, end) = (
+            corners[0].0.min(corners[1].0),
+            corners[0].0.max(corners[1].0) + 1,
+        );
+        let rows = corners[0].1.min(corners[1].1)..=corners[0].1.max(corners[1].1);
+
+        let mut changed = false;
+
+        let tracked = self.back_buffer.is_none();
+        let buffer = self.back_buffer.as_mut().unwrap_or(&mut self.buffer);
+
+        for y in rows {
+            let row = &mut buffer[y * self.meta.width + start..y * self.meta.width + end];
+
+            // Only the span between the first and the last pixel of another color actually changes
+            if let Some(first) = row.iter().position(|cell| *cell != color) {
+                let last = row.iter().rposition(|cell| *cell != color).unwrap();
+
+                row[first..=last].fill(color);
+
+                if tracked {
+                    changed_state.update_row(y, start + first, start + last + 1);
+                    changed = true;
+                }
+            }
+        }
+
+        changed
+    }
+
+    fn refresh(&mut self, changed_state: &mut Change, refresh: Refresh) {
+        let Some(back_buffer) = self.back_buffer.as_ref() else {
+            return;
+        };
+
+        let width = self.meta.width.max(1);
+
+        for (y, (row, back_row)) in self
+            .buffer
+            .chunks_exact_mut(width)
+            .zip(back_buffer.chunks_exact(width))
+            .enumerate()
+        {
+            let span = match refresh {
+                // A full refresh redraws every pixel
+                Refresh::Full => Some((0, width - 1)),
+                Refresh::Partial => row
+                    .iter()
+                    .zip(back_row)
+                    .position(|(cell, back)| cell != back)
+                    .map(|first| {
+                        let last = row
+                            .iter()
+                            .zip(back_row)
+                            .rposition(|(cell, back)| cell != back)
+                            .unwrap();
+
+                        (first, last)
+                    }),
+            };
+
+            if let Some((first, last)) = span {
+                row[first..=last].copy_from_slice(&back_row[first..=last]);
+
+                changed_state.update_row(y, first, last + 1);
+            }
+        }
+
+        changed_state.refresh = changed_state.refresh.max(Some(refresh));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::simulator::{Client, DisplayUpdate, Simulator, UpdateEvent};
+
+    use super::*;
+
+    const WIDTH: usize = 5;
+    const HEIGHT: usize = 3;
+
+    const TRANSFORMS: &[(Rotation, bool)] = &[
+        (Rotation::Deg0, false),
+        (Rotation::Deg0, true),
+        (Rotation::Deg90, false),
+        (Rotation::Deg180, true),
+        (Rotation::Deg270, false),
+    ];
+
+    /// Areas inside the display, clipped at each of its edges and outside of it
+    fn areas(display: &impl Dimensions) -> Vec<Rectangle> {
+        let size = display.bounding_box().size;
+
+        vec![
+            Rectangle::new(Point::new(1, 1), Size::new(2, 1)),
+            Rectangle::new(Point::new(-2, -1), Size::new(4, 3)),
+            Rectangle::new(Point::new(1, 0), Size::new(size.width + 3, size.height + 3)),
+            Rectangle::new(
+                Point::new(-1, -1),
+                Size::new(size.width + 2, size.height + 2),
+            ),
+            Rectangle::new(Point::new(size.width as _, 0), Size::new(2, 2)),
+            Rectangle::new(Point::new(0, -3), Size::new(2, 2)),
+            Rectangle::new(Point::new(1, 1), Size::zero()),
+        ]
+    }
+
+    /// Two displays with the same size, rotation and mirroring, and the same pattern already drawn on them
+    fn displays(
+        simulator: &Simulator,
+        rotation: Rotation,
+        mirrored: bool,
+    ) -> (Display<Rgb888>, Display<Rgb888>) {
+        let mut peripherals = simulator.take().unwrap();
+
+        let mut display = |name| {
+            let mut display = peripherals
+                .displays
+                .display_for::<Rgb888>(name, WIDTH, HEIGHT)
+                .with_rotation(rotation)
+                .with_mirroring(mirrored);
+
+            let pattern = display
+                .bounding_box()
+                .points()
+                .filter(|point| (point.x + point.y) % 2 == 0)
+                .map(|point| Pixel(point, Rgb888::RED));
+
+            display.draw_iter(pattern).unwrap();
+
+            display
+        };
+
+        (display("Fast"), display("Reference"))
+    }
+
+    fn buffer(simulator: &Simulator, id: usize) -> Vec<u32> {
+        simulator.displays().lock().unwrap()[id]
+            .display()
+            .buffer()
+            .to_vec()
+    }
+
+    #[test]
+    fn fill_solid() {
+        for (rotation, mirrored) in TRANSFORMS {
+            let simulator = Simulator::new();
+            let (mut fast, mut reference) = displays(&simulator, *rotation, *mirrored);
+
+            for area in areas(&fast) {
+                fast.fill_solid(&area, Rgb888::RED).unwrap();
+                reference
+                    .draw_iter(area.points().map(|point| Pixel(point, Rgb888::RED)))
+                    .unwrap();
+
+                assert_eq!(
+                    buffer(&simulator, 0),
+                    buffer(&simulator, 1),
+                    "{rotation:?}, mirrored: {mirrored}, {area:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn fill_contiguous() {
+        for (rotation, mirrored) in TRANSFORMS {
+            let simulator = Simulator::new();
+            let (mut fast, mut reference) = displays(&simulator, *rotation, *mirrored);
+
+            for area in areas(&fast) {
+                let colors = || (0..).map(|index| Rgb888::new(index, 0, 1));
+
+                fast.fill_contiguous(&area, colors()).unwrap();
+                reference
+                    .draw_iter(
+                        area.points()
+                            .zip(colors())
+                            .map(|(point, color)| Pixel(point, color)),
+                    )
+                    .unwrap();
+
+                assert_eq!(
+                    buffer(&simulator, 0),
+                    buffer(&simulator, 1),
+                    "{rotation:?}, mirrored: {mirrored}, {area:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn clear() {
+        for (rotation, mirrored) in TRANSFORMS {
+            let simulator = Simulator::new();
+            let (mut fast, mut reference) = displays(&simulator, *rotation, *mirrored);
+
+            fast.clear(Rgb888::BLUE).unwrap();
+            reference
+                .draw_iter(
+                    reference
+                        .bounding_box()
+                        .points()
+                        .map(|point| Pixel(point, Rgb888::BLUE)),
+                )
+                .unwrap();
+
+            assert_eq!(buffer(&simulator, 0), vec![0x0000ff; WIDTH * HEIGHT]);
+            assert_eq!(buffer(&simulator, 0), buffer(&simulator, 1));
+        }
+    }
+
+    #[test]
+    fn to_rgb888() {
+        let custom = BinaryPalette {
+            on: 0x123456,
+            off: 0x654321,
+        };
+
+        let cases = [
+            (Rgb565::new(31, 63, 31).to_rgb888(&()), 0xffffff),
+            (Rgb565::new(16, 0, 0).to_rgb888(&()), 0x840000),
+            (Rgb565::new(0, 32, 0).to_rgb888(&()), 0x008200),
+            (Bgr565::new(31, 0, 0).to_rgb888(&()), 0xff0000),
+            (Rgb555::new(31, 31, 31).to_rgb888(&()), 0xffffff),
+            (Rgb555::new(0, 0, 16).to_rgb888(&()), 0x000084),
+            (Rgb888::new(0x12, 0x34, 0x56).to_rgb888(&()), 0x123456),
+            (Gray2::new(1).to_rgb888(&()), 0x555555),
+            (Gray2::new(3).to_rgb888(&()), 0xffffff),
+            (Gray4::new(8).to_rgb888(&()), 0x888888),
+            (Gray8::new(0x40).to_rgb888(&()), 0x404040),
+            (
+                BinaryColor::On.to_rgb888(&BinaryPalette::default()),
+                0xffffff,
+            ),
+            (
+                BinaryColor::Off.to_rgb888(&BinaryPalette::default()),
+                0x000000,
+            ),
+            (
+                BinaryColor::On.to_rgb888(&BinaryPalette::OLED_BLUE),
+                0x40c0ff,
+            ),
+            (BinaryColor::On.to_rgb888(&custom), 0x123456),
+            (BinaryColor::Off.to_rgb888(&custom), 0x654321),
+        ];
+
+        for (index, (actual, expected)) in cases.into_iter().enumerate() {
+            assert_eq!(
+                actual, expected,
+                "case #{index}: {actual:06x} != {expected:06x}"
+            );
+        }
+    }
+
+    /// An e-paper display, with the client which consumes its changes
+    fn epaper(simulator: &Simulator) -> (EpaperDisplay<BinaryColor>, Client) {
+        let mut peripherals = simulator.take().unwrap();
+
+        let display = peripherals.displays.epaper(
+            "Epaper",
+            WIDTH,
+            HEIGHT,
+            EpaperLatency::default(),
+            |color: BinaryColor| color.to_rgb888(&BinaryPalette::WHITE),
+        );
+
+        let client = simulator.client();
+        while client.fetch().is_some() {}
+
+        (display, client)
+    }
+
+    fn change(simulator: &Simulator) -> Change {
+        simulator.displays().lock().unwrap()[0].change().clone()
+    }
+
+    fn draw(display: &mut EpaperDisplay<BinaryColor>, points: &[(i32, i32)]) {
+        display
+            .draw_iter(
+                points
+                    .iter()
+                    .map(|(x, y)| Pixel(Point::new(*x, *y), BinaryColor::On)),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn epaper_draw() {
+        let simulator = Simulator::new();
+        let (mut display, client) = epaper(&simulator);
+
+        draw(&mut display, &[(0, 0), (4, 2)]);
+        display.clear(BinaryColor::On).unwrap();
+
+        // Nothing is visible until the display is refreshed
+        assert_eq!(buffer(&simulator, 0), vec![0; WIDTH * HEIGHT]);
+        assert!(change(&simulator)
+            .screen_updates
+            .iter()
+            .all(|(start, end)| start >= end));
+        assert!(client.fetch().is_none());
+    }
+
+    #[test]
+    fn epaper_partial_refresh() {
+        let simulator = Simulator::new();
+        let (mut display, client) = epaper(&simulator);
+
+        draw(&mut display, &[(1, 0), (3, 0), (2, 2)]);
+        display.refresh_partial();
+
+        let change = change(&simulator);
+
+        assert_eq!(change.refresh, Some(Refresh::Partial));
+        assert_eq!(change.screen_updates, [(1, 4), (0, 0), (2, 3)]);
+
+        let mut expected = vec![0; WIDTH * HEIGHT];
+        for index in [1, 3, 2 * WIDTH + 2] {
+            expected[index] = 0xffffff;
+        }
+
+        assert_eq!(buffer(&simulator, 0), expected);
+
+        assert!(matches!(
+            client.fetch(),
+            Some(UpdateEvent::DisplayUpdate(DisplayUpdate::RefreshUpdate {
+                id: 0,
+                refresh: Refresh::Partial,
+            }))
+        ));
+
+        // Refreshing again without drawing changes nothing
+        while client.fetch().is_some() {}
+
+        display.refresh_partial();
+
+        assert!(matches!(
+            client.fetch(),
+            Some(UpdateEvent::DisplayUpdate(
+                DisplayUpdate::RefreshUpdate { .. }
+            ))
+        ));
+        assert!(client.fetch().is_none());
+    }
+
+    #[test]
+    fn epaper_full_refresh() {
+        let simulator = Simulator::new();
+        let (mut display, client) = epaper(&simulator);
+
+        draw(&mut display, &[(2, 1)]);
+        display.refresh_full();
+
+        let change = change(&simulator);
+
+        assert_eq!(change.refresh, Some(Refresh::Full));
+        assert_eq!(change.screen_updates, vec![(0, WIDTH); HEIGHT]);
+
+        assert_eq!(buffer(&simulator, 0)[WIDTH + 2], 0xffffff);
+
+        assert!(matches!(
+            client.fetch(),
+            Some(UpdateEvent::DisplayUpdate(DisplayUpdate::RefreshUpdate {
+                id: 0,
+                refresh: Refresh::Full,
+            }))
+        ));
+
+        // All rows follow, in full
+        let rows = core::iter::from_fn(|| client.fetch())
+            .map(|event| match event {
+                UpdateEvent::DisplayUpdate(DisplayUpdate::RectUpdate(update)) => {
+                    assert_eq!((update.x, update.width), (0, WIDTH as u16));
+                    update.height as usize
+                }
+                event => panic!("Unexpected event: {event:?}"),
+            })
+            .sum::<usize>();
+
+        assert_eq!(rows, HEIGHT);
+    }
 }
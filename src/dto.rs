@@ -1,12 +1,19 @@
+extern crate alloc;
+use alloc::vec::Vec;
+
 use serde::*;
 
 use super::{
-    display::DisplayMeta,
+    display::{DisplayMeta, Refresh, TouchPoint},
     gpio::{PinMeta, PinValue},
+    uart::UartMeta,
 };
 
 pub mod display;
 pub mod gpio;
+pub mod uart;
+#[cfg(feature = "wire")]
+pub mod wire;
 
 pub type RequestId = usize;
 
@@ -14,9 +21,22 @@ pub const SCREEN_MAX_STRIPE_LEN: usize = 320; // Stripes and overall WebEvents g
 pub const SCREEN_MAX_STRIPE_U8_LEN: usize = SCREEN_MAX_STRIPE_LEN * STRIPE_PIXEL_SIZE;
 pub const STRIPE_PIXEL_SIZE: usize = 3;
 
+/// The default maximum size of the pixel data of a single `RectUpdate`, in bytes
+pub const DEFAULT_RECT_PAYLOAD_BUDGET: usize = 16384;
+
+pub const UART_MAX_CHUNK_LEN: usize = 64;
+
+/// The maximum number of output bytes of a UART kept until they are fetched; older bytes are dropped
+pub const UART_MAX_PENDING_OUTPUT: usize = 65536;
+
+/// The maximum number of simultaneous contacts on the touch panel of a display
+pub const TOUCH_MAX_POINTS: usize = 5;
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum UpdateRequest {
     PinInputUpdate(PinInputUpdate),
+    UartInputUpdate(UartInputUpdate),
+    TouchUpdate(TouchUpdate),
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
@@ -33,6 +53,16 @@ impl PinInputUpdate {
         }
     }
 
+    /// Whether the update is of the kind of input of a pin with `pin_value`
+    pub fn applies_to(&self, pin_value: &PinValue) -> bool {
+        match self {
+            Self::Discrete(..) => {
+                matches!(pin_value, PinValue::Input(_) | PinValue::InputOutput { .. })
+            }
+            Self::Analog(..) => matches!(pin_value, PinValue::Adc(_)),
+        }
+    }
+
     pub fn update_value(&self, pin_value: &mut PinValue) {
         match self {
             Self::Discrete(_, value) => match pin_value {
@@ -47,11 +77,26 @@ impl PinInputUpdate {
     }
 }
 
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct UartInputUpdate {
+    pub id: u8,
+    pub data: heapless::Vec<u8, UART_MAX_CHUNK_LEN>,
+}
+
+/// The contacts on the touch panel of display `id`, which replace the previous ones
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TouchUpdate {
+    pub id: u8,
+    /// The contacts, in the logical orientation of the display; empty when the panel is released
+    pub points: heapless::Vec<TouchPoint, TOUCH_MAX_POINTS>,
+}
+
 #[allow(clippy::large_enum_variant)]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum UpdateEvent {
     PinUpdate(PinUpdate),
     DisplayUpdate(DisplayUpdate),
+    UartUpdate(UartUpdate),
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
@@ -70,14 +115,22 @@ pub enum DisplayUpdate {
         meta: Option<DisplayMeta>,
         dropped: bool,
     },
+    /// An e-paper display is refreshed; the changed pixels follow as `RectUpdate`s
+    RefreshUpdate {
+        id: u8,
+        refresh: Refresh,
+    },
     StripeUpdate(StripeUpdate),
+    RectUpdate(RectUpdate),
 }
 
 impl DisplayUpdate {
     pub fn id(&self) -> u8 {
         match self {
             Self::MetaUpdate { id, .. } => *id,
+            Self::RefreshUpdate { id, .. } => *id,
             Self::StripeUpdate(StripeUpdate { id, .. }) => *id,
+            Self::RectUpdate(RectUpdate { id, .. }) => *id,
         }
     }
 }
@@ -89,3 +142,40 @@ pub struct StripeUpdate {
     pub start: u16,
     pub data: heapless::Vec<u8, SCREEN_MAX_STRIPE_U8_LEN>,
 }
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RectUpdate {
+    pub id: u8,
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+    /// The pixels of the rectangle, row by row, with `STRIPE_PIXEL_SIZE` bytes (RGB) per pixel
+    pub data: Vec<u8>,
+}
+
+impl RectUpdate {
+    /// The pixels of the rectangle as `(x, y, rgb)`, in display coordinates
+    pub fn pixels(&self) -> impl Iterator<Item = (usize, usize, &[u8])> {
+        let width = self.width.max(1) as usize;
+
+        self.data
+            .chunks_exact(STRIPE_PIXEL_SIZE)
+            .enumerate()
+            .map(move |(index, pixel)| {
+                (
+                    self.x as usize + index % width,
+                    self.y as usize + index / width,
+                    pixel,
+                )
+            })
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct UartUpdate {
+    pub id: u8,
+    pub meta: Option<UartMeta>,
+    pub dropped: bool,
+    pub data: heapless::Vec<u8, UART_MAX_CHUNK_LEN>,
+}
@@ -5,17 +5,196 @@ use serde::*;
 
 pub type DisplayName = heapless::String<64>;
 
-#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize, Deserialize)]
+/// `width` and `height` are the size of the framebuffer, i.e. of the panel in its native orientation
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct DisplayMeta {
     pub name: DisplayName,
     pub width: usize,
     pub height: usize,
+    /// The refresh latencies, if this is an e-paper display
+    pub epaper: Option<EpaperLatency>,
+    /// How the panel is mounted, clockwise
+    pub rotation: Rotation,
+    /// Whether the panel is mirrored horizontally, after the rotation
+    pub mirrored: bool,
+    /// The initial zoom factor of the UI
+    pub zoom: u8,
+    /// Whether the UI renders the display as an LCD panel
+    pub lcd: Option<LcdEffect>,
+}
+
+impl DisplayMeta {
+    /// The size of the display in its logical orientation, i.e. as drawn by the firmware and shown by the UI
+    pub fn logical_size(&self) -> (usize, usize) {
+        if self.rotation.swaps_axes() {
+            (self.height, self.width)
+        } else {
+            (self.width, self.height)
+        }
+    }
+
+    /// Whether the logical orientation differs from the orientation of the framebuffer
+    pub fn is_transformed(&self) -> bool {
+        self.rotation != Rotation::Deg0 || self.mirrored
+    }
+
+    /// Maps a point in the logical orientation of the display to the framebuffer
+    ///
+    /// Returns `None` for points outside of the display. The framebuffer, and therefore the snapshots of
+    /// `TestBench::display_image`, are in the physical orientation of the panel, i.e. without rotation and mirroring.
+    pub fn to_framebuffer(&self, x: i32, y: i32) -> Option<(usize, usize)> {
+        let (width, height) = self.logical_size();
+
+        if x < 0 || y < 0 || x as usize >= width || y as usize >= height {
+            return None;
+        }
+
+        let (x, y) = (x as usize, y as usize);
+        let x = if self.mirrored { width - 1 - x } else { x };
+
+        Some(match self.rotation {
+            Rotation::Deg0 => (x, y),
+            Rotation::Deg90 => (y, self.height - 1 - x),
+            Rotation::Deg180 => (self.width - 1 - x, self.height - 1 - y),
+            Rotation::Deg270 => (self.width - 1 - y, x),
+        })
+    }
+}
+
+impl Default for DisplayMeta {
+    fn default() -> Self {
+        Self {
+            name: Default::default(),
+            width: 0,
+            height: 0,
+            epaper: None,
+            rotation: Rotation::Deg0,
+            mirrored: false,
+            zoom: 1,
+            lcd: None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Rotation {
+    #[default]
+    Deg0,
+    Deg90,
+    Deg180,
+    Deg270,
+}
+
+impl Rotation {
+    pub const fn degrees(&self) -> u16 {
+        match self {
+            Self::Deg0 => 0,
+            Self::Deg90 => 90,
+            Self::Deg180 => 180,
+            Self::Deg270 => 270,
+        }
+    }
+
+    /// Whether the width and the height of the display are exchanged
+    pub const fn swaps_axes(&self) -> bool {
+        matches!(self, Self::Deg90 | Self::Deg270)
+    }
+}
+
+/// Rendering of a display as an LCD panel, to judge the readability of small fonts as on real hardware
+///
+/// The effect is only visible with a zoom factor of at least 2.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct LcdEffect {
+    /// The fraction of every pixel taken by the dead space to its neighbours, in percents
+    pub dead_space: u8,
+    /// The color of the dead space, i.e. of the pixel grid, as `0x00RRGGBB`
+    pub grid_color: u32,
+    /// The backlight level, in percents
+    pub backlight: u8,
+    /// The gamma of the panel, in hundredths, e.g. `220` for the gamma of 2.2 of sRGB displays
+    pub gamma: u16,
+}
+
+impl LcdEffect {
+    /// The width of the dead space between the pixels of a display zoomed by `zoom`, in screen pixels
+    pub fn dead_space(&self, zoom: usize) -> usize {
+        if zoom < 2 || self.dead_space == 0 {
+            0
+        } else {
+            ((zoom * self.dead_space as usize + 50) / 100).clamp(1, zoom - 1)
+        }
+    }
+
+    /// The exponent which maps the (sRGB) levels of the framebuffer to the levels shown by the panel
+    pub fn gamma_exponent(&self) -> f32 {
+        self.gamma as f32 / 220.0
+    }
+
+    /// The factor by which the backlight dims all levels
+    pub fn brightness(&self) -> f32 {
+        self.backlight.min(100) as f32 / 100.0
+    }
+
+    /// Maps a color channel of the framebuffer to the level shown by the panel
+    pub fn level(&self, channel: u8) -> u8 {
+        let level = (channel as f32 / 255.0).powf(self.gamma_exponent()) * self.brightness();
+
+        (level * 255.0 + 0.5) as u8
+    }
+}
+
+impl Default for LcdEffect {
+    fn default() -> Self {
+        Self {
+            dead_space: 15,
+            grid_color: 0x101010,
+            backlight: 100,
+            gamma: 220,
+        }
+    }
+}
+
+/// How long the refreshes of an e-paper display take, in milliseconds
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct EpaperLatency {
+    pub full_ms: u32,
+    pub partial_ms: u32,
+}
+
+impl Default for EpaperLatency {
+    fn default() -> Self {
+        Self {
+            full_ms: 2000,
+            partial_ms: 300,
+        }
+    }
+}
+
+/// The kind of an e-paper refresh
+///
+/// A full refresh flashes the whole panel, while a partial one only updates the changed pixels.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum Refresh {
+    Partial,
+    Full,
+}
+
+/// A contact on the touch panel of a display, in the logical orientation of the display
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct TouchPoint {
+    /// Identifies the contact (i.e. the finger) while it moves
+    pub id: u8,
+    pub x: u16,
+    pub y: u16,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Change {
     pub created: bool,
     pub dropped: bool,
+    pub refresh: Option<Refresh>,
     pub screen_updates: Vec<(usize, usize)>,
 }
 
@@ -23,6 +202,7 @@ impl Change {
     pub fn update(&mut self, other: &Self) {
         self.created |= other.created;
         self.dropped |= other.dropped;
+        self.refresh = self.refresh.max(other.refresh);
 
         for (i, other_row) in other.screen_updates.iter().enumerate() {
             self.update_row(i, other_row.0, other_row.1);
@@ -41,6 +221,47 @@ impl Change {
         }
     }
 
+    /// Takes the next dirty rectangle out of the change
+    ///
+    /// Consecutive changed rows are merged into one rectangle as long as it has at most `max_pixels` pixels.
+    /// Rows wider than `max_pixels` are split. Returns the rectangle as `(x, y, width, height)`.
+    pub fn take_rect(&mut self, max_pixels: usize) -> Option<(usize, usize, usize, usize)> {
+        let max_pixels = max(max_pixels, 1);
+
+        let first = self
+            .screen_updates
+            .iter()
+            .position(|(start, end)| start < end)?;
+
+        let (start, end) = self.screen_updates[first];
+        let end = min(end, start + max_pixels);
+
+        let (mut rect_start, mut rect_end) = (start, end);
+        let mut height = 1;
+
+        while let Some(&(start, end)) = self.screen_updates.get(first + height) {
+            let (new_start, new_end) = (min(rect_start, start), max(rect_end, end));
+
+            if start >= end || (new_end - new_start) * (height + 1) > max_pixels {
+                break;
+            }
+
+            (rect_start, rect_end) = (new_start, new_end);
+            height += 1;
+        }
+
+        for row in &mut self.screen_updates[first..first + height] {
+            if row.1 > end && height == 1 {
+                // Only part of a row wider than `max_pixels` was taken
+                row.0 = end;
+            } else {
+                *row = (0, 0);
+            }
+        }
+
+        Some((rect_start, first, rect_end - rect_start, height))
+    }
+
     fn update_stripe(s_start: &mut usize, s_end: &mut usize, start: usize, end: usize) {
         if start < end {
             if *s_start < *s_end {
@@ -53,3 +274,236 @@ impl Change {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    fn change(rows: &[(usize, usize, usize)]) -> Change {
+        let mut change = Change {
+            created: false,
+            dropped: false,
+            refresh: None,
+            screen_updates: Vec::new(),
+        };
+
+        for (row, start, end) in rows {
+            change.update_row(*row, *start, *end);
+        }
+
+        change
+    }
+
+    fn area(rects: impl IntoIterator<Item = (usize, usize, usize, usize)>) -> Vec<(usize, usize)> {
+        rects
+            .into_iter()
+            .flat_map(|(x, y, width, height)| {
+                (y..y + height).flat_map(move |y| (x..x + width).map(move |x| (x, y)))
+            })
+            .collect()
+    }
+
+    /// Takes all rectangles out of `change`, checking that they are within `max_pixels` and do not overlap,
+    /// and that their union is exactly `expected`
+    fn take_all(
+        mut change: Change,
+        max_pixels: usize,
+        expected: &[(usize, usize, usize, usize)],
+    ) -> Vec<(usize, usize, usize, usize)> {
+        let mut rects = Vec::new();
+
+        while let Some(rect) = change.take_rect(max_pixels) {
+            assert!(rect.2 * rect.3 <= max_pixels, "{rect:?} is too large");
+            assert!(rect.2 > 0 && rect.3 > 0, "{rect:?} is empty");
+
+            rects.push(rect);
+        }
+
+        let pixels = area(rects.iter().copied());
+        let covered = pixels.iter().copied().collect::<HashSet<_>>();
+
+        assert_eq!(pixels.len(), covered.len(), "{rects:?} overlap");
+        assert_eq!(
+            covered,
+            area(expected.iter().copied()).into_iter().collect()
+        );
+
+        rects
+    }
+
+    #[test]
+    fn to_framebuffer() {
+        const WIDTH: usize = 3;
+        const HEIGHT: usize = 2;
+
+        // Every pixel of the framebuffer is labeled with its coordinates
+        let framebuffer = (0..HEIGHT)
+            .map(|y| (0..WIDTH).map(|x| (x, y)).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        for (turns, rotation) in [
+            Rotation::Deg0,
+            Rotation::Deg90,
+            Rotation::Deg180,
+            Rotation::Deg270,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            for mirrored in [false, true] {
+                let meta = DisplayMeta {
+                    width: WIDTH,
+                    height: HEIGHT,
+                    rotation,
+                    mirrored,
+                    ..Default::default()
+                };
+
+                // What the panel shows: the framebuffer turned clockwise, then mirrored horizontally
+                let mut shown = framebuffer.clone();
+
+                for _ in 0..turns {
+                    shown = (0..shown[0].len())
+                        .map(|x| shown.iter().rev().map(|row| row[x]).collect())
+                        .collect();
+                }
+
+                if mirrored {
+                    shown.iter_mut().for_each(|row| row.reverse());
+                }
+
+                assert_eq!(
+                    meta.logical_size(),
+                    (shown[0].len(), shown.len()),
+                    "{rotation:?}, mirrored: {mirrored}"
+                );
+
+                for (y, row) in shown.iter().enumerate() {
+                    for (x, pixel) in row.iter().enumerate() {
+                        assert_eq!(
+                            meta.to_framebuffer(x as _, y as _),
+                            Some(*pixel),
+                            "({x}, {y}) with {rotation:?}, mirrored: {mirrored}"
+                        );
+                    }
+                }
+
+                let (width, height) = meta.logical_size();
+
+                for (x, y) in [(-1, 0), (0, -1), (width as i32, 0), (0, height as i32)] {
+                    assert_eq!(meta.to_framebuffer(x, y), None);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn single_pixel() {
+        let rects = take_all(change(&[(7, 3, 4)]), 100, &[(3, 7, 1, 1)]);
+
+        assert_eq!(rects, [(3, 7, 1, 1)]);
+    }
+
+    #[test]
+    fn full_screen_above_budget() {
+        const WIDTH: usize = 100;
+        const HEIGHT: usize = 50;
+
+        let rows = (0..HEIGHT).map(|row| (row, 0, WIDTH)).collect::<Vec<_>>();
+
+        // Whole rows, merged up to the budget
+        let rects = take_all(change(&rows), 1024, &[(0, 0, WIDTH, HEIGHT)]);
+        assert_eq!(rects.len(), HEIGHT.div_ceil(10));
+
+        // Rows wider than the budget are split
+        let rects = take_all(change(&rows), 64, &[(0, 0, WIDTH, HEIGHT)]);
+        assert_eq!(rects.len(), HEIGHT * 2);
+    }
+
+    #[test]
+    fn disjoint_rects() {
+        let rows = (2..6)
+            .map(|row| (row, 10, 20))
+            .chain((8..10).map(|row| (row, 0, 5)))
+            .chain([(12, 30, 31)])
+            .collect::<Vec<_>>();
+
+        let rects = take_all(
+            change(&rows),
+            1000,
+            &[(10, 2, 10, 4), (0, 8, 5, 2), (30, 12, 1, 1)],
+        );
+
+        assert_eq!(rects, [(10, 2, 10, 4), (0, 8, 5, 2), (30, 12, 1, 1)]);
+    }
+
+    #[test]
+    fn lcd_level() {
+        let effect = LcdEffect::default();
+
+        assert_eq!(effect.level(0), 0);
+        assert_eq!(effect.level(128), 128);
+        assert_eq!(effect.level(255), 255);
+
+        let dimmed = LcdEffect {
+            backlight: 50,
+            ..effect
+        };
+
+        assert_eq!(dimmed.level(255), 128);
+        assert_eq!(dimmed.level(0), 0);
+
+        // Backlight levels above 100% are clamped
+        let bright = LcdEffect {
+            backlight: 200,
+            ..effect
+        };
+
+        assert_eq!(bright.level(255), 255);
+
+        let gamma = LcdEffect {
+            gamma: 110,
+            ..effect
+        };
+
+        assert_eq!(gamma.level(64), 128);
+        assert_eq!(gamma.level(255), 255);
+    }
+
+    #[test]
+    fn lcd_dead_space() {
+        let effect = LcdEffect::default();
+
+        // No dead space without zoom
+        assert_eq!(effect.dead_space(0), 0);
+        assert_eq!(effect.dead_space(1), 0);
+
+        assert_eq!(effect.dead_space(4), 1);
+        assert_eq!(effect.dead_space(10), 2);
+
+        // At least one screen pixel of dead space, and one of pixel
+        let thin = LcdEffect {
+            dead_space: 1,
+            ..effect
+        };
+
+        assert_eq!(thin.dead_space(8), 1);
+
+        let wide = LcdEffect {
+            dead_space: 90,
+            ..effect
+        };
+
+        assert_eq!(wide.dead_space(2), 1);
+        assert_eq!(wide.dead_space(10), 9);
+
+        let none = LcdEffect {
+            dead_space: 0,
+            ..effect
+        };
+
+        assert_eq!(none.dead_space(8), 0);
+    }
+}
@@ -17,17 +17,65 @@ pub enum PinType {
     Output,
     InputOutput(ButtonType),
     Analog(u16, u16),
+    Pwm(u16),
+    Encoder(EncoderPin),
+    Keypad(KeypadPin),
 }
 
 impl PinType {
     pub fn is_click(&self) -> bool {
         matches!(
             self,
-            Self::Input(ButtonType::Click) | Self::InputOutput(ButtonType::Click)
+            Self::Input(ButtonType::Click)
+                | Self::InputOutput(ButtonType::Click)
+                | Self::Encoder(EncoderPin::Button)
+                | Self::Keypad(KeypadPin::Key { .. })
         )
     }
 }
 
+/// The role of an input pin of a rotary encoder
+///
+/// The pins of an encoder have consecutive ids: A, B and then the optional push button.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum EncoderPin {
+    /// The A output, shown as the knob of the encoder
+    A {
+        steps_per_detent: u8,
+    },
+    B,
+    Button,
+}
+
+/// The role of a pin of a matrix keypad
+///
+/// The pins of a keypad have consecutive ids: the rows, the columns and then the keys, row by row.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum KeypadPin {
+    /// An output, driven by the firmware to scan the row
+    Row(u8),
+    /// An input, pulled up unless connected to a row driven low through the pressed keys
+    Column(u8),
+    /// A (click) input, connecting its row and its column while pressed
+    Key { row: u8, column: u8 },
+}
+
+/// Turns a rotary encoder with the levels `(a, b)` of its A and B outputs by one quadrature step
+///
+/// Returns the new levels, of which exactly one differs. Clockwise, A leads B, i.e. the levels cycle through
+/// `(low, low)`, `(high, low)`, `(high, high)` and `(low, high)`.
+pub fn quadrature_step(a: bool, b: bool, clockwise: bool) -> (bool, bool) {
+    const SEQUENCE: [(bool, bool); 4] =
+        [(false, false), (true, false), (true, true), (false, true)];
+
+    let index = SEQUENCE
+        .iter()
+        .position(|levels| *levels == (a, b))
+        .unwrap();
+
+    SEQUENCE[if clockwise { index + 1 } else { index + 3 } % SEQUENCE.len()]
+}
+
 impl Default for PinType {
     fn default() -> Self {
         Self::Output
@@ -39,6 +87,9 @@ pub struct PinMeta {
     pub name: PinName,
     pub category: PinCategory,
     pub pin_type: PinType,
+    /// The key which presses the (input) pin in the UI
+    #[serde(default)]
+    pub key: Option<char>,
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
@@ -47,4 +98,58 @@ pub enum PinValue {
     Output(bool),
     InputOutput { input: bool, output: bool },
     Adc(u16),
+    Pwm { duty: u16, enabled: bool },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The levels after each of 4 quadrature steps from `start`
+    fn cycle(start: (bool, bool), clockwise: bool) -> Vec<(bool, bool)> {
+        core::iter::successors(Some(start), |(a, b)| {
+            Some(quadrature_step(*a, *b, clockwise))
+        })
+        .skip(1)
+        .take(4)
+        .collect()
+    }
+
+    #[test]
+    fn quadrature_clockwise() {
+        assert_eq!(
+            cycle((false, false), true),
+            [(true, false), (true, true), (false, true), (false, false)]
+        );
+    }
+
+    #[test]
+    fn quadrature_counterclockwise() {
+        assert_eq!(
+            cycle((false, false), false),
+            [(false, true), (true, true), (true, false), (false, false)]
+        );
+    }
+
+    #[test]
+    fn quadrature_gray_code() {
+        for a in [false, true] {
+            for b in [false, true] {
+                for clockwise in [false, true] {
+                    let levels = cycle((a, b), clockwise);
+
+                    // Every step changes exactly one level, and a full cycle returns to the start
+                    for (from, to) in core::iter::once((a, b)).chain(levels.clone()).zip(&levels) {
+                        assert_eq!((from.0 != to.0) as u8 + (from.1 != to.1) as u8, 1);
+                    }
+
+                    assert_eq!(levels[3], (a, b));
+
+                    // Turning back retraces the steps
+                    let (back_a, back_b) = levels[0];
+                    assert_eq!(quadrature_step(back_a, back_b, !clockwise), (a, b));
+                }
+            }
+        }
+    }
 }
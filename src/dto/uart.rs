@@ -0,0 +1,38 @@
+use core::fmt::Debug;
+
+use serde::*;
+
+use super::UART_MAX_PENDING_OUTPUT;
+
+pub type UartName = heapless::String<64>;
+
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct UartMeta {
+    pub name: UartName,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Change {
+    pub created: bool,
+    pub dropped: bool,
+    pub output: Vec<u8>,
+}
+
+impl Change {
+    pub fn update(&mut self, other: &Self) {
+        self.created |= other.created;
+        self.dropped |= other.dropped;
+        self.append_output(&other.output);
+    }
+
+    /// Appends `data` to the output, dropping the oldest bytes beyond `UART_MAX_PENDING_OUTPUT`
+    pub fn append_output(&mut self, data: &[u8]) {
+        self.output.extend_from_slice(data);
+
+        let excess = self.output.len().saturating_sub(UART_MAX_PENDING_OUTPUT);
+
+        if excess > 0 {
+            self.output.drain(..excess);
+        }
+    }
+}
@@ -0,0 +1,380 @@
+use core::fmt;
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+use serde::*;
+
+use super::display::{DisplayMeta, Refresh};
+use super::*;
+
+/// The version of the wire format, sent as the first byte of every frame
+///
+/// The rest of the frame is the `postcard` encoding of either a single request or a batch of events, with
+/// stripe data run-length encoded whenever that is shorter than the raw pixels.
+pub const WIRE_VERSION: u8 = 9;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum WireError {
+    /// The frame is encoded with a different version of the format
+    Version(u8),
+    Malformed,
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Version(version) => write!(
+                f,
+                "Unsupported wire format version {version}, expected {WIRE_VERSION}"
+            ),
+            Self::Malformed => write!(f, "Malformed frame"),
+        }
+    }
+}
+
+impl std::error::Error for WireError {}
+
+pub fn encode_request(request: &UpdateRequest) -> Vec<u8> {
+    encode(request)
+}
+
+pub fn decode_request(frame: &[u8]) -> Result<UpdateRequest, WireError> {
+    decode(frame)
+}
+
+/// Encodes `events` as a single frame, e.g. all events of one frame of the simulation
+pub fn encode_events<'a>(events: impl IntoIterator<Item = &'a UpdateEvent>) -> Vec<u8> {
+    encode(&events.into_iter().map(WireEvent::from).collect::<Vec<_>>())
+}
+
+pub fn decode_events(frame: &[u8]) -> Result<Vec<UpdateEvent>, WireError> {
+    decode::<Vec<WireEvent>>(frame)?
+        .into_iter()
+        .map(TryInto::try_into)
+        .collect()
+}
+
+fn encode<T: Serialize>(data: &T) -> Vec<u8> {
+    let mut frame = postcard::to_allocvec(&(WIRE_VERSION, data)).unwrap();
+
+    // postcard encodes `u8` as a single byte, so the version is always the first byte of the frame
+    debug_assert_eq!(frame[0], WIRE_VERSION);
+
+    frame.shrink_to_fit();
+
+    frame
+}
+
+fn decode<T: de::DeserializeOwned>(frame: &[u8]) -> Result<T, WireError> {
+    match frame.first() {
+        Some(&WIRE_VERSION) => postcard::from_bytes::<(u8, T)>(frame)
+            .map(|(_, data)| data)
+            .map_err(|_| WireError::Malformed),
+        Some(version) => Err(WireError::Version(*version)),
+        None => Err(WireError::Malformed),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+enum WireEvent {
+    Pin(PinUpdate),
+    DisplayMeta {
+        id: u8,
+        meta: Option<DisplayMeta>,
+        dropped: bool,
+    },
+    Refresh {
+        id: u8,
+        refresh: Refresh,
+    },
+    Stripe {
+        id: u8,
+        row: u16,
+        start: u16,
+        data: PixelData,
+    },
+    Rect {
+        id: u8,
+        x: u16,
+        y: u16,
+        width: u16,
+        height: u16,
+        data: PixelData,
+    },
+    Uart(UartUpdate),
+}
+
+impl From<&UpdateEvent> for WireEvent {
+    fn from(event: &UpdateEvent) -> Self {
+        match event {
+            UpdateEvent::PinUpdate(update) => Self::Pin(update.clone()),
+            UpdateEvent::DisplayUpdate(DisplayUpdate::MetaUpdate { id, meta, dropped }) => {
+                Self::DisplayMeta {
+                    id: *id,
+                    meta: meta.clone(),
+                    dropped: *dropped,
+                }
+            }
+            UpdateEvent::DisplayUpdate(DisplayUpdate::RefreshUpdate { id, refresh }) => {
+                Self::Refresh {
+                    id: *id,
+                    refresh: *refresh,
+                }
+            }
+            UpdateEvent::DisplayUpdate(DisplayUpdate::StripeUpdate(update)) => Self::Stripe {
+                id: update.id,
+                row: update.row,
+                start: update.start,
+                data: PixelData::encode(&update.data),
+            },
+            UpdateEvent::DisplayUpdate(DisplayUpdate::RectUpdate(update)) => Self::Rect {
+                id: update.id,
+                x: update.x,
+                y: update.y,
+                width: update.width,
+                height: update.height,
+                data: PixelData::encode(&update.data),
+            },
+            UpdateEvent::UartUpdate(update) => Self::Uart(update.clone()),
+        }
+    }
+}
+
+impl TryFrom<WireEvent> for UpdateEvent {
+    type Error = WireError;
+
+    fn try_from(event: WireEvent) -> Result<Self, Self::Error> {
+        Ok(match event {
+            WireEvent::Pin(update) => Self::PinUpdate(update),
+            WireEvent::DisplayMeta { id, meta, dropped } => {
+                Self::DisplayUpdate(DisplayUpdate::MetaUpdate { id, meta, dropped })
+            }
+            WireEvent::Refresh { id, refresh } => {
+                Self::DisplayUpdate(DisplayUpdate::RefreshUpdate { id, refresh })
+            }
+            WireEvent::Stripe {
+                id,
+                row,
+                start,
+                data,
+            } => Self::DisplayUpdate(DisplayUpdate::StripeUpdate(StripeUpdate {
+                id,
+                row,
+                start,
+                data: heapless::Vec::from_slice(&data.decode(SCREEN_MAX_STRIPE_LEN)?)
+                    .map_err(|_| WireError::Malformed)?,
+            })),
+            WireEvent::Rect {
+                id,
+                x,
+                y,
+                width,
+                height,
+                data,
+            } => Self::DisplayUpdate(DisplayUpdate::RectUpdate(RectUpdate {
+                id,
+                x,
+                y,
+                width,
+                height,
+                data: data.decode(width as usize * height as usize)?,
+            })),
+            WireEvent::Uart(update) => Self::UartUpdate(update),
+        })
+    }
+}
+
+/// RGB888 pixels, either raw or as runs of identical pixels
+#[derive(Serialize, Deserialize)]
+enum PixelData {
+    Raw(Vec<u8>),
+    Rle(Vec<(u16, [u8; STRIPE_PIXEL_SIZE])>),
+}
+
+impl PixelData {
+    fn encode(data: &[u8]) -> Self {
+        let mut runs = Vec::<(u16, [u8; STRIPE_PIXEL_SIZE])>::new();
+
+        for pixel in data.chunks_exact(STRIPE_PIXEL_SIZE) {
+            let pixel = [pixel[0], pixel[1], pixel[2]];
+
+            match runs.last_mut() {
+                Some((len, last)) if *last == pixel && *len < u16::MAX => *len += 1,
+                _ => runs.push((1, pixel)),
+            }
+        }
+
+        // A run takes at least one byte more than a single raw pixel
+        if runs.len() * (STRIPE_PIXEL_SIZE + 1) < data.len() {
+            Self::Rle(runs)
+        } else {
+            Self::Raw(data.to_vec())
+        }
+    }
+
+    /// Decodes at most `max_pixels` pixels
+    fn decode(self, max_pixels: usize) -> Result<Vec<u8>, WireError> {
+        match self {
+            Self::Raw(data) if data.len() <= max_pixels * STRIPE_PIXEL_SIZE => Ok(data),
+            Self::Raw(_) => Err(WireError::Malformed),
+            Self::Rle(runs) => {
+                let len = runs.iter().map(|(len, _)| *len as usize).sum::<usize>();
+
+                if len > max_pixels {
+                    return Err(WireError::Malformed);
+                }
+
+                let mut data = Vec::with_capacity(len * STRIPE_PIXEL_SIZE);
+
+                for (len, pixel) in runs {
+                    for _ in 0..len {
+                        data.extend_from_slice(&pixel);
+                    }
+                }
+
+                Ok(data)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(width: u16, height: u16, data: Vec<u8>) -> RectUpdate {
+        RectUpdate {
+            id: 1,
+            x: 2,
+            y: 3,
+            width,
+            height,
+            data,
+        }
+    }
+
+    fn stripe(data: &[u8]) -> StripeUpdate {
+        StripeUpdate {
+            id: 1,
+            row: 2,
+            start: 3,
+            data: heapless::Vec::from_slice(data).unwrap(),
+        }
+    }
+
+    fn round_trip_rect(update: RectUpdate) -> RectUpdate {
+        let frame = encode_events(&[UpdateEvent::DisplayUpdate(DisplayUpdate::RectUpdate(
+            update,
+        ))]);
+
+        match decode_events(&frame).unwrap().as_slice() {
+            [UpdateEvent::DisplayUpdate(DisplayUpdate::RectUpdate(update))] => update.clone(),
+            events => panic!("Unexpected events: {events:?}"),
+        }
+    }
+
+    fn round_trip_stripe(update: StripeUpdate) -> StripeUpdate {
+        let frame = encode_events(&[UpdateEvent::DisplayUpdate(DisplayUpdate::StripeUpdate(
+            update,
+        ))]);
+
+        match decode_events(&frame).unwrap().as_slice() {
+            [UpdateEvent::DisplayUpdate(DisplayUpdate::StripeUpdate(update))] => update.clone(),
+            events => panic!("Unexpected events: {events:?}"),
+        }
+    }
+
+    #[test]
+    fn raw() {
+        let data = (0..4 * 4 * STRIPE_PIXEL_SIZE)
+            .map(|index| index as u8)
+            .collect::<Vec<_>>();
+
+        assert!(matches!(PixelData::encode(&data), PixelData::Raw(_)));
+
+        let update = rect(4, 4, data);
+        assert_eq!(round_trip_rect(update.clone()), update);
+
+        let update = stripe(&[1, 2, 3, 4, 5, 6]);
+        assert_eq!(round_trip_stripe(update.clone()), update);
+    }
+
+    #[test]
+    fn rle() {
+        let data = [[0xff, 0, 0]; 10]
+            .into_iter()
+            .chain([[0, 0xff, 0]; 6])
+            .flatten()
+            .collect::<Vec<_>>();
+
+        assert!(matches!(PixelData::encode(&data), PixelData::Rle(runs) if runs.len() == 2));
+
+        let update = rect(4, 4, data.clone());
+        assert_eq!(round_trip_rect(update.clone()), update);
+
+        let update = stripe(&data);
+        assert_eq!(round_trip_stripe(update.clone()), update);
+    }
+
+    #[test]
+    fn long_runs() {
+        let pixels = u16::MAX as usize * 2 + 10;
+
+        let data = [0x12, 0x34, 0x56].repeat(pixels);
+
+        assert!(matches!(PixelData::encode(&data), PixelData::Rle(runs) if runs.len() == 3));
+
+        let update = rect((pixels / 5) as u16, 5, data);
+        assert_eq!(round_trip_rect(update.clone()), update);
+    }
+
+    #[test]
+    fn empty() {
+        let update = stripe(&[]);
+        assert_eq!(round_trip_stripe(update.clone()), update);
+
+        let update = rect(0, 0, Vec::new());
+        assert_eq!(round_trip_rect(update.clone()), update);
+    }
+
+    #[test]
+    fn too_many_pixels() {
+        let frame = encode(&vec![WireEvent::Rect {
+            id: 0,
+            x: 0,
+            y: 0,
+            width: 2,
+            height: 2,
+            data: PixelData::Rle(vec![(5, [0, 0, 0])]),
+        }]);
+
+        assert_eq!(decode_events(&frame).unwrap_err(), WireError::Malformed);
+    }
+
+    #[test]
+    fn version_mismatch() {
+        let mut frame = encode_request(&UpdateRequest::PinInputUpdate(PinInputUpdate::Discrete(
+            1, true,
+        )));
+
+        assert!(matches!(
+            decode_request(&frame),
+            Ok(UpdateRequest::PinInputUpdate(PinInputUpdate::Discrete(
+                1, true
+            )))
+        ));
+
+        frame[0] = WIRE_VERSION.wrapping_add(1);
+
+        assert_eq!(
+            decode_request(&frame).unwrap_err(),
+            WireError::Version(WIRE_VERSION.wrapping_add(1))
+        );
+        assert_eq!(
+            decode_events(&frame).unwrap_err(),
+            WireError::Version(frame[0])
+        );
+        assert_eq!(decode_events(&[]).unwrap_err(), WireError::Malformed);
+    }
+}
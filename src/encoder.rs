@@ -0,0 +1,82 @@
+use crate::gpio::{Input, Pin, PinCategory, PinName, PinType, PinValue, Pins};
+
+pub use crate::dto::gpio::{quadrature_step, EncoderPin};
+
+/// The input pins of a simulated rotary encoder
+///
+/// Created with `Pins::rotary_encoder` or `Pins::rotary_encoder_with_button`. The UI shows the encoder as a knob,
+/// which is turned by detents with the mouse wheel or the buttons next to it. Every detent is a sequence of
+/// quadrature steps on `a` and `b` (A leads B clockwise), applied one after the other, as with a real encoder.
+pub struct RotaryEncoder {
+    pub a: Pin<Input>,
+    pub b: Pin<Input>,
+    pub button: Option<Pin<Input>>,
+}
+
+impl RotaryEncoder {
+    /// The quadrature steps of every detent of most encoders, i.e. a full quadrature cycle
+    pub const DEFAULT_STEPS_PER_DETENT: u8 = 4;
+
+    /// Sets the number of quadrature steps (i.e. level changes of A or B) of every detent
+    ///
+    /// Usually 4 (a full quadrature cycle), but some encoders have 2 or even 1.
+    pub fn with_steps_per_detent(self, steps_per_detent: u8) -> Self {
+        self.a.update_meta(|meta| {
+            meta.pin_type = PinType::Encoder(EncoderPin::A {
+                steps_per_detent: steps_per_detent.max(1),
+            })
+        });
+
+        self
+    }
+}
+
+impl Pins {
+    /// Creates the A and B input pins of a rotary encoder, named `<name> A` and `<name> B`
+    pub fn rotary_encoder(
+        &mut self,
+        name: impl TryInto<PinName>,
+        category: impl TryInto<PinCategory>,
+    ) -> RotaryEncoder {
+        self.new_rotary_encoder(name, category, false)
+    }
+
+    /// Same as `rotary_encoder`, with an additional push button pin named `<name> Button`
+    pub fn rotary_encoder_with_button(
+        &mut self,
+        name: impl TryInto<PinName>,
+        category: impl TryInto<PinCategory>,
+    ) -> RotaryEncoder {
+        self.new_rotary_encoder(name, category, true)
+    }
+
+    fn new_rotary_encoder(
+        &mut self,
+        name: impl TryInto<PinName>,
+        category: impl TryInto<PinCategory>,
+        button: bool,
+    ) -> RotaryEncoder {
+        let name: PinName = name.try_into().map_err(|_| ()).unwrap();
+        let category: PinCategory = category.try_into().map_err(|_| ()).unwrap();
+
+        let mut new_pin = |suffix: &str, role: EncoderPin| {
+            self.new_pin(
+                format!("{name} {suffix}").as_str(),
+                category.clone(),
+                PinType::Encoder(role),
+                PinValue::Input(false),
+            )
+        };
+
+        RotaryEncoder {
+            a: new_pin(
+                "A",
+                EncoderPin::A {
+                    steps_per_detent: RotaryEncoder::DEFAULT_STEPS_PER_DETENT,
+                },
+            ),
+            b: new_pin("B", EncoderPin::B),
+            button: button.then(|| new_pin("Button", EncoderPin::Button)),
+        }
+    }
+}
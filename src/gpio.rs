@@ -11,6 +11,8 @@ use embedded_hal::digital::{ErrorType, InputPin, OutputPin};
 use embedded_hal02::digital::v2::{InputPin as InputPin02, OutputPin as OutputPin02};
 
 use crate::adc::AdcTrait;
+use crate::keypad::KeypadMatrix;
+use crate::pwm::PwmTrait;
 
 pub trait InputMode {}
 pub trait OutputMode {}
@@ -27,17 +29,19 @@ impl OutputMode for InputOutput {}
 
 pub use crate::dto::gpio::*;
 
-pub(crate) static PINS: Mutex<Vec<PinState>> = Mutex::new(Vec::new());
+pub type SharedPins = Arc<Mutex<Vec<PinState>>>;
 
 pub struct Pins {
     id_gen: u8,
+    pins: SharedPins,
     changed: PinsChangedCallback,
 }
 
 impl Pins {
-    pub(crate) fn new(changed: impl Fn() + 'static) -> Self {
+    pub(crate) fn new(pins: SharedPins, changed: impl Fn() + 'static) -> Self {
         Self {
             id_gen: 0,
+            pins,
             changed: Arc::new(changed),
         }
     }
@@ -140,7 +144,32 @@ impl Pins {
         )
     }
 
-    fn new_pin<MODE>(
+    pub fn pwm<PWM>(
+        &mut self,
+        name: impl TryInto<PinName>,
+        category: impl TryInto<PinCategory>,
+        max_duty: u16,
+        duty: u16,
+    ) -> Pin<PWM>
+    where
+        PWM: PwmTrait,
+    {
+        self.new_pin(
+            name,
+            category,
+            PinType::Pwm(max_duty),
+            PinValue::Pwm {
+                duty,
+                enabled: true,
+            },
+        )
+    }
+
+    pub(crate) fn states(&self) -> &SharedPins {
+        &self.pins
+    }
+
+    pub(crate) fn new_pin<MODE>(
         &mut self,
         name: impl TryInto<PinName>,
         category: impl TryInto<PinCategory>,
@@ -158,11 +187,11 @@ impl Pins {
         );
 
         {
-            let mut states = PINS.lock().unwrap();
+            let mut states = self.pins.lock().unwrap();
             states.push(state);
         }
 
-        Pin::new(id, self.changed.clone())
+        Pin::new(id, self.pins.clone(), self.changed.clone())
     }
 }
 
@@ -170,25 +199,77 @@ pub type PinsChangedCallback = Arc<dyn Fn()>;
 
 pub struct Pin<MODE> {
     id: u8,
+    pins: SharedPins,
     changed: PinsChangedCallback,
     _mode: PhantomData<MODE>,
 }
 
 impl<MODE> Pin<MODE> {
-    fn new(id: u8, changed: PinsChangedCallback) -> Self {
+    fn new(id: u8, pins: SharedPins, changed: PinsChangedCallback) -> Self {
         Self {
             id,
+            pins,
             changed,
             _mode: PhantomData,
         }
     }
+
+    pub(crate) fn id(&self) -> u8 {
+        self.id
+    }
+
+    pub(crate) fn update_meta(&self, f: impl FnOnce(&mut PinMeta)) {
+        {
+            let mut guard = self.pins.lock().unwrap();
+            let state = &mut guard[self.id as usize];
+
+            f(&mut state.shared.meta);
+
+            // Resend the meta
+            state.change = Change::Created;
+        }
+
+        (self.changed)();
+    }
+
+    /// Returns a probe for reading the level of this pin, e.g. from a device model
+    pub fn probe(&self) -> PinProbe {
+        PinProbe {
+            id: self.id,
+            pins: self.pins.clone(),
+        }
+    }
+}
+
+/// A read-only view of the level of a pin, for device models which sample the GPIOs driven by the firmware,
+/// like the data/command line of a display controller
+#[derive(Clone)]
+pub struct PinProbe {
+    id: u8,
+    pins: SharedPins,
+}
+
+impl PinProbe {
+    /// Returns the output level of output pins and the input level of input pins
+    pub fn is_high(&self) -> bool {
+        let guard = self.pins.lock().unwrap();
+
+        match guard[self.id as usize].shared.value {
+            PinValue::Output(level)
+            | PinValue::InputOutput { output: level, .. }
+            | PinValue::Input(level) => level,
+            PinValue::Adc(_) | PinValue::Pwm { .. } => false,
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 enum WaitType {
     LowLevel,
     HighLevel,
-    Edge,
+    RisingEdge,
+    FallingEdge,
+    AnyEdge,
 }
 
 impl<MODE> Pin<MODE>
@@ -196,51 +277,69 @@ where
     MODE: InputMode,
 {
     fn is_high(&self) -> bool {
-        let guard = PINS.lock().unwrap();
+        let guard = self.pins.lock().unwrap();
 
-        match guard[self.id as usize].shared.value {
-            PinValue::Input(value) => value,
-            PinValue::InputOutput { input: value, .. } => value,
-            _ => unreachable!(),
-        }
+        guard[self.id as usize].shared.input_level()
     }
 
     async fn wait(&self, wait_type: WaitType) {
-        let notif = {
-            let guard = PINS.lock().unwrap();
+        // Snapshot the edge counters when the wait starts, so that edges which happen
+        // while the future is not being polled are still detected
+        let (rising_edges, falling_edges) = {
+            let guard = self.pins.lock().unwrap();
+            let shared = &guard[self.id as usize].shared;
 
-            let notif = guard[self.id as usize].shared.notification();
+            (shared.rising_edges, shared.falling_edges)
+        };
 
-            notif.reset();
+        loop {
+            let (notif, done) = {
+                let guard = self.pins.lock().unwrap();
+                let shared = &guard[self.id as usize].shared;
 
-            notif
-        };
+                let notif = shared.notification();
 
-        match wait_type {
-            WaitType::LowLevel => {
-                if !self.is_high() {
-                    return;
-                }
-            }
-            WaitType::HighLevel => {
-                if self.is_high() {
-                    return;
-                }
+                notif.reset();
+
+                let rising = shared.rising_edges != rising_edges;
+                let falling = shared.falling_edges != falling_edges;
+
+                let done = match wait_type {
+                    WaitType::LowLevel => !shared.input_level(),
+                    WaitType::HighLevel => shared.input_level(),
+                    WaitType::RisingEdge => rising,
+                    WaitType::FallingEdge => falling,
+                    WaitType::AnyEdge => rising || falling,
+                };
+
+                (notif, done)
+            };
+
+            if done {
+                break;
             }
-            _ => (),
+
+            notif.wait().await;
         }
+    }
 
-        notif.wait().await;
+    /// Binds a key to the pin, so that the UI presses it while the key is held down
+    ///
+    /// Click pins are pressed on key down and released on key up, as with the mouse, while toggle pins are
+    /// toggled on key down. Letters are matched regardless of their case.
+    pub fn with_key(self, key: char) -> Self {
+        self.update_meta(|meta| meta.key = Some(key));
+        self
     }
 
     pub fn subscribe(&mut self, callback: impl Fn() + Send + 'static) {
-        let mut guard = PINS.lock().unwrap();
+        let mut guard = self.pins.lock().unwrap();
 
         guard[self.id as usize].shared.callback = Some(Box::new(callback));
     }
 
     pub fn unsubscribe(&mut self) {
-        let mut guard = PINS.lock().unwrap();
+        let mut guard = self.pins.lock().unwrap();
 
         guard[self.id as usize].shared.callback = None;
     }
@@ -252,10 +351,10 @@ where
 {
     fn set_output(&mut self, high: bool) {
         let changed = {
-            let mut guard = PINS.lock().unwrap();
+            let mut guard = self.pins.lock().unwrap();
             let pin = &mut guard[self.id as usize];
 
-            match &mut pin.shared.value {
+            let changed = match &mut pin.shared.value {
                 PinValue::Output(output) | PinValue::InputOutput { output, .. } => {
                     if *output != high {
                         *output = high;
@@ -267,7 +366,13 @@ where
                     }
                 }
                 _ => unreachable!(),
+            };
+
+            if changed {
+                propagate(&mut guard, self.id as _);
             }
+
+            changed
         };
 
         if changed {
@@ -281,7 +386,7 @@ where
     MODE: AdcTrait,
 {
     pub(crate) fn get_input(&self) -> u16 {
-        let guard = PINS.lock().unwrap();
+        let guard = self.pins.lock().unwrap();
 
         match guard[self.id as usize].shared.value {
             PinValue::Adc(value) => value,
@@ -290,10 +395,75 @@ where
     }
 }
 
+impl<MODE> Pin<MODE>
+where
+    MODE: PwmTrait,
+{
+    pub(crate) fn duty(&self) -> u16 {
+        let guard = self.pins.lock().unwrap();
+
+        match guard[self.id as usize].shared.value {
+            PinValue::Pwm { duty, .. } => duty,
+            _ => unreachable!(),
+        }
+    }
+
+    pub(crate) fn max_duty(&self) -> u16 {
+        let guard = self.pins.lock().unwrap();
+
+        match guard[self.id as usize].shared.meta.pin_type {
+            PinType::Pwm(max_duty) => max_duty,
+            _ => unreachable!(),
+        }
+    }
+
+    pub(crate) fn set_duty(&mut self, duty: u16) {
+        self.update_pwm(Some(duty), None);
+    }
+
+    pub(crate) fn set_enabled(&mut self, enabled: bool) {
+        self.update_pwm(None, Some(enabled));
+    }
+
+    fn update_pwm(&mut self, new_duty: Option<u16>, new_enabled: Option<bool>) {
+        let changed = {
+            let mut guard = self.pins.lock().unwrap();
+            let pin = &mut guard[self.id as usize];
+
+            let max_duty = match pin.shared.meta.pin_type {
+                PinType::Pwm(max_duty) => max_duty,
+                _ => unreachable!(),
+            };
+
+            match &mut pin.shared.value {
+                PinValue::Pwm { duty, enabled } => {
+                    let new_duty = new_duty.map(|duty| duty.min(max_duty)).unwrap_or(*duty);
+                    let new_enabled = new_enabled.unwrap_or(*enabled);
+
+                    if *duty != new_duty || *enabled != new_enabled {
+                        *duty = new_duty;
+                        *enabled = new_enabled;
+                        pin.change.update(&Change::Updated);
+
+                        true
+                    } else {
+                        false
+                    }
+                }
+                _ => unreachable!(),
+            }
+        };
+
+        if changed {
+            (self.changed)()
+        }
+    }
+}
+
 impl<MODE> Drop for Pin<MODE> {
     fn drop(&mut self) {
         {
-            let mut guard = PINS.lock().unwrap();
+            let mut guard = self.pins.lock().unwrap();
 
             guard[self.id as usize].shared.dropped = true;
             guard[self.id as usize].change.update(&Change::Updated);
@@ -337,19 +507,19 @@ where
     }
 
     async fn wait_for_rising_edge(&mut self) -> Result<(), Self::Error> {
-        self.wait(WaitType::Edge).await; // TODO
+        self.wait(WaitType::RisingEdge).await;
 
         Ok(())
     }
 
     async fn wait_for_falling_edge(&mut self) -> Result<(), Self::Error> {
-        self.wait(WaitType::Edge).await; // TODO
+        self.wait(WaitType::FallingEdge).await;
 
         Ok(())
     }
 
     async fn wait_for_any_edge(&mut self) -> Result<(), Self::Error> {
-        self.wait(WaitType::Edge).await;
+        self.wait(WaitType::AnyEdge).await;
 
         Ok(())
     }
@@ -402,9 +572,18 @@ where
     }
 }
 
+/// Updates the pins which depend on the level of pin `id`, after it changed
+pub(crate) fn propagate(pins: &mut [PinState], id: usize) {
+    if let Some(keypad) = pins[id].keypad.clone() {
+        keypad.update(pins);
+    }
+}
+
 pub struct PinState {
     shared: SharedPin,
     change: Change,
+    /// The keypad whose columns depend on the level of this pin
+    keypad: Option<Arc<KeypadMatrix>>,
 }
 
 impl PinState {
@@ -412,6 +591,19 @@ impl PinState {
         Self {
             shared: SharedPin::new(name, category, pin_type, value),
             change: Change::Created,
+            keypad: None,
+        }
+    }
+
+    pub(crate) fn set_keypad(&mut self, keypad: Arc<KeypadMatrix>) {
+        self.keypad = Some(keypad);
+    }
+
+    /// Sets the level of an input pin driven by the simulator itself, like a column of a keypad
+    pub(crate) fn drive_input(&mut self, high: bool) {
+        if self.shared.input_level() != high {
+            self.shared.set_discrete_input(high);
+            self.change.update(&Change::Updated);
         }
     }
 
@@ -436,6 +628,8 @@ pub struct SharedPin {
     meta: PinMeta,
     value: PinValue,
     dropped: bool,
+    rising_edges: usize,
+    falling_edges: usize,
     callback: Option<Box<dyn Fn() + Send>>,
     notification: Arc<Notification>,
 }
@@ -447,9 +641,12 @@ impl SharedPin {
                 name,
                 category,
                 pin_type,
+                key: None,
             },
             value,
             dropped: false,
+            rising_edges: 0,
+            falling_edges: 0,
             callback: None,
             notification: Arc::new(Notification::new()),
         }
@@ -471,6 +668,14 @@ impl SharedPin {
         self.notification.clone()
     }
 
+    fn input_level(&self) -> bool {
+        match self.value {
+            PinValue::Input(value) => value,
+            PinValue::InputOutput { input: value, .. } => value,
+            _ => unreachable!(),
+        }
+    }
+
     pub fn set_discrete_input(&mut self, high: bool) {
         if !self.dropped {
             let changed = match &mut self.value {
@@ -490,6 +695,12 @@ impl SharedPin {
             };
 
             if changed {
+                if high {
+                    self.rising_edges = self.rising_edges.wrapping_add(1);
+                } else {
+                    self.falling_edges = self.falling_edges.wrapping_add(1);
+                }
+
                 if let Some(callback) = self.callback.as_ref() {
                     (callback)();
                 }
@@ -542,3 +753,122 @@ impl Change {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use core::future::Future;
+    use core::pin::pin;
+    use core::task::{Context, Poll};
+
+    use embedded_hal_async::digital::Wait;
+
+    use super::*;
+
+    fn input(value: bool) -> (SharedPins, Pin<Input>) {
+        let states = SharedPins::default();
+
+        let pin = Pins::new(states.clone(), || ()).input("Input", "Test", value);
+
+        (states, pin)
+    }
+
+    fn set(states: &SharedPins, high: bool) {
+        states.lock().unwrap()[0].pin_mut().set_discrete_input(high);
+    }
+
+    fn poll<F: Future>(future: core::pin::Pin<&mut F>) -> Poll<F::Output> {
+        future.poll(&mut Context::from_waker(futures::task::noop_waker_ref()))
+    }
+
+    /// Polls `future` once before and once after `inputs` are applied to the pin, returning whether it completed
+    /// only after them
+    fn completes_after<F: Future>(states: &SharedPins, future: F, inputs: &[bool]) -> bool {
+        let mut future = pin!(future);
+
+        if poll(future.as_mut()).is_ready() {
+            return false;
+        }
+
+        for high in inputs {
+            set(states, *high);
+        }
+
+        poll(future.as_mut()).is_ready()
+    }
+
+    #[test]
+    fn wait_for_level() {
+        let (states, mut pin) = input(false);
+
+        assert!(poll(pin!(pin.wait_for_low())).is_ready());
+        assert!(completes_after(&states, pin.wait_for_high(), &[true]));
+        assert!(poll(pin!(pin.wait_for_high())).is_ready());
+        assert!(completes_after(&states, pin.wait_for_low(), &[false]));
+    }
+
+    #[test]
+    fn wait_for_rising_edge() {
+        let (states, mut pin) = input(true);
+
+        // The level being high already is not an edge
+        assert!(!completes_after(&states, pin.wait_for_rising_edge(), &[]));
+        assert!(!completes_after(
+            &states,
+            pin.wait_for_rising_edge(),
+            &[false]
+        ));
+        assert!(completes_after(
+            &states,
+            pin.wait_for_rising_edge(),
+            &[true]
+        ));
+    }
+
+    #[test]
+    fn wait_for_falling_edge() {
+        let (states, mut pin) = input(false);
+
+        assert!(!completes_after(&states, pin.wait_for_falling_edge(), &[]));
+        assert!(!completes_after(
+            &states,
+            pin.wait_for_falling_edge(),
+            &[true]
+        ));
+        assert!(completes_after(
+            &states,
+            pin.wait_for_falling_edge(),
+            &[false]
+        ));
+    }
+
+    #[test]
+    fn wait_for_any_edge() {
+        let (states, mut pin) = input(false);
+
+        assert!(!completes_after(&states, pin.wait_for_any_edge(), &[]));
+        assert!(completes_after(&states, pin.wait_for_any_edge(), &[true]));
+        assert!(completes_after(&states, pin.wait_for_any_edge(), &[false]));
+    }
+
+    #[test]
+    fn edges_while_not_polled() {
+        let (states, mut pin) = input(false);
+
+        // Short pulses, which are over by the time the futures are polled again
+        assert!(completes_after(
+            &states,
+            pin.wait_for_rising_edge(),
+            &[true, false]
+        ));
+        assert!(completes_after(
+            &states,
+            pin.wait_for_falling_edge(),
+            &[true, false, true]
+        ));
+        assert!(completes_after(
+            &states,
+            pin.wait_for_any_edge(),
+            &[false, true]
+        ));
+    }
+}
@@ -0,0 +1,317 @@
+use embedded_hal::i2c::{ErrorType, I2c as I2c1, Operation, SevenBitAddress};
+use embedded_hal02::blocking::i2c::{
+    Operation as Operation02, Read as Read02, Transactional as Transactional02, Write as Write02,
+    WriteRead as WriteRead02,
+};
+
+pub use embedded_hal::i2c::{ErrorKind, NoAcknowledgeSource};
+
+/// A simulated device model attached to an `I2c` bus.
+///
+/// The bus calls `start` whenever the device is addressed (on START or repeated START),
+/// then `write` or `read` with the data of the frame and finally `stop` when the transaction is over.
+/// Consecutive operations of the same kind belong to the same frame and result in consecutive
+/// `write` or `read` calls without an intermediate `start`.
+pub trait I2cDevice {
+    fn start(&mut self) {}
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), ErrorKind>;
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<(), ErrorKind>;
+
+    fn stop(&mut self) {}
+}
+
+pub struct I2c {
+    devices: Vec<(SevenBitAddress, Box<dyn I2cDevice>)>,
+}
+
+impl I2c {
+    pub(crate) const fn new() -> Self {
+        Self {
+            devices: Vec::new(),
+        }
+    }
+
+    /// Attaches a device model to the bus, replacing any device already attached at `address`.
+    ///
+    /// # Panics
+    ///
+    /// If `address` does not fit in seven bits.
+    pub fn attach(&mut self, address: SevenBitAddress, device: impl I2cDevice + 'static) {
+        assert!(
+            address <= 0x7f,
+            "I2C address {address:#04x} is not a 7-bit address"
+        );
+
+        self.detach(address);
+
+        self.devices.push((address, Box::new(device)));
+    }
+
+    pub fn detach(&mut self, address: SevenBitAddress) -> Option<Box<dyn I2cDevice>> {
+        let index = self.devices.iter().position(|(a, _)| *a == address)?;
+
+        Some(self.devices.remove(index).1)
+    }
+
+    fn transact(
+        &mut self,
+        address: SevenBitAddress,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), ErrorKind> {
+        let device = self
+            .devices
+            .iter_mut()
+            .find_map(|(a, device)| (*a == address).then_some(device))
+            .ok_or(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address))?;
+
+        let mut reading = None;
+
+        let result = operations.iter_mut().try_for_each(|operation| {
+            let read = matches!(operation, Operation::Read(_));
+
+            if reading != Some(read) {
+                device.start();
+                reading = Some(read);
+            }
+
+            match operation {
+                Operation::Read(buffer) => device.read(buffer),
+                Operation::Write(bytes) => device.write(bytes),
+            }
+        });
+
+        device.stop();
+
+        result
+    }
+}
+
+impl ErrorType for I2c {
+    type Error = ErrorKind;
+}
+
+impl I2c1 for I2c {
+    fn transaction(
+        &mut self,
+        address: SevenBitAddress,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        self.transact(address, operations)
+    }
+}
+
+impl embedded_hal_async::i2c::I2c for I2c {
+    async fn transaction(
+        &mut self,
+        address: SevenBitAddress,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        self.transact(address, operations)
+    }
+}
+
+impl Read02 for I2c {
+    type Error = ErrorKind;
+
+    fn read(&mut self, address: SevenBitAddress, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.transact(address, &mut [Operation::Read(buffer)])
+    }
+}
+
+impl Write02 for I2c {
+    type Error = ErrorKind;
+
+    fn write(&mut self, address: SevenBitAddress, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.transact(address, &mut [Operation::Write(bytes)])
+    }
+}
+
+impl WriteRead02 for I2c {
+    type Error = ErrorKind;
+
+    fn write_read(
+        &mut self,
+        address: SevenBitAddress,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        self.transact(
+            address,
+            &mut [Operation::Write(bytes), Operation::Read(buffer)],
+        )
+    }
+}
+
+impl Transactional02 for I2c {
+    type Error = ErrorKind;
+
+    fn exec(
+        &mut self,
+        address: SevenBitAddress,
+        operations: &mut [Operation02<'_>],
+    ) -> Result<(), Self::Error> {
+        let mut operations = operations
+            .iter_mut()
+            .map(|operation| match operation {
+                Operation02::Read(buffer) => Operation::Read(buffer),
+                Operation02::Write(bytes) => Operation::Write(bytes),
+            })
+            .collect::<Vec<_>>();
+
+        self.transact(address, &mut operations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    enum Event {
+        Start,
+        Write(Vec<u8>),
+        Read(usize),
+        Stop,
+    }
+
+    /// Records the calls of the bus and answers reads with its address
+    struct Recorder {
+        address: u8,
+        events: Rc<RefCell<Vec<Event>>>,
+    }
+
+    impl I2cDevice for Recorder {
+        fn start(&mut self) {
+            self.events.borrow_mut().push(Event::Start);
+        }
+
+        fn write(&mut self, bytes: &[u8]) -> Result<(), ErrorKind> {
+            self.events.borrow_mut().push(Event::Write(bytes.to_vec()));
+
+            Ok(())
+        }
+
+        fn read(&mut self, buffer: &mut [u8]) -> Result<(), ErrorKind> {
+            self.events.borrow_mut().push(Event::Read(buffer.len()));
+            buffer.fill(self.address);
+
+            Ok(())
+        }
+
+        fn stop(&mut self) {
+            self.events.borrow_mut().push(Event::Stop);
+        }
+    }
+
+    fn attach(i2c: &mut I2c, address: u8) -> Rc<RefCell<Vec<Event>>> {
+        let events = Rc::new(RefCell::new(Vec::new()));
+
+        i2c.attach(
+            address,
+            Recorder {
+                address,
+                events: events.clone(),
+            },
+        );
+
+        events
+    }
+
+    #[test]
+    fn routing() {
+        let mut i2c = I2c::new();
+
+        let first = attach(&mut i2c, 0x20);
+        let second = attach(&mut i2c, 0x3c);
+
+        let mut buffer = [0; 2];
+
+        I2c1::write_read(&mut i2c, 0x3c, &[1], &mut buffer).unwrap();
+        assert_eq!(buffer, [0x3c; 2]);
+        assert!(first.borrow().is_empty());
+
+        I2c1::read(&mut i2c, 0x20, &mut buffer).unwrap();
+        assert_eq!(buffer, [0x20; 2]);
+        assert_eq!(*first.borrow(), [Event::Start, Event::Read(2), Event::Stop]);
+        assert_eq!(
+            *second.borrow(),
+            [
+                Event::Start,
+                Event::Write(vec![1]),
+                Event::Start,
+                Event::Read(2),
+                Event::Stop
+            ]
+        );
+    }
+
+    #[test]
+    fn no_acknowledge() {
+        let mut i2c = I2c::new();
+
+        let events = attach(&mut i2c, 0x20);
+
+        assert_eq!(
+            I2c1::write(&mut i2c, 0x21, &[1]),
+            Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address))
+        );
+
+        i2c.detach(0x20).unwrap();
+
+        assert_eq!(
+            I2c1::write(&mut i2c, 0x20, &[1]),
+            Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address))
+        );
+        assert!(events.borrow().is_empty());
+    }
+
+    #[test]
+    fn framing() {
+        let mut i2c = I2c::new();
+
+        let events = attach(&mut i2c, 0x50);
+
+        let mut first = [0; 1];
+        let mut second = [0; 3];
+
+        I2c1::transaction(
+            &mut i2c,
+            0x50,
+            &mut [
+                Operation::Write(&[1]),
+                Operation::Write(&[2, 3]),
+                Operation::Read(&mut first),
+                Operation::Read(&mut second),
+                Operation::Write(&[4]),
+            ],
+        )
+        .unwrap();
+
+        // Consecutive operations of the same kind share a frame
+        assert_eq!(
+            *events.borrow(),
+            [
+                Event::Start,
+                Event::Write(vec![1]),
+                Event::Write(vec![2, 3]),
+                Event::Start,
+                Event::Read(1),
+                Event::Read(3),
+                Event::Start,
+                Event::Write(vec![4]),
+                Event::Stop
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn ten_bit_address() {
+        attach(&mut I2c::new(), 0x80);
+    }
+}
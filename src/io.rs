@@ -6,78 +6,128 @@ use embassy_sync::blocking_mutex::raw::{NoopRawMutex, RawMutex};
 use embassy_sync::mutex::Mutex as AsyncMutex;
 
 use channel_bridge::asynch::{Receiver, Sender};
-use channel_bridge::notification::Notification;
 
-use crate::display::Change as DisplayChange;
-use crate::gpio::Change as PinChange;
-use crate::peripherals::Peripherals;
+use crate::simulator::{Client, Simulator};
 
 pub use crate::dto::*;
 
-pub(crate) static NOTIFY: Notification = Notification::new();
-
+/// Notifies the process-wide default `Simulator` of a change
+///
+/// Not necessary anymore, as the simulator is notified of changes regardless of the callback passed to `Peripherals::take`.
 pub fn peripherals_callback() {
-    NOTIFY.notify();
+    Simulator::global().notify();
 }
 
+/// Serves the process-wide default `Simulator`, as used by `Peripherals::take`
 pub async fn process<S, R>(sender: S, receiver: R)
 where
     S: Sender<Data = UpdateEvent>,
     R: Receiver<Data = UpdateRequest, Error = S::Error>,
 {
-    handle(sender, receiver, &mut None, &mut None, &NOTIFY)
-        .await
-        .unwrap();
+    process_simulator(Simulator::global(), sender, receiver).await
+}
+
+pub async fn process_simulator<S, R>(simulator: &Simulator, sender: S, receiver: R)
+where
+    S: Sender<Data = UpdateEvent>,
+    R: Receiver<Data = UpdateRequest, Error = S::Error>,
+{
+    handle(simulator, sender, receiver).await.unwrap();
 }
 
-pub async fn handle<S, R>(
-    sender: S,
-    receiver: R,
-    pins_changes: &mut Option<Vec<PinChange>>,
-    displays_changes: &mut Option<Vec<DisplayChange>>,
-    notification: &Notification,
-) -> Result<(), S::Error>
+/// Serves a single UI connected over `sender` and `receiver`
+///
+/// The UI is attached to `simulator` as a new `Client`, so it first receives the complete state of the simulator,
+/// and several UIs can be served at the same time.
+pub async fn handle<S, R>(simulator: &Simulator, sender: S, receiver: R) -> Result<(), S::Error>
 where
     S: Sender<Data = UpdateEvent>,
     R: Receiver<Data = UpdateRequest, Error = S::Error>,
 {
+    let client = simulator.client();
+
     let sender = AsyncMutex::<NoopRawMutex, _>::new(sender);
 
-    select(
-        receive(receiver),
-        send(&sender, pins_changes, displays_changes, notification),
-    )
-    .await;
+    select(receive(simulator, receiver), send(&client, &sender)).await;
 
     Ok(())
 }
 
-async fn receive<R>(mut receiver: R) -> Result<(), R::Error>
+async fn receive<R>(simulator: &Simulator, mut receiver: R) -> Result<(), R::Error>
 where
     R: Receiver<Data = UpdateRequest>,
 {
     loop {
-        Peripherals::apply(receiver.recv().await?);
+        simulator.apply(receiver.recv().await?);
     }
 }
 
-async fn send<S>(
-    sender: &AsyncMutex<impl RawMutex, S>,
-    pins_changes: &mut Option<Vec<PinChange>>,
-    displays_changes: &mut Option<Vec<DisplayChange>>,
-    notification: &Notification,
-) -> Result<(), S::Error>
+async fn send<S>(client: &Client, sender: &AsyncMutex<impl RawMutex, S>) -> Result<(), S::Error>
 where
     S: Sender<Data = UpdateEvent>,
 {
     loop {
-        notification.wait().await;
+        client.notification().wait().await;
 
         let mut sender = sender.lock().await;
 
-        while let Some(event) = Peripherals::fetch(pins_changes, displays_changes) {
+        while let Some(event) = client.fetch() {
             trace!("SENDING: {:?}", event);
             sender.send(event).await?;
         }
     }
 }
+
+/// Serves `simulator` on a new thread, returning the channels for receiving its events and sending it requests
+///
+/// The thread exits once the requests sender is dropped.
+#[cfg(any(feature = "native", feature = "server"))]
+pub(crate) fn spawn(
+    simulator: &Simulator,
+) -> (
+    std::sync::mpsc::Receiver<UpdateEvent>,
+    futures::channel::mpsc::UnboundedSender<UpdateRequest>,
+) {
+    let (event_sender, event_receiver) = std::sync::mpsc::channel();
+    let (request_sender, request_receiver) = futures::channel::mpsc::unbounded();
+
+    let simulator = simulator.clone();
+
+    std::thread::spawn(move || {
+        futures::executor::block_on(handle(
+            &simulator,
+            EventSender(event_sender),
+            RequestReceiver(request_receiver),
+        ))
+    });
+
+    (event_receiver, request_sender)
+}
+
+#[cfg(any(feature = "native", feature = "server"))]
+struct EventSender(std::sync::mpsc::Sender<UpdateEvent>);
+
+#[cfg(any(feature = "native", feature = "server"))]
+impl Sender for EventSender {
+    type Error = ();
+
+    type Data = UpdateEvent;
+
+    async fn send(&mut self, data: Self::Data) -> Result<(), Self::Error> {
+        self.0.send(data).map_err(|_| ())
+    }
+}
+
+#[cfg(any(feature = "native", feature = "server"))]
+struct RequestReceiver(futures::channel::mpsc::UnboundedReceiver<UpdateRequest>);
+
+#[cfg(any(feature = "native", feature = "server"))]
+impl Receiver for RequestReceiver {
+    type Error = ();
+
+    type Data = UpdateRequest;
+
+    async fn recv(&mut self) -> Result<Self::Data, Self::Error> {
+        futures::StreamExt::next(&mut self.0).await.ok_or(())
+    }
+}
@@ -0,0 +1,295 @@
+extern crate alloc;
+use alloc::sync::Arc;
+
+use crate::gpio::{Input, Output, Pin, PinState, PinType, PinValue, Pins};
+
+pub use crate::dto::gpio::KeypadPin;
+
+/// The pins of a simulated matrix keypad
+///
+/// Created with `Pins::keypad`. The firmware scans the keypad by driving one row low at a time and reading the
+/// columns, which are pulled up unless they are connected to a row driven low through the pressed keys. As the
+/// keypad has no diodes, pressing three keys at the corners of a rectangle connects the fourth corner too
+/// (ghosting), as on real keypads.
+///
+/// The UI shows the keys as a clickable keypad, and they can be pressed by their labels with `TestBench::press`.
+pub struct Keypad {
+    pub rows: Vec<Pin<Output>>,
+    pub columns: Vec<Pin<Input>>,
+    /// The key pins, with their labels
+    keys: Vec<(Pin<Input>, String)>,
+}
+
+impl Keypad {
+    /// Binds the keys with single-character labels (e.g. digits) to the same keys of the keyboard in the UI
+    pub fn with_key_bindings(mut self) -> Self {
+        self.keys = self
+            .keys
+            .into_iter()
+            .map(|(key, label)| {
+                let mut chars = label.chars();
+
+                let key = match (chars.next(), chars.next()) {
+                    (Some(ch), None) => key.with_key(ch),
+                    _ => key,
+                };
+
+                (key, label)
+            })
+            .collect();
+
+        self
+    }
+}
+
+impl Pins {
+    /// Creates a keypad of `rows` x `cols` keys, labelled row by row with `labels`
+    ///
+    /// The row output pins are named `Keypad Row <n>` and the column input pins `Keypad Col <n>`, while the key pins
+    /// are named after their labels (keys without a label after their position). All of them are in the `Keypad`
+    /// category.
+    pub fn keypad(&mut self, rows: usize, cols: usize, labels: &[&str]) -> Keypad {
+        const CATEGORY: &str = "Keypad";
+
+        let row_pins = (0..rows)
+            .map(|row| {
+                self.new_pin(
+                    format!("Keypad Row {}", row + 1).as_str(),
+                    CATEGORY,
+                    PinType::Keypad(KeypadPin::Row(row as _)),
+                    PinValue::Output(true),
+                )
+            })
+            .collect::<Vec<Pin<Output>>>();
+
+        let column_pins = (0..cols)
+            .map(|column| {
+                self.new_pin(
+                    format!("Keypad Col {}", column + 1).as_str(),
+                    CATEGORY,
+                    PinType::Keypad(KeypadPin::Column(column as _)),
+                    PinValue::Input(true),
+                )
+            })
+            .collect::<Vec<Pin<Input>>>();
+
+        let mut keys = Vec::new();
+        let mut key_pins = Vec::new();
+
+        for row in 0..rows {
+            for column in 0..cols {
+                let label = labels
+                    .get(row * cols + column)
+                    .map(|label| label.to_string())
+                    .unwrap_or_else(|| format!("Key {}/{}", row + 1, column + 1));
+
+                let pin: Pin<Input> = self.new_pin(
+                    label.as_str(),
+                    CATEGORY,
+                    PinType::Keypad(KeypadPin::Key {
+                        row: row as _,
+                        column: column as _,
+                    }),
+                    PinValue::Input(false),
+                );
+
+                keys.push((pin.id(), row, column));
+                key_pins.push((pin, label));
+            }
+        }
+
+        let matrix = Arc::new(KeypadMatrix {
+            rows: row_pins.iter().map(Pin::id).collect(),
+            columns: column_pins.iter().map(Pin::id).collect(),
+            keys,
+        });
+
+        {
+            let mut states = self.states().lock().unwrap();
+
+            for id in matrix
+                .rows
+                .iter()
+                .chain(matrix.keys.iter().map(|(id, _, _)| id))
+            {
+                states[*id as usize].set_keypad(matrix.clone());
+            }
+        }
+
+        Keypad {
+            rows: row_pins,
+            columns: column_pins,
+            keys: key_pins,
+        }
+    }
+}
+
+/// The wiring of a keypad, by pin ids
+pub(crate) struct KeypadMatrix {
+    rows: Vec<u8>,
+    columns: Vec<u8>,
+    /// The keys, with their row and column indices
+    keys: Vec<(u8, usize, usize)>,
+}
+
+impl KeypadMatrix {
+    /// Drives the columns according to the levels of the rows and the pressed keys
+    pub(crate) fn update(&self, pins: &mut [PinState]) {
+        // The rows and then the columns, connected by the pressed keys
+        let mut nets = Nets::new(self.rows.len() + self.columns.len());
+
+        for (id, row, column) in &self.keys {
+            if matches!(pins[*id as usize].pin().value(), PinValue::Input(true)) {
+                nets.connect(*row, self.rows.len() + column);
+            }
+        }
+
+        let mut low = vec![false; self.rows.len() + self.columns.len()];
+
+        for (row, id) in self.rows.iter().enumerate() {
+            if matches!(pins[*id as usize].pin().value(), PinValue::Output(false)) {
+                low[nets.find(row)] = true;
+            }
+        }
+
+        for (column, id) in self.columns.iter().enumerate() {
+            let net = nets.find(self.rows.len() + column);
+
+            pins[*id as usize].drive_input(!low[net]);
+        }
+    }
+}
+
+/// A union-find of electrically connected nodes
+struct Nets(Vec<usize>);
+
+impl Nets {
+    fn new(nodes: usize) -> Self {
+        Self((0..nodes).collect())
+    }
+
+    /// The representative node of the net of `node`
+    fn find(&mut self, mut node: usize) -> usize {
+        while self.0[node] != node {
+            self.0[node] = self.0[self.0[node]];
+            node = self.0[node];
+        }
+
+        node
+    }
+
+    fn connect(&mut self, node: usize, other: usize) {
+        let (node, other) = (self.find(node), self.find(other));
+
+        self.0[node] = other;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_hal::digital::{InputPin, OutputPin};
+
+    use crate::simulator::Simulator;
+    use crate::testbench::TestBench;
+
+    use super::*;
+
+    const LABELS: &[&str] = &["1", "2", "3", "4", "5", "6", "7", "8", "9"];
+
+    fn keypad() -> (TestBench, Keypad) {
+        let simulator = Simulator::new();
+        let mut peripherals = simulator.take().unwrap();
+
+        let keypad = peripherals.pins.keypad(3, 3, LABELS);
+
+        (TestBench::new(&simulator), keypad)
+    }
+
+    /// Drives `row` low and the other rows high, as scanning firmware does
+    fn scan(keypad: &mut Keypad, row: Option<usize>) {
+        for (index, pin) in keypad.rows.iter_mut().enumerate() {
+            if Some(index) == row {
+                pin.set_low().unwrap();
+            } else {
+                pin.set_high().unwrap();
+            }
+        }
+    }
+
+    /// The columns which read low
+    fn low_columns(keypad: &mut Keypad) -> Vec<usize> {
+        keypad
+            .columns
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(index, pin)| pin.is_low().unwrap().then_some(index))
+            .collect()
+    }
+
+    #[test]
+    fn key_on_driven_row() {
+        let (bench, mut keypad) = keypad();
+
+        bench.press("6");
+
+        scan(&mut keypad, Some(1));
+        assert_eq!(low_columns(&mut keypad), [2]);
+
+        // The other rows do not reach the key
+        scan(&mut keypad, Some(0));
+        assert!(low_columns(&mut keypad).is_empty());
+
+        scan(&mut keypad, Some(2));
+        assert!(low_columns(&mut keypad).is_empty());
+
+        // Pressing or releasing the key while its row is driven changes the column right away
+        scan(&mut keypad, Some(1));
+
+        bench.release("6");
+        assert!(low_columns(&mut keypad).is_empty());
+
+        bench.press("6");
+        assert_eq!(low_columns(&mut keypad), [2]);
+    }
+
+    #[test]
+    fn key_on_idle_row() {
+        let (bench, mut keypad) = keypad();
+
+        bench.press("5");
+
+        scan(&mut keypad, None);
+        assert!(low_columns(&mut keypad).is_empty());
+
+        // Pressing more keys does not change anything while no row is driven
+        bench.press("1");
+        bench.press("9");
+        assert!(low_columns(&mut keypad).is_empty());
+    }
+
+    #[test]
+    fn ghosting() {
+        let (bench, mut keypad) = keypad();
+
+        // Three corners of a rectangle
+        bench.press("5");
+        bench.press("6");
+        bench.press("9");
+
+        // The bottom row reaches the middle column through keys 9, 6 and 5, as if key 8 was pressed
+        scan(&mut keypad, Some(2));
+        assert_eq!(low_columns(&mut keypad), [1, 2]);
+
+        scan(&mut keypad, Some(1));
+        assert_eq!(low_columns(&mut keypad), [1, 2]);
+
+        scan(&mut keypad, Some(0));
+        assert!(low_columns(&mut keypad).is_empty());
+
+        // Without the key connecting the two rows, there is no ghost
+        bench.release("6");
+
+        scan(&mut keypad, Some(2));
+        assert_eq!(low_columns(&mut keypad), [2]);
+    }
+}
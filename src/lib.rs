@@ -9,10 +9,40 @@ pub mod adc;
 pub mod display;
 pub mod dto;
 #[cfg(feature = "sim")]
+pub mod encoder;
+#[cfg(feature = "sim")]
 pub mod gpio;
+#[cfg(feature = "sim")]
+pub mod i2c;
 #[cfg(feature = "io")]
 pub mod io;
 #[cfg(feature = "sim")]
+pub mod keypad;
+#[cfg(feature = "sim")]
+pub mod mipi_dcs;
+#[cfg(feature = "native")]
+pub mod native;
+#[cfg(feature = "sim")]
 pub mod peripherals;
+#[cfg(feature = "sim")]
+pub mod pwm;
+#[cfg(feature = "record")]
+pub mod record;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "sim")]
+pub mod simulator;
+#[cfg(feature = "snapshot")]
+pub mod snapshot;
+#[cfg(feature = "sim")]
+pub mod spi;
+#[cfg(feature = "sim")]
+pub mod ssd1306;
+#[cfg(feature = "sim")]
+pub mod testbench;
+#[cfg(feature = "sim")]
+pub mod touch;
+#[cfg(feature = "sim")]
+pub mod uart;
 #[cfg(feature = "ui")]
 pub mod ui;
@@ -0,0 +1,513 @@
+use log::trace;
+
+use embedded_graphics_core::{
+    pixelcolor::Rgb888,
+    prelude::{Dimensions, DrawTarget, Point},
+    Pixel,
+};
+
+use crate::display::Display;
+use crate::gpio::PinProbe;
+use crate::spi::SpiDeviceModel;
+
+const SWRESET: u8 = 0x01;
+const SLPIN: u8 = 0x10;
+const SLPOUT: u8 = 0x11;
+const INVOFF: u8 = 0x20;
+const INVON: u8 = 0x21;
+const DISPOFF: u8 = 0x28;
+const DISPON: u8 = 0x29;
+const CASET: u8 = 0x2a;
+const RASET: u8 = 0x2b;
+const RAMWR: u8 = 0x2c;
+const MADCTL: u8 = 0x36;
+const COLMOD: u8 = 0x3a;
+const RAMWRC: u8 = 0x3c;
+
+const MADCTL_MY: u8 = 0x80;
+const MADCTL_MX: u8 = 0x40;
+const MADCTL_MV: u8 = 0x20;
+const MADCTL_BGR: u8 = 0x08;
+
+/// A MIPI DCS display controller, like the ST7789 or the ILI9341, rendering into a simulated display
+///
+/// The panel has the size of `display`. When attached to an `Spi` bus with `Spi::attach`, the bytes sent while
+/// the data/command pin `dc` is low are commands, and the others are their parameters or pixel data, so
+/// drivers like `mipidsi` over `display-interface-spi` run unmodified. Other interfaces (e.g. parallel ones)
+/// can feed the controller with `command` and `data`.
+///
+/// The supported commands are `SWRESET`, `SLPIN`/`SLPOUT`, `INVOFF`/`INVON`, `DISPOFF`/`DISPON`, `CASET`, `RASET`,
+/// `RAMWR`, `RAMWRC`, `MADCTL` (rotation, mirroring and BGR order) and `COLMOD` (12, 16, 18 and 24 bits per
+/// pixel). Other commands and their parameters are ignored, and nothing is ever read back.
+pub struct MipiDcs {
+    display: Display<Rgb888>,
+    dc: PinProbe,
+    width: usize,
+    height: usize,
+    bgr_panel: bool,
+    inverted_panel: bool,
+    /// The frame memory, in the orientation of the panel
+    gram: Vec<u32>,
+    command: Option<u8>,
+    params: Vec<u8>,
+    columns: (u16, u16),
+    rows: (u16, u16),
+    madctl: u8,
+    bits_per_pixel: u8,
+    inverted: bool,
+    on: bool,
+    cursor: (u16, u16),
+    /// The bytes of a pixel which was not completely received yet
+    pending: Vec<u8>,
+}
+
+impl MipiDcs {
+    pub fn new(display: Display<Rgb888>, dc: PinProbe) -> Self {
+        let size = display.bounding_box().size;
+
+        let mut this = Self {
+            display,
+            dc,
+            width: size.width as _,
+            height: size.height as _,
+            bgr_panel: false,
+            inverted_panel: false,
+            gram: vec![0; size.width as usize * size.height as usize],
+            command: None,
+            params: Vec::new(),
+            columns: (0, 0),
+            rows: (0, 0),
+            madctl: 0,
+            bits_per_pixel: 18,
+            inverted: false,
+            on: false,
+            cursor: (0, 0),
+            pending: Vec::new(),
+        };
+
+        this.reset();
+
+        this
+    }
+
+    /// Models a panel with its subpixels in BGR order, for which drivers set the BGR bit of `MADCTL`
+    pub fn with_bgr_panel(mut self) -> Self {
+        self.bgr_panel = true;
+        self
+    }
+
+    /// Models a panel with inverted colors (as most IPS panels), for which drivers send `INVON`
+    pub fn with_inverted_panel(mut self) -> Self {
+        self.inverted_panel = true;
+        self.redraw();
+        self
+    }
+
+    /// Processes a command byte
+    pub fn command(&mut self, command: u8) {
+        trace!("MIPI DCS command 0x{command:02x}");
+
+        self.command = Some(command);
+        self.params.clear();
+        self.pending.clear();
+
+        match command {
+            SWRESET => self.reset(),
+            SLPIN | SLPOUT => (),
+            INVOFF | INVON => {
+                self.inverted = command == INVON;
+                self.redraw();
+            }
+            DISPOFF | DISPON => {
+                self.on = command == DISPON;
+                self.redraw();
+            }
+            RAMWR => self.cursor = (self.columns.0, self.rows.0),
+            _ => (),
+        }
+    }
+
+    /// Processes the parameter or pixel bytes of the last command
+    pub fn data(&mut self, data: &[u8]) {
+        match self.command {
+            Some(RAMWR | RAMWRC) => self.write_pixels(data),
+            Some(command) => {
+                self.params.extend_from_slice(data);
+
+                match (command, self.params.as_slice()) {
+                    (CASET, [sh, sl, eh, el, ..]) => {
+                        self.columns = (
+                            u16::from_be_bytes([*sh, *sl]),
+                            u16::from_be_bytes([*eh, *el]),
+                        )
+                    }
+                    (RASET, [sh, sl, eh, el, ..]) => {
+                        self.rows = (
+                            u16::from_be_bytes([*sh, *sl]),
+                            u16::from_be_bytes([*eh, *el]),
+                        )
+                    }
+                    (MADCTL, [madctl, ..]) => self.madctl = *madctl,
+                    (COLMOD, [colmod, ..]) => {
+                        self.bits_per_pixel = match colmod & 0x07 {
+                            0b011 => 12,
+                            0b101 => 16,
+                            0b111 => 24,
+                            _ => 18,
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            None => (),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.columns = (0, self.width.saturating_sub(1) as _);
+        self.rows = (0, self.height.saturating_sub(1) as _);
+        self.madctl = 0;
+        self.bits_per_pixel = 18;
+        self.inverted = false;
+        self.on = false;
+        self.cursor = (0, 0);
+
+        self.redraw();
+    }
+
+    fn write_pixels(&mut self, data: &[u8]) {
+        let mut pixels = Vec::new();
+
+        for byte in data {
+            self.pending.push(*byte);
+
+            match (self.bits_per_pixel, self.pending.as_slice()) {
+                (12, [b0, b1, b2]) => {
+                    let (b0, b1, b2) = (*b0 as u32, *b1 as u32, *b2 as u32);
+
+                    let first = rgb(expand(b0 >> 4, 4), expand(b0 & 0xf, 4), expand(b1 >> 4, 4));
+                    let second = rgb(expand(b1 & 0xf, 4), expand(b2 >> 4, 4), expand(b2 & 0xf, 4));
+
+                    self.write_pixel(first, &mut pixels);
+                    self.write_pixel(second, &mut pixels);
+                }
+                (16, [b0, b1]) => {
+                    let rgb565 = u16::from_be_bytes([*b0, *b1]) as u32;
+
+                    let color = rgb(
+                        expand(rgb565 >> 11, 5),
+                        expand((rgb565 >> 5) & 0x3f, 6),
+                        expand(rgb565 & 0x1f, 5),
+                    );
+
+                    self.write_pixel(color, &mut pixels);
+                }
+                (18, [r, g, b]) => {
+                    let color = rgb(
+                        expand(*r as u32 >> 2, 6),
+                        expand(*g as u32 >> 2, 6),
+                        expand(*b as u32 >> 2, 6),
+                    );
+
+                    self.write_pixel(color, &mut pixels);
+                }
+                (24, [r, g, b]) => {
+                    self.write_pixel(u32::from_be_bytes([0, *r, *g, *b]), &mut pixels);
+                }
+                _ => continue,
+            }
+
+            self.pending.clear();
+        }
+
+        if self.on {
+            self.display.draw_iter(pixels).unwrap();
+        }
+    }
+
+    fn write_pixel(&mut self, color: u32, pixels: &mut Vec<Pixel<Rgb888>>) {
+        let (column, row) = self.cursor;
+
+        self.cursor = if column < self.columns.1 {
+            (column + 1, row)
+        } else if row < self.rows.1 {
+            (self.columns.0, row + 1)
+        } else {
+            (self.columns.0, self.rows.0)
+        };
+
+        let (x, y) = if self.madctl & MADCTL_MV != 0 {
+            (row as usize, column as usize)
+        } else {
+            (column as usize, row as usize)
+        };
+
+        if x >= self.width || y >= self.height {
+            return;
+        }
+
+        let x = if self.madctl & MADCTL_MX != 0 {
+            self.width - 1 - x
+        } else {
+            x
+        };
+
+        let y = if self.madctl & MADCTL_MY != 0 {
+            self.height - 1 - y
+        } else {
+            y
+        };
+
+        let color = if (self.madctl & MADCTL_BGR != 0) != self.bgr_panel {
+            let [_, r, g, b] = color.to_be_bytes();
+
+            u32::from_be_bytes([0, b, g, r])
+        } else {
+            color
+        };
+
+        self.gram[y * self.width + x] = color;
+
+        pixels.push(Pixel(Point::new(x as _, y as _), self.visible(color)));
+    }
+
+    fn redraw(&mut self) {
+        let area = self.display.bounding_box();
+        let colors = self
+            .gram
+            .iter()
+            .map(|color| self.visible(*color))
+            .collect::<Vec<_>>();
+
+        self.display.fill_contiguous(&area, colors).unwrap();
+    }
+
+    /// The color shown by the panel for `color` in the frame memory
+    fn visible(&self, color: u32) -> Rgb888 {
+        let color = if !self.on {
+            0
+        } else if self.inverted != self.inverted_panel {
+            !color & 0xffffff
+        } else {
+            color
+        };
+
+        let [_, r, g, b] = color.to_be_bytes();
+
+        Rgb888::new(r, g, b)
+    }
+}
+
+impl SpiDeviceModel for MipiDcs {
+    fn transfer(&mut self, _read: &mut [u8], write: &[u8]) {
+        if self.dc.is_high() {
+            self.data(write);
+        } else {
+            for command in write {
+                self.command(*command);
+            }
+        }
+    }
+}
+
+fn rgb(r: u32, g: u32, b: u32) -> u32 {
+    u32::from_be_bytes([0, r as u8, g as u8, b as u8])
+}
+
+fn expand(value: u32, bits: u32) -> u32 {
+    let max = (1 << bits) - 1;
+
+    (value * 255 + max / 2) / max
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_hal::digital::OutputPin;
+
+    use crate::gpio::{Output, Pin};
+    use crate::simulator::Simulator;
+    use crate::testbench::TestBench;
+
+    use super::*;
+
+    const WIDTH: usize = 8;
+    const HEIGHT: usize = 6;
+
+    const RED: u32 = 0xff0000;
+
+    fn controller() -> (TestBench, MipiDcs, Pin<Output>) {
+        let simulator = Simulator::new();
+        let mut peripherals = simulator.take().unwrap();
+
+        let display = peripherals
+            .displays
+            .display_for::<Rgb888>("Panel", WIDTH, HEIGHT);
+        let dc = peripherals.pins.output("DC", "Panel", false);
+
+        let mut controller = MipiDcs::new(display, dc.probe());
+
+        controller.command(SLPOUT);
+        controller.command(DISPON);
+
+        (TestBench::new(&simulator), controller, dc)
+    }
+
+    fn window(controller: &mut MipiDcs, columns: (u16, u16), rows: (u16, u16)) {
+        controller.command(CASET);
+        controller.data(&[columns.0.to_be_bytes(), columns.1.to_be_bytes()].concat());
+
+        controller.command(RASET);
+        controller.data(&[rows.0.to_be_bytes(), rows.1.to_be_bytes()].concat());
+    }
+
+    fn pixels(bench: &TestBench) -> Vec<u32> {
+        let image = bench.display_image("Panel").unwrap();
+
+        (0..HEIGHT)
+            .flat_map(|y| (0..WIDTH).map(move |x| (x, y)))
+            .map(|(x, y)| image.pixel(x, y))
+            .collect()
+    }
+
+    /// The expected pixels, with `color` in the rectangle from `(x0, y0)` to `(x1, y1)` (inclusive) and black elsewhere
+    fn rect(color: u32, (x0, y0): (usize, usize), (x1, y1): (usize, usize)) -> Vec<u32> {
+        (0..HEIGHT)
+            .flat_map(|y| (0..WIDTH).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                if (x0..=x1).contains(&x) && (y0..=y1).contains(&y) {
+                    color
+                } else {
+                    0
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn window_16bpp() {
+        let (bench, mut controller, _) = controller();
+
+        controller.command(COLMOD);
+        controller.data(&[0x55]);
+
+        window(&mut controller, (2, 4), (1, 2));
+
+        controller.command(RAMWR);
+        controller.data(&[0xf8, 0x00].repeat(6));
+
+        assert_eq!(pixels(&bench), rect(RED, (2, 1), (4, 2)));
+
+        // The write wraps around to the start of the window
+        controller.data(&[0x00, 0x1f]);
+
+        let mut expected = rect(RED, (2, 1), (4, 2));
+        expected[WIDTH + 2] = 0x0000ff;
+
+        assert_eq!(pixels(&bench), expected);
+    }
+
+    #[test]
+    fn pixel_formats() {
+        let (bench, mut controller, _) = controller();
+
+        window(&mut controller, (0, 1), (0, 0));
+
+        // 18 bits per pixel by default
+        controller.command(RAMWR);
+        controller.data(&[0xfc, 0x00, 0x00, 0x00, 0xfc, 0x00]);
+
+        assert_eq!(&pixels(&bench)[..2], [RED, 0x00ff00]);
+
+        controller.command(COLMOD);
+        controller.data(&[0x77]);
+
+        controller.command(RAMWR);
+        controller.data(&[0x12, 0x34, 0x56]);
+
+        assert_eq!(&pixels(&bench)[..2], [0x123456, 0x00ff00]);
+
+        // Two pixels in three bytes
+        controller.command(COLMOD);
+        controller.data(&[0x33]);
+
+        controller.command(RAMWR);
+        controller.data(&[0xf0, 0x00, 0xff]);
+
+        assert_eq!(&pixels(&bench)[..2], [RED, 0x00ffff]);
+    }
+
+    #[test]
+    fn madctl() {
+        let (bench, mut controller, _) = controller();
+
+        controller.command(COLMOD);
+        controller.data(&[0x77]);
+
+        // Row/column exchange, mirrored along x
+        controller.command(MADCTL);
+        controller.data(&[MADCTL_MV | MADCTL_MX]);
+
+        window(&mut controller, (1, 1), (0, 0));
+
+        controller.command(RAMWR);
+        controller.data(&[0xff, 0x00, 0x00]);
+
+        assert_eq!(pixels(&bench), rect(RED, (WIDTH - 1, 1), (WIDTH - 1, 1)));
+
+        // BGR order, mirrored along y
+        controller.command(MADCTL);
+        controller.data(&[MADCTL_BGR | MADCTL_MY]);
+
+        window(&mut controller, (0, 0), (0, 0));
+
+        controller.command(RAMWR);
+        controller.data(&[0xff, 0x00, 0x00]);
+
+        assert_eq!(pixels(&bench)[(HEIGHT - 1) * WIDTH], 0x0000ff);
+    }
+
+    #[test]
+    fn display_off_and_inversion() {
+        let (bench, mut controller, _) = controller();
+
+        controller.command(DISPOFF);
+
+        controller.command(RAMWR);
+        controller.data(&[0xfc, 0x00, 0x00]);
+
+        assert_eq!(pixels(&bench), rect(0, (0, 0), (0, 0)));
+
+        controller.command(DISPON);
+
+        assert_eq!(pixels(&bench), rect(RED, (0, 0), (0, 0)));
+
+        controller.command(INVON);
+
+        assert_eq!(pixels(&bench)[..2], [0x00ffff, 0xffffff]);
+    }
+
+    #[test]
+    fn spi() {
+        let (bench, mut controller, mut dc) = controller();
+
+        dc.set_low().unwrap();
+        controller.transfer(&mut [], &[CASET]);
+
+        dc.set_high().unwrap();
+        controller.transfer(&mut [], &[0, 3, 0, 3]);
+
+        dc.set_low().unwrap();
+        controller.transfer(&mut [], &[RASET]);
+
+        dc.set_high().unwrap();
+        controller.transfer(&mut [], &[0, 2, 0, 2]);
+
+        dc.set_low().unwrap();
+        controller.transfer(&mut [], &[RAMWR]);
+
+        dc.set_high().unwrap();
+        controller.transfer(&mut [], &[0xfc, 0x00, 0x00]);
+
+        assert_eq!(pixels(&bench), rect(RED, (3, 2), (3, 2)));
+    }
+}
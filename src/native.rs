@@ -0,0 +1,926 @@
+use core::convert::Infallible;
+
+use std::sync::{Arc, Mutex};
+
+use log::trace;
+
+use embedded_graphics::mono_font::{ascii::FONT_6X10, MonoTextStyle};
+use embedded_graphics::pixelcolor::Rgb888;
+use embedded_graphics::prelude::*;
+use embedded_graphics::text::{Baseline, Text};
+use embedded_graphics::Pixel;
+
+use minifb::{InputCallback, MouseButton, MouseMode, Window, WindowOptions};
+
+use crate::dto::display::*;
+use crate::dto::gpio::*;
+use crate::dto::uart::*;
+use crate::dto::*;
+use crate::simulator::Simulator;
+
+pub use minifb::Error;
+
+/// Runs the simulator UI in a native window
+///
+/// Blocks the calling thread until the window is closed. As some platforms require windows to be
+/// created on the main thread, the firmware should run on a separate thread.
+pub fn run(simulator: &Simulator) -> Result<(), Error> {
+    let (event_receiver, request_sender) = crate::io::spawn(simulator);
+
+    let mut window = Window::new(
+        "hal-sim",
+        NativeUi::DEFAULT_WIDTH,
+        NativeUi::DEFAULT_HEIGHT,
+        WindowOptions {
+            resize: true,
+            ..Default::default()
+        },
+    )?;
+
+    window.set_target_fps(60);
+
+    let typed = Arc::new(Mutex::new(Vec::new()));
+    window.set_input_callback(Box::new(TypedChars(typed.clone())));
+
+    let mut ui = NativeUi::new();
+
+    while window.is_open() {
+        while let Ok(event) = event_receiver.try_recv() {
+            trace!("Received event: {:?}", event);
+            ui.update(event);
+        }
+
+        let requests = ui.handle_input(
+            window.get_mouse_pos(MouseMode::Discard),
+            window.get_mouse_down(MouseButton::Left),
+            core::mem::take(&mut *typed.lock().unwrap()),
+        );
+
+        for request in requests {
+            trace!("Sending request: {:?}", request);
+
+            if request_sender.unbounded_send(request).is_err() {
+                return Ok(());
+            }
+        }
+
+        let (width, height) = window.get_size();
+
+        ui.render(width, height);
+
+        window.update_with_buffer(&ui.buffer, width, height)?;
+    }
+
+    Ok(())
+}
+
+struct TypedChars(Arc<Mutex<Vec<char>>>);
+
+impl InputCallback for TypedChars {
+    fn add_char(&mut self, uni_char: u32) {
+        if let Some(ch) = char::from_u32(uni_char) {
+            self.0.lock().unwrap().push(ch);
+        }
+    }
+}
+
+struct PinState {
+    meta: PinMeta,
+    dropped: bool,
+    value: PinValue,
+}
+
+struct DisplayState {
+    meta: DisplayMeta,
+    dropped: bool,
+    buffer: Vec<u32>,
+}
+
+struct UartState {
+    meta: UartMeta,
+    dropped: bool,
+    output: String,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Widget {
+    Toggle(u8),
+    Click(u8),
+    Slider {
+        id: u8,
+        x: usize,
+        width: usize,
+        min: u16,
+        max: u16,
+    },
+    Terminal(u8),
+    /// The knob of the rotary encoder with the A pin `a`, turned counterclockwise left of `middle`
+    Encoder {
+        a: u8,
+        middle: usize,
+        steps_per_detent: u8,
+    },
+    /// The touch panel of display `id`, drawn at `x`, `y` with `zoom`
+    Touch {
+        id: u8,
+        x: usize,
+        y: usize,
+        zoom: usize,
+    },
+}
+
+struct NativeUi {
+    pins: Vec<PinState>,
+    displays: Vec<DisplayState>,
+    uarts: Vec<UartState>,
+    buffer: Vec<u32>,
+    width: usize,
+    height: usize,
+    widgets: Vec<(Rect, Widget)>,
+    pressed: Option<Widget>,
+    focused_uart: u8,
+    /// The quadrature steps not applied yet, by the A pins of rotary encoders
+    encoder_steps: Vec<(u8, i32)>,
+}
+
+impl NativeUi {
+    const DEFAULT_WIDTH: usize = 900;
+    const DEFAULT_HEIGHT: usize = 600;
+
+    const MARGIN: usize = 8;
+    const LINE_HEIGHT: usize = 16;
+    const PINS_WIDTH: usize = 280;
+    const CONTROL_WIDTH: usize = 90;
+    const TERMINAL_LINES: usize = 12;
+    const MAX_OUTPUT_LEN: usize = 16384;
+
+    const BACKGROUND: u32 = 0xffffff;
+    const PRIMARY: u32 = 0x00d1b2;
+    const TEXT: u32 = 0x4a4a4a;
+    const BORDER: u32 = 0xbbbbbb;
+    const LED: u32 = 0xff3860;
+
+    fn new() -> Self {
+        Self {
+            pins: Vec::new(),
+            displays: Vec::new(),
+            uarts: Vec::new(),
+            buffer: Vec::new(),
+            width: 0,
+            height: 0,
+            widgets: Vec::new(),
+            pressed: None,
+            focused_uart: 0,
+            encoder_steps: Vec::new(),
+        }
+    }
+
+    fn update(&mut self, event: UpdateEvent) {
+        match event {
+            UpdateEvent::PinUpdate(update) => {
+                while self.pins.len() <= update.id as _ {
+                    self.pins.push(PinState {
+                        meta: Default::default(),
+                        dropped: false,
+                        value: PinValue::Output(false),
+                    });
+                }
+
+                let pin = &mut self.pins[update.id as usize];
+
+                if let Some(meta) = update.meta {
+                    pin.meta = meta;
+                }
+
+                pin.dropped = update.dropped;
+                pin.value = update.value;
+            }
+            UpdateEvent::DisplayUpdate(DisplayUpdate::MetaUpdate { id, meta, dropped }) => {
+                while self.displays.len() <= id as _ {
+                    self.displays.push(DisplayState {
+                        meta: Default::default(),
+                        dropped: false,
+                        buffer: Vec::new(),
+                    });
+                }
+
+                let display = &mut self.displays[id as usize];
+
+                if let Some(meta) = meta {
+                    display.buffer = vec![0; meta.width * meta.height];
+                    display.meta = meta;
+                }
+
+                display.dropped = dropped;
+            }
+            // The refreshed pixels follow as regular updates
+            UpdateEvent::DisplayUpdate(DisplayUpdate::RefreshUpdate { .. }) => (),
+            UpdateEvent::DisplayUpdate(DisplayUpdate::StripeUpdate(update)) => {
+                if let Some(display) = self.displays.get_mut(update.id as usize) {
+                    let offset = update.row as usize * display.meta.width + update.start as usize;
+
+                    for (index, pixel) in update.data.chunks_exact(STRIPE_PIXEL_SIZE).enumerate() {
+                        if let Some(cell) = display.buffer.get_mut(offset + index) {
+                            *cell = u32::from_be_bytes([0, pixel[0], pixel[1], pixel[2]]);
+                        }
+                    }
+                }
+            }
+            UpdateEvent::DisplayUpdate(DisplayUpdate::RectUpdate(update)) => {
+                if let Some(display) = self.displays.get_mut(update.id as usize) {
+                    for (x, y, pixel) in update.pixels() {
+                        if let Some(cell) = display.buffer.get_mut(y * display.meta.width + x) {
+                            *cell = u32::from_be_bytes([0, pixel[0], pixel[1], pixel[2]]);
+                        }
+                    }
+                }
+            }
+            UpdateEvent::UartUpdate(update) => {
+                while self.uarts.len() <= update.id as _ {
+                    self.uarts.push(UartState {
+                        meta: Default::default(),
+                        dropped: false,
+                        output: String::new(),
+                    });
+                }
+
+                let uart = &mut self.uarts[update.id as usize];
+
+                if let Some(meta) = update.meta {
+                    uart.meta = meta;
+                }
+
+                uart.dropped = update.dropped;
+
+                for ch in String::from_utf8_lossy(&update.data).chars() {
+                    match ch {
+                        '\r' => (),
+                        '\x08' | '\x7f' => {
+                            uart.output.pop();
+                        }
+                        ch => uart.output.push(ch),
+                    }
+                }
+
+                if uart.output.len() > Self::MAX_OUTPUT_LEN {
+                    let mut start = uart.output.len() - Self::MAX_OUTPUT_LEN;
+                    while !uart.output.is_char_boundary(start) {
+                        start += 1;
+                    }
+
+                    uart.output.drain(..start);
+                }
+            }
+        }
+    }
+
+    fn handle_input(
+        &mut self,
+        mouse: Option<(f32, f32)>,
+        mouse_down: bool,
+        typed: Vec<char>,
+    ) -> Vec<UpdateRequest> {
+        let mut requests = Vec::new();
+
+        let hit = mouse.and_then(|(x, y)| {
+            let (x, y) = (x as usize, y as usize);
+
+            self.widgets
+                .iter()
+                .find(|(rect, _)| rect.contains(x, y))
+                .map(|(_, widget)| (x, *widget))
+        });
+
+        let touch = |widget| mouse.and_then(|mouse| Self::touch(widget, mouse));
+
+        match (self.pressed, mouse_down) {
+            (None, true) => {
+                if let Some((x, widget)) = hit {
+                    match widget {
+                        Widget::Toggle(id) => {
+                            if let Some(input) = self.input(id) {
+                                requests.push(UpdateRequest::PinInputUpdate(
+                                    PinInputUpdate::Discrete(id, !input),
+                                ));
+                            }
+                        }
+                        Widget::Click(id) => {
+                            requests.push(UpdateRequest::PinInputUpdate(PinInputUpdate::Discrete(
+                                id, true,
+                            )));
+                        }
+                        Widget::Slider { .. } => {
+                            requests.extend(Self::slide(widget, x));
+                        }
+                        Widget::Terminal(id) => self.focused_uart = id,
+                        Widget::Touch { .. } => requests.extend(touch(widget)),
+                        Widget::Encoder {
+                            a,
+                            middle,
+                            steps_per_detent,
+                        } => {
+                            let steps = steps_per_detent.max(1) as i32;
+                            let steps = if x < middle { -steps } else { steps };
+
+                            match self.encoder_steps.iter_mut().find(|(id, _)| *id == a) {
+                                Some((_, pending)) => *pending += steps,
+                                None => self.encoder_steps.push((a, steps)),
+                            }
+                        }
+                    }
+
+                    self.pressed = Some(widget);
+                }
+            }
+            (Some(widget @ Widget::Slider { .. }), true) => {
+                if let Some((x, _)) = hit {
+                    requests.extend(Self::slide(widget, x));
+                }
+            }
+            (Some(widget @ Widget::Touch { .. }), true) => {
+                requests.extend(touch(widget));
+            }
+            (Some(Widget::Touch { id, .. }), false) => {
+                requests.push(UpdateRequest::TouchUpdate(TouchUpdate {
+                    id,
+                    points: heapless::Vec::new(),
+                }));
+
+                self.pressed = None;
+            }
+            (Some(Widget::Click(id)), false) => {
+                requests.push(UpdateRequest::PinInputUpdate(PinInputUpdate::Discrete(
+                    id, false,
+                )));
+
+                self.pressed = None;
+            }
+            (Some(_), false) => self.pressed = None,
+            _ => (),
+        }
+
+        requests.extend(self.step_encoders());
+
+        if self.uarts.len() > self.focused_uart as usize {
+            let mut data = heapless::Vec::<u8, UART_MAX_CHUNK_LEN>::new();
+
+            for ch in typed {
+                let ch = if ch == '\n' { '\r' } else { ch };
+
+                let mut bytes = [0; 4];
+                let bytes = ch.encode_utf8(&mut bytes).as_bytes();
+
+                if data.len() + bytes.len() > data.capacity() {
+                    requests.push(UpdateRequest::UartInputUpdate(UartInputUpdate {
+                        id: self.focused_uart,
+                        data: core::mem::take(&mut data),
+                    }));
+                }
+
+                data.extend_from_slice(bytes).unwrap();
+            }
+
+            if !data.is_empty() {
+                requests.push(UpdateRequest::UartInputUpdate(UartInputUpdate {
+                    id: self.focused_uart,
+                    data,
+                }));
+            }
+        }
+
+        requests
+    }
+
+    /// Applies the next pending quadrature step of every rotary encoder, one step per frame
+    fn step_encoders(&mut self) -> Vec<UpdateRequest> {
+        let mut requests = Vec::new();
+
+        for (a, pending) in core::mem::take(&mut self.encoder_steps) {
+            // The B pin is created right after the A pin
+            let Some(b) = a.checked_add(1) else {
+                continue;
+            };
+
+            let (Some(level_a), Some(level_b)) = (self.input(a), self.input(b)) else {
+                continue;
+            };
+
+            let (new_a, new_b) = quadrature_step(level_a, level_b, pending > 0);
+
+            let (id, level) = if new_a != level_a {
+                (a, new_a)
+            } else {
+                (b, new_b)
+            };
+
+            // Apply the step right away, so that the next one follows from it even before the simulator confirms it
+            self.pins[id as usize].value = PinValue::Input(level);
+
+            requests.push(UpdateRequest::PinInputUpdate(PinInputUpdate::Discrete(
+                id, level,
+            )));
+
+            let pending = pending - pending.signum();
+
+            if pending != 0 {
+                self.encoder_steps.push((a, pending));
+            }
+        }
+
+        requests
+    }
+
+    fn input(&self, id: u8) -> Option<bool> {
+        match self.pins.get(id as usize)?.value {
+            PinValue::Input(input) | PinValue::InputOutput { input, .. } => Some(input),
+            _ => None,
+        }
+    }
+
+    fn slide(widget: Widget, x: usize) -> Option<UpdateRequest> {
+        if let Widget::Slider {
+            id,
+            x: start,
+            width,
+            min,
+            max,
+        } = widget
+        {
+            let offset = x.clamp(start, start + width) - start;
+            let value = min as usize + offset * max.saturating_sub(min) as usize / width.max(1);
+
+            Some(UpdateRequest::PinInputUpdate(PinInputUpdate::Analog(
+                id, value as _,
+            )))
+        } else {
+            None
+        }
+    }
+
+    /// The (single) contact on the touch panel of a display, for the mouse at `(x, y)`
+    fn touch(widget: Widget, (x, y): (f32, f32)) -> Option<UpdateRequest> {
+        if let Widget::Touch {
+            id,
+            x: start_x,
+            y: start_y,
+            zoom,
+        } = widget
+        {
+            let point = TouchPoint {
+                id: 0,
+                x: ((x as usize).saturating_sub(start_x) / zoom) as _,
+                y: ((y as usize).saturating_sub(start_y) / zoom) as _,
+            };
+
+            Some(UpdateRequest::TouchUpdate(TouchUpdate {
+                id,
+                points: heapless::Vec::from_slice(&[point]).unwrap(),
+            }))
+        } else {
+            None
+        }
+    }
+
+    fn render(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        self.buffer.clear();
+        self.buffer.resize(width * height, Self::BACKGROUND);
+        self.widgets.clear();
+
+        let mut y = Self::MARGIN;
+
+        for id in 0..self.displays.len() {
+            y = self.render_display(id, Self::MARGIN, y);
+        }
+
+        let x = width.saturating_sub(Self::PINS_WIDTH + Self::MARGIN);
+        let mut y = Self::MARGIN;
+
+        let mut categories = Vec::<&str>::new();
+        for pin in self.pins.iter().filter(|pin| !pin.dropped) {
+            if !categories.contains(&pin.meta.category.as_str()) {
+                categories.push(pin.meta.category.as_str());
+            }
+        }
+
+        let categories = categories
+            .into_iter()
+            .map(|category| category.to_string())
+            .collect::<Vec<_>>();
+
+        for category in categories {
+            y = self.render_heading(&category, x, y, Self::PINS_WIDTH);
+
+            for id in 0..self.pins.len() {
+                let pin = &self.pins[id];
+
+                if !pin.dropped && pin.meta.category.as_str() == category {
+                    y = self.render_pin(id, x, y);
+                }
+            }
+
+            y += Self::MARGIN;
+        }
+
+        for id in 0..self.uarts.len() {
+            y = self.render_uart(id, x, y);
+        }
+    }
+
+    fn render_display(&mut self, id: usize, x: usize, y: usize) -> usize {
+        let display = &self.displays[id];
+
+        if display.dropped {
+            return y;
+        }
+
+        let title = format!(
+            "{} {}x{}",
+            display.meta.name, display.meta.width, display.meta.height
+        );
+
+        let zoom = display.meta.zoom.max(1) as usize;
+
+        let (width, height) = display.meta.logical_size();
+        let (width, height) = (width * zoom, height * zoom);
+
+        let mut y = self.render_heading(&title, x, y, width.max(Self::PINS_WIDTH));
+
+        let display = &self.displays[id];
+
+        let lcd = display.meta.lcd.map(|lcd| {
+            let levels: [u8; 256] = core::array::from_fn(|channel| lcd.level(channel as u8));
+
+            (lcd.dead_space(zoom), lcd.grid_color, levels)
+        });
+
+        for row in 0..height.min(self.height.saturating_sub(y)) {
+            for col in 0..width.min(self.width.saturating_sub(x)) {
+                if let Some((fb_x, fb_y)) = display
+                    .meta
+                    .to_framebuffer((col / zoom) as _, (row / zoom) as _)
+                {
+                    let color = display.buffer[fb_y * display.meta.width + fb_x];
+
+                    self.buffer[(y + row) * self.width + x + col] = match &lcd {
+                        Some((dead_space, grid_color, _))
+                            if col % zoom < *dead_space || row % zoom < *dead_space =>
+                        {
+                            *grid_color
+                        }
+                        Some((_, _, levels)) => {
+                            let [_, r, g, b] = color.to_be_bytes();
+
+                            u32::from_be_bytes([
+                                0,
+                                levels[r as usize],
+                                levels[g as usize],
+                                levels[b as usize],
+                            ])
+                        }
+                        None => color,
+                    };
+                }
+            }
+        }
+
+        self.widgets.push((
+            Rect {
+                x,
+                y,
+                width,
+                height,
+            },
+            Widget::Touch {
+                id: id as _,
+                x,
+                y,
+                zoom,
+            },
+        ));
+
+        y += height + Self::MARGIN;
+
+        y
+    }
+
+    fn render_heading(&mut self, text: &str, x: usize, y: usize, width: usize) -> usize {
+        self.fill(x, y, width, Self::LINE_HEIGHT, Self::PRIMARY);
+        self.text(text, x + 4, y + 3, 0xffffff);
+
+        y + Self::LINE_HEIGHT
+    }
+
+    fn render_pin(&mut self, id: usize, x: usize, y: usize) -> usize {
+        let pin = &self.pins[id];
+
+        let meta = pin.meta.clone();
+        let value = pin.value;
+
+        // The B pins of rotary encoders are turned by the knobs of their A pins
+        if meta.pin_type == PinType::Encoder(EncoderPin::B) {
+            return y;
+        }
+
+        let led = match value {
+            PinValue::Output(output) | PinValue::InputOutput { output, .. } => {
+                Some(if output { Self::LED } else { Self::BACKGROUND })
+            }
+            PinValue::Pwm { duty, enabled } => {
+                let max_duty = match meta.pin_type {
+                    PinType::Pwm(max_duty) => max_duty.max(1),
+                    _ => 1,
+                };
+
+                let duty = if enabled { duty as u32 } else { 0 };
+
+                Some(Self::blend(
+                    Self::BACKGROUND,
+                    Self::LED,
+                    duty,
+                    max_duty as _,
+                ))
+            }
+            _ => None,
+        };
+
+        if let Some(color) = led {
+            self.fill(x + 4, y + 3, 10, 10, Self::BORDER);
+            self.fill(x + 5, y + 4, 8, 8, color);
+        }
+
+        let name = match meta.pin_type {
+            PinType::Encoder(EncoderPin::A { .. }) => meta.name.trim_end_matches(" A"),
+            _ => meta.name.as_str(),
+        };
+
+        self.text(name, x + 20, y + 3, Self::TEXT);
+
+        let control_x = x + Self::PINS_WIDTH - Self::CONTROL_WIDTH - 4;
+        let control = Rect {
+            x: control_x,
+            y: y + 2,
+            width: Self::CONTROL_WIDTH,
+            height: Self::LINE_HEIGHT - 4,
+        };
+
+        match value {
+            PinValue::Input(_)
+                if matches!(meta.pin_type, PinType::Encoder(EncoderPin::A { .. })) =>
+            {
+                let steps_per_detent = match meta.pin_type {
+                    PinType::Encoder(EncoderPin::A { steps_per_detent }) => steps_per_detent,
+                    _ => unreachable!(),
+                };
+
+                let middle = control.x + control.width / 2;
+
+                self.fill(
+                    control.x,
+                    control.y,
+                    control.width,
+                    control.height,
+                    Self::BORDER,
+                );
+                self.fill(
+                    control.x + 1,
+                    control.y + 1,
+                    control.width - 2,
+                    control.height - 2,
+                    Self::BACKGROUND,
+                );
+                self.fill(middle, control.y, 1, control.height, Self::BORDER);
+
+                self.text("<", control.x + 4, control.y + 1, Self::TEXT);
+                self.text(
+                    ">",
+                    control.x + control.width - 10,
+                    control.y + 1,
+                    Self::TEXT,
+                );
+
+                self.widgets.push((
+                    control,
+                    Widget::Encoder {
+                        a: id as _,
+                        middle,
+                        steps_per_detent,
+                    },
+                ));
+            }
+            PinValue::Input(input) | PinValue::InputOutput { input, .. } => {
+                let widget = if meta.pin_type.is_click() {
+                    Widget::Click(id as _)
+                } else {
+                    Widget::Toggle(id as _)
+                };
+
+                self.fill(
+                    control.x,
+                    control.y,
+                    control.width,
+                    control.height,
+                    Self::BORDER,
+                );
+                self.fill(
+                    control.x + 1,
+                    control.y + 1,
+                    control.width - 2,
+                    control.height - 2,
+                    if input {
+                        Self::PRIMARY
+                    } else {
+                        Self::BACKGROUND
+                    },
+                );
+
+                let label = match (widget, input) {
+                    (Widget::Click(_), _) => "Click",
+                    (_, true) => "On",
+                    (_, false) => "Off",
+                };
+
+                self.text(
+                    label,
+                    control.x + 4,
+                    control.y + 1,
+                    if input { 0xffffff } else { Self::TEXT },
+                );
+
+                self.widgets.push((control, widget));
+            }
+            PinValue::Adc(value) => {
+                let (min, max) = match meta.pin_type {
+                    PinType::Analog(min, max) => (min, max),
+                    _ => (0, 0),
+                };
+
+                let filled = if max > min {
+                    (value.saturating_sub(min)) as usize * control.width / (max - min) as usize
+                } else {
+                    0
+                };
+
+                self.fill(
+                    control.x,
+                    control.y,
+                    control.width,
+                    control.height,
+                    Self::BORDER,
+                );
+                self.fill(
+                    control.x,
+                    control.y,
+                    filled.min(control.width),
+                    control.height,
+                    Self::PRIMARY,
+                );
+                self.text(
+                    &value.to_string(),
+                    control.x - 40,
+                    control.y + 1,
+                    Self::TEXT,
+                );
+
+                self.widgets.push((
+                    control,
+                    Widget::Slider {
+                        id: id as _,
+                        x: control.x,
+                        width: control.width,
+                        min,
+                        max,
+                    },
+                ));
+            }
+            _ => (),
+        }
+
+        y + Self::LINE_HEIGHT
+    }
+
+    fn render_uart(&mut self, id: usize, x: usize, y: usize) -> usize {
+        let uart = &self.uarts[id];
+
+        if uart.dropped {
+            return y;
+        }
+
+        let focused = self.focused_uart as usize == id;
+
+        let name = format!("{}{}", uart.meta.name, if focused { " *" } else { "" });
+
+        let lines = uart
+            .output
+            .split('\n')
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+            .take(Self::TERMINAL_LINES)
+            .rev()
+            .map(|line| line.to_string())
+            .collect::<Vec<_>>();
+
+        let y = self.render_heading(&name, x, y, Self::PINS_WIDTH);
+
+        let rect = Rect {
+            x,
+            y,
+            width: Self::PINS_WIDTH,
+            height: Self::TERMINAL_LINES * 10 + 4,
+        };
+
+        self.fill(rect.x, rect.y, rect.width, rect.height, 0x000000);
+
+        for (index, line) in lines.iter().enumerate() {
+            self.text(line, x + 2, y + 2 + index * 10, 0xdddddd);
+        }
+
+        self.widgets.push((rect, Widget::Terminal(id as _)));
+
+        y + rect.height + Self::MARGIN
+    }
+
+    fn text(&mut self, text: &str, x: usize, y: usize, color: u32) {
+        let style = MonoTextStyle::new(&FONT_6X10, Self::rgb(color));
+
+        Text::with_baseline(text, Point::new(x as _, y as _), style, Baseline::Top)
+            .draw(self)
+            .unwrap();
+    }
+
+    fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, color: u32) {
+        for row in y..(y + height).min(self.height) {
+            for col in x..(x + width).min(self.width) {
+                self.buffer[row * self.width + col] = color;
+            }
+        }
+    }
+
+    fn pixel(&mut self, x: usize, y: usize, color: u32) {
+        if x < self.width && y < self.height {
+            self.buffer[y * self.width + x] = color;
+        }
+    }
+
+    fn rgb(color: u32) -> Rgb888 {
+        let bytes = color.to_be_bytes();
+
+        Rgb888::new(bytes[1], bytes[2], bytes[3])
+    }
+
+    fn blend(from: u32, to: u32, num: u32, denom: u32) -> u32 {
+        let from = from.to_be_bytes();
+        let to = to.to_be_bytes();
+
+        let channel = |index: usize| {
+            ((from[index] as u32 * (denom - num) + to[index] as u32 * num) / denom) as u8
+        };
+
+        u32::from_be_bytes([0, channel(1), channel(2), channel(3)])
+    }
+}
+
+impl OriginDimensions for NativeUi {
+    fn size(&self) -> Size {
+        Size::new(self.width as _, self.height as _)
+    }
+}
+
+impl DrawTarget for NativeUi {
+    type Color = Rgb888;
+
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            if point.x >= 0 && point.y >= 0 {
+                self.pixel(
+                    point.x as _,
+                    point.y as _,
+                    u32::from_be_bytes([0, color.r(), color.g(), color.b()]),
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Rect {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+impl Rect {
+    fn contains(&self, x: usize, y: usize) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+}
@@ -0,0 +1,64 @@
+use core::convert::Infallible;
+use core::marker::PhantomData;
+
+use embedded_hal::pwm::{ErrorType, SetDutyCycle};
+use embedded_hal02::PwmPin;
+
+use crate::gpio::Pin;
+
+pub trait PwmTrait {
+    fn timer() -> u8;
+}
+
+pub struct Pwm<const ID: u8>(PhantomData<u8>);
+
+impl<const ID: u8> Pwm<ID> {
+    pub(crate) const fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<const ID: u8> PwmTrait for Pwm<ID> {
+    fn timer() -> u8 {
+        ID
+    }
+}
+
+impl<PWM: PwmTrait> ErrorType for Pin<PWM> {
+    type Error = Infallible;
+}
+
+impl<PWM: PwmTrait> SetDutyCycle for Pin<PWM> {
+    fn max_duty_cycle(&self) -> u16 {
+        Pin::max_duty(self)
+    }
+
+    fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Self::Error> {
+        Pin::set_duty(self, duty);
+        Ok(())
+    }
+}
+
+impl<PWM: PwmTrait> PwmPin for Pin<PWM> {
+    type Duty = u16;
+
+    fn disable(&mut self) {
+        Pin::set_enabled(self, false);
+    }
+
+    fn enable(&mut self) {
+        Pin::set_enabled(self, true);
+    }
+
+    fn get_duty(&self) -> Self::Duty {
+        Pin::duty(self)
+    }
+
+    fn get_max_duty(&self) -> Self::Duty {
+        Pin::max_duty(self)
+    }
+
+    fn set_duty(&mut self, duty: Self::Duty) {
+        Pin::set_duty(self, duty);
+    }
+}
@@ -0,0 +1,618 @@
+use core::fmt;
+use core::time::Duration;
+
+extern crate alloc;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+use channel_bridge::asynch::{Receiver, Sender};
+
+use crate::dto::display::DisplayMeta;
+use crate::dto::*;
+use crate::gpio::PinValue;
+use crate::simulator::Simulator;
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum RecordedData {
+    Request(UpdateRequest),
+    Event(UpdateEvent),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecordEntry {
+    /// Time elapsed since the start of the recording
+    pub timestamp: Duration,
+    pub data: RecordedData,
+}
+
+/// A recorded session of `UpdateRequest` inputs and `UpdateEvent` outputs
+///
+/// Recordings are stored as JSON lines, one `RecordEntry` per line.
+#[derive(Clone, Debug, Default)]
+pub struct Recording {
+    pub entries: Vec<RecordEntry>,
+}
+
+impl Recording {
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Self::read_from(BufReader::new(std::fs::File::open(path)?))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let mut write = BufWriter::new(std::fs::File::create(path)?);
+
+        self.write_to(&mut write)?;
+
+        write.flush()
+    }
+
+    pub fn read_from(read: impl BufRead) -> std::io::Result<Self> {
+        let entries = read
+            .lines()
+            .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+            .map(|line| serde_json::from_str(&line?).map_err(std::io::Error::from))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { entries })
+    }
+
+    pub fn write_to(&self, mut write: impl Write) -> std::io::Result<()> {
+        for entry in &self.entries {
+            serde_json::to_writer(&mut write, entry)?;
+            writeln!(write)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn requests(&self) -> impl Iterator<Item = (Duration, &UpdateRequest)> {
+        self.entries.iter().filter_map(|entry| match &entry.data {
+            RecordedData::Request(request) => Some((entry.timestamp, request)),
+            _ => None,
+        })
+    }
+
+    pub fn events(&self) -> impl Iterator<Item = (Duration, &UpdateEvent)> {
+        self.entries.iter().filter_map(|entry| match &entry.data {
+            RecordedData::Event(event) => Some((entry.timestamp, event)),
+            _ => None,
+        })
+    }
+
+    /// Replays the recorded requests against `simulator` with their original timing,
+    /// and compares the outputs of the simulator with the recorded ones
+    ///
+    /// The firmware under test should already be running against `simulator` on another thread.
+    /// After the last request is applied, outputs are collected for a further `settle` duration.
+    ///
+    /// Outputs are compared per pin, UART and display rather than event by event, as the
+    /// simulator coalesces changes differently depending on timing.
+    pub fn replay(&self, simulator: &Simulator, settle: Duration) -> Result<(), Divergence> {
+        const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+        let recorder = Recorder::new();
+        let client = simulator.client();
+
+        let mut requests = self.requests().peekable();
+        let mut settle_deadline = None;
+
+        loop {
+            let elapsed = recorder.start.elapsed();
+
+            while let Some((_, request)) = requests.next_if(|(timestamp, _)| *timestamp <= elapsed)
+            {
+                recorder.record(RecordedData::Request(request.clone()));
+                simulator.apply(request.clone());
+            }
+
+            while let Some(event) = client.fetch() {
+                recorder.record(RecordedData::Event(event));
+            }
+
+            if requests.peek().is_none() {
+                let deadline = *settle_deadline.get_or_insert(elapsed + settle);
+
+                if elapsed >= deadline {
+                    break;
+                }
+            }
+
+            std::thread::sleep(POLL_INTERVAL);
+        }
+
+        Outputs::new(self).compare(&Outputs::new(&recorder.recording()))
+    }
+}
+
+/// Records the requests and events passing through a `Sender` and a `Receiver`
+///
+/// Wrap the sender and receiver passed to `io::handle` or `io::process` with `Recorder::sender`
+/// and `Recorder::receiver` to record a session.
+#[derive(Clone)]
+pub struct Recorder {
+    start: Instant,
+    entries: Arc<Mutex<Vec<RecordEntry>>>,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            entries: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn sender<S>(&self, sender: S) -> RecordingSender<S> {
+        RecordingSender {
+            recorder: self.clone(),
+            sender,
+        }
+    }
+
+    pub fn receiver<R>(&self, receiver: R) -> RecordingReceiver<R> {
+        RecordingReceiver {
+            recorder: self.clone(),
+            receiver,
+        }
+    }
+
+    /// Returns a snapshot of what has been recorded so far
+    pub fn recording(&self) -> Recording {
+        Recording {
+            entries: self.entries.lock().unwrap().clone(),
+        }
+    }
+
+    fn record(&self, data: RecordedData) {
+        self.entries.lock().unwrap().push(RecordEntry {
+            timestamp: self.start.elapsed(),
+            data,
+        });
+    }
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct RecordingSender<S> {
+    recorder: Recorder,
+    sender: S,
+}
+
+impl<S> Sender for RecordingSender<S>
+where
+    S: Sender<Data = UpdateEvent>,
+{
+    type Error = S::Error;
+
+    type Data = UpdateEvent;
+
+    async fn send(&mut self, data: Self::Data) -> Result<(), Self::Error> {
+        self.recorder.record(RecordedData::Event(data.clone()));
+
+        self.sender.send(data).await
+    }
+}
+
+pub struct RecordingReceiver<R> {
+    recorder: Recorder,
+    receiver: R,
+}
+
+impl<R> Receiver for RecordingReceiver<R>
+where
+    R: Receiver<Data = UpdateRequest>,
+{
+    type Error = R::Error;
+
+    type Data = UpdateRequest;
+
+    async fn recv(&mut self) -> Result<Self::Data, Self::Error> {
+        let data = self.receiver.recv().await?;
+
+        self.recorder.record(RecordedData::Request(data.clone()));
+
+        Ok(data)
+    }
+}
+
+/// The first point where the outputs of a replayed session differ from the recorded ones
+///
+/// `timestamp` is the time of the recorded output, if the recording has one at that point.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Divergence {
+    Pin {
+        id: u8,
+        index: usize,
+        timestamp: Option<Duration>,
+        expected: Option<PinValue>,
+        actual: Option<PinValue>,
+    },
+    Uart {
+        id: u8,
+        offset: usize,
+        timestamp: Option<Duration>,
+        expected: Option<u8>,
+        actual: Option<u8>,
+    },
+    Display {
+        id: u8,
+        x: usize,
+        y: usize,
+        expected: Option<u32>,
+        actual: Option<u32>,
+    },
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pin {
+                id,
+                index,
+                timestamp,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Pin {id} diverges at change #{index} (recorded at {timestamp:?}): expected {expected:?}, got {actual:?}"
+            ),
+            Self::Uart {
+                id,
+                offset,
+                timestamp,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "UART {id} diverges at byte #{offset} (recorded at {timestamp:?}): expected {expected:?}, got {actual:?}"
+            ),
+            Self::Display {
+                id,
+                x,
+                y,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Display {id} diverges at x={x} y={y}: expected {expected:?}, got {actual:?}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Divergence {}
+
+/// The outputs of a session, reconstructed from its events
+#[derive(Default)]
+struct Outputs {
+    pins: BTreeMap<u8, Vec<(Duration, PinValue)>>,
+    uarts: BTreeMap<u8, Vec<(Duration, u8)>>,
+    displays: BTreeMap<u8, (DisplayMeta, Vec<u32>)>,
+}
+
+impl Outputs {
+    fn new(recording: &Recording) -> Self {
+        let mut this = Self::default();
+
+        for (timestamp, event) in recording.events() {
+            match event {
+                UpdateEvent::PinUpdate(update) => {
+                    let values = this.pins.entry(update.id).or_default();
+
+                    if values.last().map(|(_, value)| value) != Some(&update.value) {
+                        values.push((timestamp, update.value));
+                    }
+                }
+                UpdateEvent::UartUpdate(update) => {
+                    this.uarts
+                        .entry(update.id)
+                        .or_default()
+                        .extend(update.data.iter().map(|byte| (timestamp, *byte)));
+                }
+                UpdateEvent::DisplayUpdate(DisplayUpdate::MetaUpdate {
+                    id,
+                    meta: Some(meta),
+                    ..
+                }) => {
+                    this.displays
+                        .insert(*id, (meta.clone(), vec![0; meta.width * meta.height]));
+                }
+                UpdateEvent::DisplayUpdate(
+                    DisplayUpdate::MetaUpdate { .. } | DisplayUpdate::RefreshUpdate { .. },
+                ) => (),
+                // Pixels outside of the display (e.g. of a corrupt recording) are skipped, and show up
+                // as a divergence of the display instead
+                UpdateEvent::DisplayUpdate(DisplayUpdate::StripeUpdate(update)) => {
+                    if let Some((meta, buffer)) = this.displays.get_mut(&update.id) {
+                        for (index, pixel) in
+                            update.data.chunks_exact(STRIPE_PIXEL_SIZE).enumerate()
+                        {
+                            Self::set_pixel(
+                                meta,
+                                buffer,
+                                update.start as usize + index,
+                                update.row as usize,
+                                pixel,
+                            );
+                        }
+                    }
+                }
+                UpdateEvent::DisplayUpdate(DisplayUpdate::RectUpdate(update)) => {
+                    if let Some((meta, buffer)) = this.displays.get_mut(&update.id) {
+                        for (x, y, pixel) in update.pixels() {
+                            Self::set_pixel(meta, buffer, x, y, pixel);
+                        }
+                    }
+                }
+            }
+        }
+
+        this
+    }
+
+    fn set_pixel(meta: &DisplayMeta, buffer: &mut [u32], x: usize, y: usize, pixel: &[u8]) {
+        if x < meta.width {
+            if let Some(cell) = buffer.get_mut(y * meta.width + x) {
+                *cell = u32::from_be_bytes([0, pixel[0], pixel[1], pixel[2]]);
+            }
+        }
+    }
+
+    fn compare(&self, actual: &Self) -> Result<(), Divergence> {
+        for id in self.pins.keys().chain(actual.pins.keys()) {
+            let expected_values = self.pins.get(id).map(Vec::as_slice).unwrap_or(&[]);
+            let actual_values = actual.pins.get(id).map(Vec::as_slice).unwrap_or(&[]);
+
+            if let Some(index) = Self::mismatch(expected_values, actual_values, |a, b| a.1 == b.1) {
+                return Err(Divergence::Pin {
+                    id: *id,
+                    index,
+                    timestamp: expected_values.get(index).map(|(timestamp, _)| *timestamp),
+                    expected: expected_values.get(index).map(|(_, value)| *value),
+                    actual: actual_values.get(index).map(|(_, value)| *value),
+                });
+            }
+        }
+
+        for id in self.uarts.keys().chain(actual.uarts.keys()) {
+            let expected_bytes = self.uarts.get(id).map(Vec::as_slice).unwrap_or(&[]);
+            let actual_bytes = actual.uarts.get(id).map(Vec::as_slice).unwrap_or(&[]);
+
+            if let Some(offset) = Self::mismatch(expected_bytes, actual_bytes, |a, b| a.1 == b.1) {
+                return Err(Divergence::Uart {
+                    id: *id,
+                    offset,
+                    timestamp: expected_bytes.get(offset).map(|(timestamp, _)| *timestamp),
+                    expected: expected_bytes.get(offset).map(|(_, byte)| *byte),
+                    actual: actual_bytes.get(offset).map(|(_, byte)| *byte),
+                });
+            }
+        }
+
+        for id in self.displays.keys().chain(actual.displays.keys()) {
+            let expected_display = self.displays.get(id);
+            let actual_display = actual.displays.get(id);
+
+            let (meta, expected_buffer, actual_buffer) = match (expected_display, actual_display) {
+                (Some((meta, expected)), Some((_, actual))) => {
+                    (meta, expected.as_slice(), actual.as_slice())
+                }
+                (Some((meta, expected)), None) => (meta, expected.as_slice(), &[][..]),
+                (None, Some((meta, actual))) => (meta, &[][..], actual.as_slice()),
+                (None, None) => unreachable!(),
+            };
+
+            if let Some(index) = Self::mismatch(expected_buffer, actual_buffer, |a, b| a == b) {
+                return Err(Divergence::Display {
+                    id: *id,
+                    x: index % meta.width.max(1),
+                    y: index / meta.width.max(1),
+                    expected: expected_buffer.get(index).copied(),
+                    actual: actual_buffer.get(index).copied(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn mismatch<T>(expected: &[T], actual: &[T], eq: impl Fn(&T, &T) -> bool) -> Option<usize> {
+        expected
+            .iter()
+            .zip(actual)
+            .position(|(expected, actual)| !eq(expected, actual))
+            .or_else(|| (expected.len() != actual.len()).then(|| expected.len().min(actual.len())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    use std::thread::{self, JoinHandle};
+
+    use embedded_hal::digital::{InputPin, OutputPin, PinState};
+
+    use crate::testbench::TestBench;
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// The time between the recorded requests, which is how long the replayed firmware has to follow them
+    const GAP: Duration = Duration::from_millis(50);
+
+    /// Firmware which drives its output with the level of its input, inverted if `invert` is set
+    fn firmware(simulator: &Simulator, invert: bool, stop: Arc<AtomicBool>) -> JoinHandle<()> {
+        let simulator = simulator.clone();
+
+        thread::spawn(move || {
+            let mut pins = simulator.take().unwrap().pins;
+
+            let mut input = pins.input("Input", "Test", false);
+            let mut output = pins.output("Output", "Test", invert);
+
+            while !stop.load(Ordering::SeqCst) {
+                let high = input.is_high().unwrap();
+
+                output.set_state(PinState::from(high != invert)).unwrap();
+
+                thread::sleep(Duration::from_millis(1));
+            }
+        })
+    }
+
+    fn input(high: bool) -> UpdateRequest {
+        UpdateRequest::PinInputUpdate(PinInputUpdate::Discrete(0, high))
+    }
+
+    /// Records a session of the non-inverting firmware receiving `requests`
+    ///
+    /// Every request is recorded once the firmware followed it, and `GAP` after the previous one.
+    fn record(requests: &[UpdateRequest]) -> Recording {
+        let simulator = Simulator::new();
+        let bench = TestBench::new(&simulator);
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let handle = firmware(&simulator, false, stop.clone());
+
+        let recorder = Recorder::new();
+        let client = simulator.client();
+
+        let fetch = || {
+            while let Some(event) = client.fetch() {
+                recorder.record(RecordedData::Event(event));
+            }
+        };
+
+        bench.wait_for_output("Output", false, TIMEOUT).unwrap();
+        fetch();
+
+        for request in requests {
+            let start = Instant::now();
+
+            recorder.record(RecordedData::Request(request.clone()));
+            simulator.apply(request.clone());
+
+            let UpdateRequest::PinInputUpdate(PinInputUpdate::Discrete(_, high)) = request else {
+                unreachable!();
+            };
+
+            bench.wait_for_output("Output", *high, TIMEOUT).unwrap();
+            fetch();
+
+            thread::sleep(GAP.saturating_sub(start.elapsed()));
+        }
+
+        stop.store(true, Ordering::SeqCst);
+        handle.join().unwrap();
+
+        recorder.recording()
+    }
+
+    fn replay(recording: &Recording, invert: bool) -> Result<(), Divergence> {
+        let simulator = Simulator::new();
+        let bench = TestBench::new(&simulator);
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let handle = firmware(&simulator, invert, stop.clone());
+
+        bench.wait_for_output("Output", invert, TIMEOUT).unwrap();
+
+        let result = recording.replay(&simulator, GAP);
+
+        stop.store(true, Ordering::SeqCst);
+        handle.join().unwrap();
+
+        result
+    }
+
+    #[test]
+    fn round_trip() {
+        let recording = record(&[input(true), input(false), input(true)]);
+
+        // The recording survives saving and loading
+        let mut data = Vec::new();
+        recording.write_to(&mut data).unwrap();
+
+        let recording = Recording::read_from(data.as_slice()).unwrap();
+
+        assert_eq!(recording.requests().count(), 3);
+        assert!(recording.events().count() > 0);
+
+        assert_eq!(replay(&recording, false), Ok(()));
+    }
+
+    #[test]
+    fn mismatch() {
+        let recording = record(&[input(true), input(false)]);
+
+        assert!(matches!(
+            replay(&recording, true),
+            Err(Divergence::Pin {
+                id: 1,
+                index: 0,
+                expected: Some(PinValue::Output(false)),
+                actual: Some(PinValue::Output(true)),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn pixels_out_of_range() {
+        let event = |update| RecordEntry {
+            timestamp: Duration::ZERO,
+            data: RecordedData::Event(UpdateEvent::DisplayUpdate(update)),
+        };
+
+        let recording = Recording {
+            entries: vec![
+                event(DisplayUpdate::MetaUpdate {
+                    id: 0,
+                    meta: Some(DisplayMeta {
+                        width: 2,
+                        height: 2,
+                        ..Default::default()
+                    }),
+                    dropped: false,
+                }),
+                event(DisplayUpdate::RectUpdate(RectUpdate {
+                    id: 0,
+                    x: 1,
+                    y: 1,
+                    width: 2,
+                    height: 2,
+                    data: vec![0xff; 4 * STRIPE_PIXEL_SIZE],
+                })),
+                event(DisplayUpdate::StripeUpdate(StripeUpdate {
+                    id: 0,
+                    row: 5,
+                    start: 0,
+                    data: heapless::Vec::from_slice(&[0xff; STRIPE_PIXEL_SIZE]).unwrap(),
+                })),
+            ],
+        };
+
+        let outputs = Outputs::new(&recording);
+
+        assert_eq!(outputs.displays[&0].1, [0, 0, 0, 0xffffff]);
+
+        assert!(matches!(
+            Outputs::new(&Recording::default()).compare(&outputs),
+            Err(Divergence::Display { id: 0, .. })
+        ));
+    }
+}
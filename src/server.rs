@@ -0,0 +1,270 @@
+use core::time::Duration;
+
+use std::fs;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Component, Path, PathBuf};
+use std::time::Instant;
+
+use log::{info, trace, warn};
+
+use tungstenite::{Message, WebSocket};
+
+use crate::dto::wire::{self, WireError};
+use crate::simulator::Simulator;
+
+/// Serves `simulator` to browsers, blocking the calling thread
+///
+/// Plain HTTP requests are answered with the files in `assets`, which is expected to contain the built UI
+/// (i.e. an `index.html` which calls `ui::middleware::init` with this server as the WebSocket endpoint).
+/// WebSocket connections are bridged to `io::handle`, with events and requests encoded in the `dto::wire` format,
+/// as expected by the UI.
+///
+/// Each client is attached to the simulator as a separate `Client`, so several browser tabs can follow the
+/// same simulator, and the complete state of the simulator is resent whenever a client (re)connects.
+pub fn serve(
+    simulator: &Simulator,
+    address: impl ToSocketAddrs,
+    assets: impl AsRef<Path>,
+) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+
+    info!("Serving the simulator on {}", listener.local_addr()?);
+
+    for stream in listener.incoming() {
+        let stream = stream?;
+
+        let simulator = simulator.clone();
+        let assets = assets.as_ref().to_path_buf();
+
+        std::thread::spawn(move || {
+            if let Err(err) = handle_connection(&simulator, stream, &assets) {
+                warn!("Connection failed: {err}");
+            }
+        });
+    }
+
+    Ok(())
+}
+
+fn handle_connection(simulator: &Simulator, stream: TcpStream, assets: &Path) -> io::Result<()> {
+    let request = peek_headers(&stream)?.to_ascii_lowercase();
+
+    if request
+        .lines()
+        .any(|line| line.starts_with("upgrade:") && line.contains("websocket"))
+    {
+        let ws = tungstenite::accept(stream).map_err(|err| io::Error::other(err.to_string()))?;
+
+        handle_ws(simulator, ws)
+    } else {
+        handle_http(stream, assets)
+    }
+}
+
+/// Returns the headers of the request on `stream` without consuming them
+///
+/// Waits until the empty line which ends the headers is received, so that requests arriving in several
+/// TCP segments are routed correctly. Headers longer than `MAX_HEADERS_LEN` are truncated.
+fn peek_headers(stream: &TcpStream) -> io::Result<String> {
+    const MAX_HEADERS_LEN: usize = 8192;
+    const POLL_INTERVAL: Duration = Duration::from_millis(1);
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    let deadline = Instant::now() + TIMEOUT;
+
+    let mut buf = vec![0; MAX_HEADERS_LEN];
+    let mut peeked = 0;
+
+    loop {
+        let len = stream.peek(&mut buf)?;
+        let headers = &buf[..len];
+
+        // Peeking returns right away while there is data, so wait for more data to arrive
+        if len == 0 || len == buf.len() || headers.windows(4).any(|window| window == b"\r\n\r\n") {
+            break Ok(String::from_utf8_lossy(headers).into_owned());
+        } else if Instant::now() >= deadline {
+            break Err(io::Error::new(
+                ErrorKind::TimedOut,
+                "Timed out waiting for the request headers",
+            ));
+        } else if len == peeked {
+            std::thread::sleep(POLL_INTERVAL);
+        }
+
+        peeked = len;
+    }
+}
+
+fn handle_ws(simulator: &Simulator, mut ws: WebSocket<TcpStream>) -> io::Result<()> {
+    const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+    info!("Client connected");
+
+    ws.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+
+    let (events, requests) = crate::io::spawn(simulator);
+
+    loop {
+        match ws.read() {
+            Ok(Message::Binary(data)) => {
+                match wire::decode_request(&data) {
+                    Ok(request) => {
+                        trace!("Received request: {:?}", request);
+
+                        if requests.unbounded_send(request).is_err() {
+                            break;
+                        }
+                    }
+                    // The client speaks another version of the format, so none of its requests can be understood
+                    Err(err @ WireError::Version(_)) => {
+                        return Err(io::Error::new(ErrorKind::InvalidData, err))
+                    }
+                    Err(err) => warn!("Dropping a malformed request: {err}"),
+                }
+            }
+            Ok(Message::Close(_)) => break,
+            Ok(_) => (),
+            Err(tungstenite::Error::Io(err))
+                if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(tungstenite::Error::ConnectionClosed) => break,
+            Err(err) => return Err(io::Error::other(err.to_string())),
+        }
+
+        // Send all events of this round as a single batch
+        let batch = events.try_iter().collect::<Vec<_>>();
+
+        if !batch.is_empty() {
+            trace!("Sending events: {:?}", batch);
+
+            ws.send(Message::Binary(wire::encode_events(&batch)))
+                .map_err(|err| io::Error::other(err.to_string()))?;
+        }
+    }
+
+    info!("Client disconnected");
+
+    Ok(())
+}
+
+fn handle_http(mut stream: TcpStream, assets: &Path) -> io::Result<()> {
+    let mut buf = [0; 4096];
+    let len = stream.read(&mut buf)?;
+
+    let request = String::from_utf8_lossy(&buf[..len]);
+
+    let path = request
+        .lines()
+        .next()
+        .and_then(|line| {
+            let mut parts = line.split_whitespace();
+
+            (parts.next() == Some("GET"))
+                .then(|| parts.next())
+                .flatten()
+        })
+        .and_then(|uri| asset_path(assets, uri));
+
+    match path.map(|path| (fs::read(&path), path)) {
+        Some((Ok(content), path)) => {
+            trace!("Serving {}", path.display());
+
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                content_type(&path),
+                content.len()
+            )?;
+
+            stream.write_all(&content)?;
+        }
+        _ => {
+            write!(
+                stream,
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+            )?;
+        }
+    }
+
+    stream.flush()
+}
+
+fn asset_path(assets: &Path, uri: &str) -> Option<PathBuf> {
+    let uri = uri.split(['?', '#']).next().unwrap_or_default();
+    let uri = uri.trim_start_matches('/');
+
+    let relative = Path::new(if uri.is_empty() { "index.html" } else { uri });
+
+    // Do not serve anything outside of the assets directory
+    if relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        Some(assets.join(relative))
+    } else {
+        None
+    }
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("js") => "text/javascript",
+        Some("wasm") => "application/wasm",
+        Some("css") => "text/css",
+        Some("json") => "application/json",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("ico") => "image/x-icon",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::thread;
+
+    use super::*;
+
+    /// Connects to a new listener, returning the client and the server side of the connection
+    fn connect() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+
+        (client, server)
+    }
+
+    #[test]
+    fn headers_in_several_segments() {
+        let (mut client, server) = connect();
+
+        let sender = thread::spawn(move || {
+            client
+                .write_all(b"GET /ws HTTP/1.1\r\nHost: localhost\r\n")
+                .unwrap();
+            client.flush().unwrap();
+
+            thread::sleep(Duration::from_millis(20));
+
+            client
+                .write_all(b"Upgrade: websocket\r\nConnection: Upgrade\r\n\r\n")
+                .unwrap();
+
+            client
+        });
+
+        let headers = peek_headers(&server).unwrap();
+
+        assert!(headers.contains("Upgrade: websocket"));
+        assert!(headers.ends_with("\r\n\r\n"));
+
+        // The headers are still there for the handler
+        let mut buf = vec![0; headers.len()];
+        (&server).read_exact(&mut buf).unwrap();
+        assert_eq!(buf, headers.as_bytes());
+
+        sender.join().unwrap();
+    }
+}
@@ -0,0 +1,727 @@
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+extern crate alloc;
+use alloc::sync::{Arc, Weak};
+
+use std::sync::{Mutex, OnceLock};
+
+use channel_bridge::notification::Notification;
+
+use log::warn;
+
+use crate::display::{Change as DisplayChange, SharedDisplay, SharedDisplays};
+use crate::gpio::{Change as PinChange, SharedPin, SharedPins};
+use crate::peripherals::{Peripherals, TakeError};
+use crate::uart::{Change as UartChange, SharedUart, SharedUarts};
+
+pub use crate::dto::*;
+
+/// A simulated board
+///
+/// Each simulator owns its own pins, displays and UARTs, as well as the notification signalled
+/// whenever any of those changes, so several simulators can run independently in the same process.
+/// Cloning a simulator returns a new handle to the same board.
+#[derive(Clone)]
+pub struct Simulator {
+    pins: SharedPins,
+    displays: SharedDisplays,
+    uarts: SharedUarts,
+    taken: Arc<AtomicBool>,
+    notification: Arc<Notification>,
+    clients: SharedClients,
+    default_client: Arc<OnceLock<Arc<ClientState>>>,
+    rect_payload_budget: Arc<AtomicUsize>,
+}
+
+type SharedClients = Arc<Mutex<Vec<Weak<ClientState>>>>;
+
+impl Simulator {
+    pub fn new() -> Self {
+        Self {
+            pins: Arc::new(Mutex::new(Vec::new())),
+            displays: Arc::new(Mutex::new(Vec::new())),
+            uarts: Arc::new(Mutex::new(Vec::new())),
+            taken: Arc::new(AtomicBool::new(false)),
+            notification: Arc::new(Notification::new()),
+            clients: Arc::new(Mutex::new(Vec::new())),
+            default_client: Arc::new(OnceLock::new()),
+            rect_payload_budget: Arc::new(AtomicUsize::new(DEFAULT_RECT_PAYLOAD_BUDGET)),
+        }
+    }
+
+    /// The process-wide default simulator, as used by `Peripherals::take` and `io::process`
+    pub(crate) fn global() -> &'static Self {
+        static GLOBAL: OnceLock<Simulator> = OnceLock::new();
+
+        GLOBAL.get_or_init(Self::new)
+    }
+
+    pub fn take(&self) -> Result<Peripherals, TakeError> {
+        self.take_with(|| ())
+    }
+
+    /// Same as `take`, but also calls `changed` whenever the state of the simulator changes
+    pub fn take_with(&self, changed: impl Fn() + 'static) -> Result<Peripherals, TakeError> {
+        if self.taken.swap(true, Ordering::SeqCst) {
+            Err(TakeError::AlreadyTaken)
+        } else {
+            let notification = self.notification.clone();
+            let clients = self.clients.clone();
+
+            Ok(Peripherals::new(
+                self.pins.clone(),
+                self.displays.clone(),
+                self.uarts.clone(),
+                move || {
+                    changed();
+                    Self::notify_all(&notification, &clients);
+                },
+            ))
+        }
+    }
+
+    /// The notification signalled whenever the state of the simulator changes
+    ///
+    /// Each `Client` has its own notification, which should be used instead when fetching changes with the client.
+    pub fn notification(&self) -> &Notification {
+        &self.notification
+    }
+
+    /// Signals the notification of the simulator and of all its clients
+    pub fn notify(&self) {
+        Self::notify_all(&self.notification, &self.clients);
+    }
+
+    fn notify_all(notification: &Notification, clients: &SharedClients) {
+        notification.notify();
+
+        for client in clients.lock().unwrap().iter().filter_map(Weak::upgrade) {
+            client.notification.notify();
+        }
+    }
+
+    /// Sets the maximum size of the pixel data of a single `DisplayUpdate::RectUpdate` event, in bytes
+    ///
+    /// Changed display rows are merged into rectangles up to that size, so smaller budgets result in
+    /// more, but smaller events. Defaults to `DEFAULT_RECT_PAYLOAD_BUDGET`.
+    pub fn set_rect_payload_budget(&self, budget: usize) {
+        self.rect_payload_budget.store(budget, Ordering::SeqCst);
+    }
+
+    /// Attaches a new client (i.e. a UI) to the simulator
+    ///
+    /// The client tracks the changes it has not yet fetched separately from all other clients, and starts
+    /// with the complete state of the simulator, so that it receives all pins, displays and UARTs created so far.
+    pub fn client(&self) -> Client {
+        Client {
+            simulator: self.clone(),
+            state: self.register_client(),
+        }
+    }
+
+    fn register_client(&self) -> Arc<ClientState> {
+        let state = Arc::new(ClientState {
+            changes: Mutex::new(Changes::new()),
+            notification: Notification::new(),
+        });
+
+        self.clients.lock().unwrap().push(Arc::downgrade(&state));
+
+        self.resync_client(&state);
+
+        state
+    }
+
+    fn resync_client(&self, client: &ClientState) {
+        {
+            let mut changes = client.changes.lock().unwrap();
+
+            for id in 0..self.pins.lock().unwrap().len() {
+                *changes.pin(id) = PinChange::Created;
+            }
+
+            for (id, state) in self.displays.lock().unwrap().iter().enumerate() {
+                let meta = state.display().meta();
+                let change = changes.display(id);
+
+                change.created = true;
+
+                for row in 0..meta.height {
+                    change.update_row(row, 0, meta.width);
+                }
+            }
+
+            for id in 0..self.uarts.lock().unwrap().len() {
+                changes.uart(id).created = true;
+            }
+        }
+
+        client.notification.notify();
+    }
+
+    fn fetch_client(&self, client: &ClientState) -> Option<UpdateEvent> {
+        self.distribute();
+
+        let mut changes = client.changes.lock().unwrap();
+
+        if let Some(event) = self.find_pin_change(&mut changes.pins) {
+            Some(event)
+        } else if let Some(event) = self.find_display_change(&mut changes.displays) {
+            Some(event)
+        } else {
+            self.find_uart_change(&mut changes.uarts)
+        }
+    }
+
+    pub(crate) fn pins(&self) -> &SharedPins {
+        &self.pins
+    }
+
+    pub(crate) fn displays(&self) -> &SharedDisplays {
+        &self.displays
+    }
+
+    /// Applies a request of a UI to the simulator
+    ///
+    /// Requests for pins, UARTs or displays which do not exist, or for pins of another kind, are logged and dropped,
+    /// as they might come from UIs of an earlier run of the simulator.
+    pub fn apply(&self, request: UpdateRequest) {
+        match request {
+            UpdateRequest::PinInputUpdate(update) => {
+                {
+                    let mut pins = self.pins.lock().unwrap();
+
+                    let Some(state) = pins
+                        .get_mut(update.id() as usize)
+                        .filter(|state| update.applies_to(state.pin().value()))
+                    else {
+                        warn!("Dropping a request for a missing pin or a pin of another kind: {update:?}");
+                        return;
+                    };
+
+                    match update {
+                        PinInputUpdate::Discrete(_, high) => {
+                            state.pin_mut().set_discrete_input(high)
+                        }
+                        PinInputUpdate::Analog(_, input) => state.pin_mut().set_analog_input(input),
+                    }
+
+                    // Let the other clients know about the new input value
+                    state.split().1.update(&PinChange::Updated);
+
+                    crate::gpio::propagate(&mut pins, update.id() as _);
+                }
+
+                self.notify();
+            }
+            UpdateRequest::UartInputUpdate(update) => {
+                let mut uarts = self.uarts.lock().unwrap();
+
+                match uarts.get_mut(update.id as usize) {
+                    Some(uart) => uart.uart_mut().receive(&update.data),
+                    None => warn!("Dropping a request for a missing UART: {}", update.id),
+                }
+            }
+            UpdateRequest::TouchUpdate(update) => {
+                let mut displays = self.displays.lock().unwrap();
+
+                match displays.get_mut(update.id as usize) {
+                    Some(display) => display.update_touch(update.points),
+                    None => warn!("Dropping a request for a missing display: {}", update.id),
+                }
+            }
+        }
+    }
+
+    /// Fetches the next change of the simulator as an event
+    ///
+    /// Equivalent to fetching with a client attached to the simulator on the first call.
+    pub fn fetch(&self) -> Option<UpdateEvent> {
+        let client = self.default_client.get_or_init(|| self.register_client());
+
+        self.fetch_client(client)
+    }
+
+    /// Moves the changes recorded by the peripherals to the change vectors of all clients
+    fn distribute(&self) {
+        let clients = {
+            let mut clients = self.clients.lock().unwrap();
+
+            clients.retain(|client| client.strong_count() > 0);
+
+            clients.iter().filter_map(Weak::upgrade).collect::<Vec<_>>()
+        };
+
+        let mut changes = clients
+            .iter()
+            .map(|client| client.changes.lock().unwrap())
+            .collect::<Vec<_>>();
+
+        for (id, state) in self.pins.lock().unwrap().iter_mut().enumerate() {
+            let change = state.split().1;
+
+            if *change != PinChange::None {
+                for changes in &mut changes {
+                    changes.pin(id).update(change);
+                }
+
+                change.reset();
+            }
+        }
+
+        for (id, state) in self.displays.lock().unwrap().iter_mut().enumerate() {
+            let change = state.split().1;
+
+            for changes in &mut changes {
+                changes.display(id).update(change);
+            }
+
+            *change = Changes::new_display_change();
+        }
+
+        for (id, state) in self.uarts.lock().unwrap().iter_mut().enumerate() {
+            let change = state.split().1;
+
+            for changes in &mut changes {
+                changes.uart(id).update(change);
+            }
+
+            *change = Changes::new_uart_change();
+        }
+    }
+
+    fn find_pin_change(&self, changes: &mut [PinChange]) -> Option<UpdateEvent> {
+        let states = self.pins.lock().unwrap();
+
+        states
+            .iter()
+            .zip(changes.iter_mut())
+            .enumerate()
+            .find_map(|(id, (state, change))| {
+                Self::consume_pin_change(id as u8, state.pin(), change)
+            })
+    }
+
+    fn consume_pin_change(id: u8, pin: &SharedPin, change: &mut PinChange) -> Option<UpdateEvent> {
+        if *change != PinChange::None {
+            let event = Some(UpdateEvent::PinUpdate(PinUpdate {
+                id,
+                meta: if *change == PinChange::Created {
+                    Some(pin.meta().clone())
+                } else {
+                    None
+                },
+                dropped: pin.dropped(),
+                value: *pin.value(),
+            }));
+
+            change.reset();
+
+            event
+        } else {
+            None
+        }
+    }
+
+    fn find_display_change(&self, changes: &mut [DisplayChange]) -> Option<UpdateEvent> {
+        let max_payload = self.rect_payload_budget.load(Ordering::SeqCst);
+
+        let states = self.displays.lock().unwrap();
+
+        states
+            .iter()
+            .zip(changes.iter_mut())
+            .enumerate()
+            .find_map(|(id, (state, change))| {
+                Self::consume_display_change(id as u8, state.display(), change, max_payload)
+            })
+    }
+
+    fn consume_display_change(
+        id: u8,
+        display: &SharedDisplay,
+        change: &mut DisplayChange,
+        max_payload: usize,
+    ) -> Option<UpdateEvent> {
+        if change.created || change.dropped {
+            let event = Some(UpdateEvent::DisplayUpdate(DisplayUpdate::MetaUpdate {
+                id,
+                meta: change.created.then_some(display.meta().clone()),
+                dropped: display.dropped(),
+            }));
+
+            change.created = false;
+            change.dropped = false;
+
+            event
+        } else if let Some(refresh) = change.refresh.take() {
+            Some(UpdateEvent::DisplayUpdate(DisplayUpdate::RefreshUpdate {
+                id,
+                refresh,
+            }))
+        } else {
+            let max_pixels = max_payload / STRIPE_PIXEL_SIZE;
+
+            change.take_rect(max_pixels).map(|(x, y, width, height)| {
+                let meta = display.meta();
+
+                let data = (y..y + height)
+                    .flat_map(|row| {
+                        display.buffer()[row * meta.width + x..row * meta.width + x + width].iter()
+                    })
+                    .flat_map(|pixel| {
+                        let bytes = pixel.to_be_bytes();
+                        [bytes[1], bytes[2], bytes[3]]
+                    })
+                    .collect();
+
+                UpdateEvent::DisplayUpdate(DisplayUpdate::RectUpdate(RectUpdate {
+                    id,
+                    x: x as _,
+                    y: y as _,
+                    width: width as _,
+                    height: height as _,
+                    data,
+                }))
+            })
+        }
+    }
+
+    fn find_uart_change(&self, changes: &mut [UartChange]) -> Option<UpdateEvent> {
+        let states = self.uarts.lock().unwrap();
+
+        states
+            .iter()
+            .zip(changes.iter_mut())
+            .enumerate()
+            .find_map(|(id, (state, change))| {
+                Self::consume_uart_change(id as u8, state.uart(), change)
+            })
+    }
+
+    fn consume_uart_change(
+        id: u8,
+        uart: &SharedUart,
+        change: &mut UartChange,
+    ) -> Option<UpdateEvent> {
+        if change.created || change.dropped || !change.output.is_empty() {
+            let len = change.output.len().min(UART_MAX_CHUNK_LEN);
+
+            let event = Some(UpdateEvent::UartUpdate(UartUpdate {
+                id,
+                meta: change.created.then_some(uart.meta().clone()),
+                dropped: uart.dropped(),
+                data: change.output.drain(..len).collect(),
+            }));
+
+            change.created = false;
+            change.dropped = false;
+
+            event
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A UI attached to a `Simulator`, e.g. a browser tab
+///
+/// Each client has its own change vectors and notification, so several clients can follow the same simulator.
+/// Created with `Simulator::client`.
+pub struct Client {
+    simulator: Simulator,
+    state: Arc<ClientState>,
+}
+
+impl Client {
+    /// The notification signalled whenever the state of the simulator changes
+    pub fn notification(&self) -> &Notification {
+        &self.state.notification
+    }
+
+    /// Marks the complete state of the simulator as changed for this client
+    ///
+    /// The `fetch` calls which follow return all pins, displays (with their full content) and UARTs as if they
+    /// were just created, which is what a newly (re)connected UI needs.
+    pub fn resync(&self) {
+        self.simulator.resync_client(&self.state);
+    }
+
+    /// Fetches the next change of the simulator not yet seen by this client as an event
+    pub fn fetch(&self) -> Option<UpdateEvent> {
+        self.simulator.fetch_client(&self.state)
+    }
+}
+
+struct ClientState {
+    changes: Mutex<Changes>,
+    notification: Notification,
+}
+
+struct Changes {
+    pins: Vec<PinChange>,
+    displays: Vec<DisplayChange>,
+    uarts: Vec<UartChange>,
+}
+
+impl Changes {
+    const fn new() -> Self {
+        Self {
+            pins: Vec::new(),
+            displays: Vec::new(),
+            uarts: Vec::new(),
+        }
+    }
+
+    const fn new_display_change() -> DisplayChange {
+        DisplayChange {
+            created: false,
+            dropped: false,
+            refresh: None,
+            screen_updates: Vec::new(),
+        }
+    }
+
+    const fn new_uart_change() -> UartChange {
+        UartChange {
+            created: false,
+            dropped: false,
+            output: Vec::new(),
+        }
+    }
+
+    fn pin(&mut self, id: usize) -> &mut PinChange {
+        while self.pins.len() <= id {
+            self.pins.push(PinChange::None);
+        }
+
+        &mut self.pins[id]
+    }
+
+    fn display(&mut self, id: usize) -> &mut DisplayChange {
+        while self.displays.len() <= id {
+            self.displays.push(Self::new_display_change());
+        }
+
+        &mut self.displays[id]
+    }
+
+    fn uart(&mut self, id: usize) -> &mut UartChange {
+        while self.uarts.len() <= id {
+            self.uarts.push(Self::new_uart_change());
+        }
+
+        &mut self.uarts[id]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_graphics_core::pixelcolor::{Rgb888, RgbColor};
+    use embedded_graphics_core::prelude::{DrawTarget, Point};
+    use embedded_graphics_core::Pixel;
+    use embedded_hal::digital::OutputPin;
+    use embedded_io::Write;
+
+    use crate::display::Display;
+    use crate::gpio::{Input, Output, Pin, PinValue};
+    use crate::uart::Uart;
+
+    use super::*;
+
+    const WIDTH: usize = 4;
+    const HEIGHT: usize = 3;
+
+    struct Board {
+        _input: Pin<Input>,
+        output: Pin<Output>,
+        display: Display<Rgb888>,
+        uart: Uart,
+    }
+
+    fn board(simulator: &Simulator) -> Board {
+        let mut peripherals = simulator.take().unwrap();
+
+        let input = peripherals.pins.input("Input", "Test", false);
+        let output = peripherals.pins.output("Output", "Test", false);
+
+        let mut display = peripherals
+            .displays
+            .display_for::<Rgb888>("Display", WIDTH, HEIGHT);
+
+        display
+            .draw_iter([Pixel(Point::new(1, 2), Rgb888::RED)])
+            .unwrap();
+
+        let uart = peripherals.uarts.uart("UART");
+
+        Board {
+            _input: input,
+            output,
+            display,
+            uart,
+        }
+    }
+
+    fn fetch_all(client: &Client) -> Vec<UpdateEvent> {
+        core::iter::from_fn(|| client.fetch()).collect()
+    }
+
+    /// Asserts that `events` describe the complete state of the board, as a newly attached client needs
+    fn assert_complete_state(events: &[UpdateEvent]) {
+        let pins = events
+            .iter()
+            .filter_map(|event| match event {
+                UpdateEvent::PinUpdate(update) => {
+                    update.meta.as_ref().map(|meta| meta.name.as_str())
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(pins, ["Input", "Output"]);
+
+        assert!(events.iter().any(|event| matches!(
+            event,
+            UpdateEvent::DisplayUpdate(DisplayUpdate::MetaUpdate { id: 0, meta: Some(meta), .. })
+                if meta.name == "Display"
+        )));
+
+        let mut buffer = vec![None; WIDTH * HEIGHT];
+
+        for event in events {
+            if let UpdateEvent::DisplayUpdate(DisplayUpdate::RectUpdate(update)) = event {
+                // The rows are sent in full
+                assert_eq!((update.x, update.width), (0, WIDTH as u16));
+
+                for (x, y, pixel) in update.pixels() {
+                    buffer[y * WIDTH + x] =
+                        Some(u32::from_be_bytes([0, pixel[0], pixel[1], pixel[2]]));
+                }
+            }
+        }
+
+        let expected = (0..WIDTH * HEIGHT)
+            .map(|index| Some(if index == 2 * WIDTH + 1 { 0xff0000 } else { 0 }))
+            .collect::<Vec<_>>();
+
+        assert_eq!(buffer, expected);
+
+        assert!(events.iter().any(|event| matches!(
+            event,
+            UpdateEvent::UartUpdate(UartUpdate { id: 0, meta: Some(meta), .. }) if meta.name == "UART"
+        )));
+    }
+
+    #[test]
+    fn late_client() {
+        let simulator = Simulator::new();
+        let _board = board(&simulator);
+
+        // Another client consumes the changes made by the peripherals so far
+        let early = simulator.client();
+        assert_complete_state(&fetch_all(&early));
+
+        let late = simulator.client();
+        assert_complete_state(&fetch_all(&late));
+
+        assert!(early.fetch().is_none());
+    }
+
+    #[test]
+    fn clients_receive_every_change() {
+        let simulator = Simulator::new();
+        let mut board = board(&simulator);
+
+        let clients = [simulator.client(), simulator.client()];
+
+        for client in &clients {
+            fetch_all(client);
+        }
+
+        board.output.set_high().unwrap();
+        board
+            .display
+            .draw_iter([Pixel(Point::new(3, 0), Rgb888::GREEN)])
+            .unwrap();
+        board.uart.write_all(b"hi").unwrap();
+
+        for client in &clients {
+            let events = fetch_all(client);
+
+            assert!(events.iter().any(|event| matches!(
+                event,
+                UpdateEvent::PinUpdate(PinUpdate {
+                    id: 1,
+                    value: PinValue::Output(true),
+                    ..
+                })
+            )));
+            assert!(events.iter().any(|event| matches!(
+                event,
+                UpdateEvent::DisplayUpdate(DisplayUpdate::RectUpdate(update))
+                    if update.pixels().any(|(x, y, pixel)| (x, y) == (3, 0) && pixel == [0, 0xff, 0])
+            )));
+            assert!(events.iter().any(|event| matches!(
+                event,
+                UpdateEvent::UartUpdate(update) if update.data == b"hi"
+            )));
+        }
+    }
+
+    #[test]
+    fn resync() {
+        let simulator = Simulator::new();
+        let _board = board(&simulator);
+
+        let client = simulator.client();
+        fetch_all(&client);
+
+        assert!(client.fetch().is_none());
+
+        client.resync();
+        assert_complete_state(&fetch_all(&client));
+    }
+
+    #[test]
+    fn independent_simulators() {
+        let first = Simulator::new();
+        let second = Simulator::new();
+
+        let mut first_pins = first.take().unwrap().pins;
+        let mut second_pins = second.take().unwrap().pins;
+
+        let _first_input = first_pins.input("First", "Test", false);
+        let _second_input = second_pins.input("Second", "Test", false);
+
+        first.apply(UpdateRequest::PinInputUpdate(PinInputUpdate::Discrete(
+            0, true,
+        )));
+
+        // The second simulator only knows its own pin, which is not affected by the first simulator
+        let events = fetch_all(&second.client());
+
+        assert_eq!(events.len(), 1);
+        assert!(matches!(
+            &events[0],
+            UpdateEvent::PinUpdate(PinUpdate {
+                id: 0,
+                meta: Some(meta),
+                value: PinValue::Input(false),
+                ..
+            }) if meta.name == "Second"
+        ));
+
+        assert_eq!(first.pins().lock().unwrap().len(), 1);
+        assert_eq!(
+            *first.pins().lock().unwrap()[0].pin().value(),
+            PinValue::Input(true)
+        );
+    }
+}
@@ -0,0 +1,474 @@
+use core::fmt;
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::testbench::{DisplayImage, TestBench};
+
+/// When this environment variable is set, `assert_golden` (re)writes the golden images instead of comparing with them
+pub const BLESS_VAR: &str = "HAL_SIM_BLESS";
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    Encoding(png::EncodingError),
+    Decoding(png::DecodingError),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "IO error: {err}"),
+            Self::Encoding(err) => write!(f, "PNG encoding error: {err}"),
+            Self::Decoding(err) => write!(f, "PNG decoding error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<png::EncodingError> for SnapshotError {
+    fn from(err: png::EncodingError) -> Self {
+        Self::Encoding(err)
+    }
+}
+
+impl From<png::DecodingError> for SnapshotError {
+    fn from(err: png::DecodingError) -> Self {
+        Self::Decoding(err)
+    }
+}
+
+/// How much an image may differ from its golden image
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash)]
+pub struct Tolerance {
+    /// The maximum difference of every color channel of two pixels which are considered equal
+    pub channel: u8,
+    /// The maximum number of pixels which may differ
+    pub pixels: usize,
+}
+
+impl Tolerance {
+    /// Pixel-exact comparison
+    pub const EXACT: Self = Self {
+        channel: 0,
+        pixels: 0,
+    };
+}
+
+/// The result of a failed comparison of two images
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Mismatch {
+    pub differing_pixels: usize,
+    /// The differing pixels in red, over a dimmed version of the actual image
+    ///
+    /// Pixels outside of one of the images (when their sizes differ) are shown in magenta.
+    pub diff: DisplayImage,
+}
+
+impl DisplayImage {
+    pub fn to_png(&self) -> Result<Vec<u8>, SnapshotError> {
+        let mut data = Vec::new();
+
+        let mut encoder = png::Encoder::new(&mut data, self.width as _, self.height as _);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.to_rgb888())?;
+        writer.finish()?;
+
+        Ok(data)
+    }
+
+    /// Decodes a PNG image of any color type, ignoring its alpha channel
+    pub fn from_png(data: &[u8]) -> Result<Self, SnapshotError> {
+        let mut decoder = png::Decoder::new(data);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+
+        let mut reader = decoder.read_info()?;
+
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer)?;
+
+        let channels = info.color_type.samples();
+
+        let pixels = buffer[..info.buffer_size()]
+            .chunks_exact(channels)
+            .map(|pixel| match pixel {
+                [luma] | [luma, _] => u32::from_be_bytes([0, *luma, *luma, *luma]),
+                [r, g, b, ..] => u32::from_be_bytes([0, *r, *g, *b]),
+                [] => 0,
+            })
+            .collect();
+
+        Ok(Self {
+            width: info.width as _,
+            height: info.height as _,
+            pixels,
+        })
+    }
+
+    pub fn save_png(&self, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        fs::write(path, self.to_png()?)?;
+
+        Ok(())
+    }
+
+    pub fn load_png(path: impl AsRef<Path>) -> Result<Self, SnapshotError> {
+        Self::from_png(&fs::read(path)?)
+    }
+
+    /// Compares the image with `expected`
+    pub fn compare(&self, expected: &Self, tolerance: Tolerance) -> Result<(), Mismatch> {
+        const OUTSIDE: u32 = 0xff00ff;
+        const DIFFERENT: u32 = 0xff0000;
+
+        let width = self.width.max(expected.width);
+        let height = self.height.max(expected.height);
+
+        let pixel = |image: &Self, x: usize, y: usize| {
+            (x < image.width && y < image.height).then(|| image.pixel(x, y))
+        };
+
+        let mut differing_pixels = 0;
+        let mut pixels = Vec::with_capacity(width * height);
+
+        for y in 0..height {
+            for x in 0..width {
+                let diff = match (pixel(self, x, y), pixel(expected, x, y)) {
+                    (Some(actual), Some(expected)) => {
+                        if Self::within(actual, expected, tolerance.channel) {
+                            // Dimmed, so that the differences stand out
+                            (actual >> 2) & 0x3f3f3f
+                        } else {
+                            DIFFERENT
+                        }
+                    }
+                    _ => OUTSIDE,
+                };
+
+                if diff == DIFFERENT || diff == OUTSIDE {
+                    differing_pixels += 1;
+                }
+
+                pixels.push(diff);
+            }
+        }
+
+        if differing_pixels > tolerance.pixels
+            || (self.width, self.height) != (expected.width, expected.height)
+        {
+            Err(Mismatch {
+                differing_pixels,
+                diff: Self {
+                    width,
+                    height,
+                    pixels,
+                },
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    fn within(actual: u32, expected: u32, tolerance: u8) -> bool {
+        actual
+            .to_be_bytes()
+            .iter()
+            .zip(expected.to_be_bytes())
+            .all(|(actual, expected)| actual.abs_diff(expected) <= tolerance)
+    }
+}
+
+/// Asserts that `image` matches the golden PNG image at `golden`, within `tolerance`
+///
+/// On a mismatch, the actual image and a diff image are written next to the golden one (as `<name>.actual.png`
+/// and `<name>.diff.png`) before panicking. With the `HAL_SIM_BLESS` environment variable set, the golden image
+/// is (re)written instead.
+pub fn assert_golden(image: &DisplayImage, golden: impl AsRef<Path>, tolerance: Tolerance) {
+    let golden = golden.as_ref();
+
+    let actual_path = sibling(golden, "actual");
+    let diff_path = sibling(golden, "diff");
+
+    if env::var_os(BLESS_VAR).is_some() {
+        image
+            .save_png(golden)
+            .unwrap_or_else(|err| panic!("Cannot write {}: {err}", golden.display()));
+
+        return;
+    }
+
+    let expected = match DisplayImage::load_png(golden) {
+        Ok(expected) => expected,
+        Err(err) => {
+            let _ = image.save_png(&actual_path);
+
+            panic!(
+                "Cannot read the golden image {}: {err}; the actual image was written to {} \
+                 (run with {BLESS_VAR}=1 to create the golden image)",
+                golden.display(),
+                actual_path.display()
+            );
+        }
+    };
+
+    match image.compare(&expected, tolerance) {
+        Ok(()) => {
+            // Remove the leftovers of earlier failures
+            let _ = fs::remove_file(&actual_path);
+            let _ = fs::remove_file(&diff_path);
+        }
+        Err(mismatch) => {
+            let _ = image.save_png(&actual_path);
+            let _ = mismatch.diff.save_png(&diff_path);
+
+            panic!(
+                "The image ({}x{}) differs from the golden image {} ({}x{}) in {} pixels; see {} and {}",
+                image.width,
+                image.height,
+                golden.display(),
+                expected.width,
+                expected.height,
+                mismatch.differing_pixels,
+                actual_path.display(),
+                diff_path.display()
+            );
+        }
+    }
+}
+
+fn sibling(path: &Path, kind: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+
+    path.with_file_name(format!("{stem}.{kind}.png"))
+}
+
+impl TestBench {
+    /// Same as `assert_golden`, for the display with the given name
+    pub fn assert_display_golden(
+        &self,
+        name: &str,
+        golden: impl AsRef<Path>,
+        tolerance: Tolerance,
+    ) {
+        let image = self
+            .display_image(name)
+            .unwrap_or_else(|| panic!("No display named `{name}`"));
+
+        assert_golden(&image, golden, tolerance);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic;
+
+    use super::*;
+
+    fn image(width: usize, height: usize, pixels: impl Fn(usize, usize) -> u32) -> DisplayImage {
+        DisplayImage {
+            width,
+            height,
+            pixels: (0..height)
+                .flat_map(|y| (0..width).map(move |x| (x, y)))
+                .map(|(x, y)| pixels(x, y))
+                .collect(),
+        }
+    }
+
+    fn gradient() -> DisplayImage {
+        image(5, 3, |x, y| {
+            (((x * 50) << 16) | ((y * 100) << 8) | 0x40) as u32
+        })
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let image = gradient();
+
+        assert_eq!(
+            DisplayImage::from_png(&image.to_png().unwrap()).unwrap(),
+            image
+        );
+    }
+
+    #[test]
+    fn png_with_alpha() {
+        let mut data = Vec::new();
+
+        {
+            let mut encoder = png::Encoder::new(&mut data, 2, 1);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+
+            let mut writer = encoder.write_header().unwrap();
+            writer
+                .write_image_data(&[0x12, 0x34, 0x56, 0xff, 0xab, 0xcd, 0xef, 0x00])
+                .unwrap();
+        }
+
+        let image = DisplayImage::from_png(&data).unwrap();
+
+        assert_eq!(image.pixels, [0x123456, 0xabcdef]);
+    }
+
+    #[test]
+    fn compare_channel_tolerance() {
+        let expected = gradient();
+        let mut actual = expected.clone();
+
+        actual.pixels[7] += 2;
+
+        let mismatch = actual.compare(&expected, Tolerance::EXACT).unwrap_err();
+        assert_eq!(mismatch.differing_pixels, 1);
+
+        assert!(actual
+            .compare(
+                &expected,
+                Tolerance {
+                    channel: 1,
+                    pixels: 0
+                }
+            )
+            .is_err());
+        assert_eq!(
+            actual.compare(
+                &expected,
+                Tolerance {
+                    channel: 2,
+                    pixels: 0
+                }
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn compare_pixel_tolerance() {
+        let expected = gradient();
+        let mut actual = expected.clone();
+
+        actual.pixels[0] = 0xffffff;
+        actual.pixels[14] = 0xffffff;
+
+        assert!(actual
+            .compare(
+                &expected,
+                Tolerance {
+                    channel: 0,
+                    pixels: 1
+                }
+            )
+            .is_err());
+        assert_eq!(
+            actual.compare(
+                &expected,
+                Tolerance {
+                    channel: 0,
+                    pixels: 2
+                }
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn diff_image() {
+        let expected = image(2, 2, |_, _| 0x808080);
+        let mut actual = expected.clone();
+
+        actual.pixels[1] = 0;
+
+        let mismatch = actual.compare(&expected, Tolerance::EXACT).unwrap_err();
+
+        // The differing pixel in red, over the dimmed image
+        assert_eq!(
+            mismatch.diff,
+            image(2, 2, |x, y| if (x, y) == (1, 0) {
+                0xff0000
+            } else {
+                0x202020
+            })
+        );
+    }
+
+    #[test]
+    fn size_mismatch() {
+        let expected = image(2, 2, |_, _| 0);
+        let actual = image(3, 1, |_, _| 0);
+
+        let mismatch = actual
+            .compare(
+                &expected,
+                Tolerance {
+                    channel: 255,
+                    pixels: usize::MAX,
+                },
+            )
+            .unwrap_err();
+
+        // The pixels outside of one of the images are in magenta
+        assert_eq!(mismatch.differing_pixels, 4);
+        assert_eq!(
+            mismatch.diff,
+            image(3, 2, |x, y| if x < 2 && y < 1 { 0 } else { 0xff00ff })
+        );
+    }
+
+    /// The only test which sets `HAL_SIM_BLESS`, as the environment is shared by all tests
+    #[test]
+    fn golden() {
+        let dir = env::temp_dir().join(format!("hal-sim-snapshot-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let golden = dir.join("display.png");
+        let actual = dir.join("display.actual.png");
+        let diff = dir.join("display.diff.png");
+
+        let image = gradient();
+
+        // In case the tests themselves are run to bless golden images
+        env::remove_var(BLESS_VAR);
+
+        // A missing golden image fails, writing the actual image
+        assert!(panic::catch_unwind(|| assert_golden(&image, &golden, Tolerance::EXACT)).is_err());
+        assert_eq!(DisplayImage::load_png(&actual).unwrap(), image);
+
+        // Blessing writes the golden image
+        env::set_var(BLESS_VAR, "1");
+        assert_golden(&image, &golden, Tolerance::EXACT);
+        env::remove_var(BLESS_VAR);
+
+        assert_eq!(DisplayImage::load_png(&golden).unwrap(), image);
+
+        // A mismatch writes the actual and the diff images
+        let mut changed = image.clone();
+        changed.pixels[0] = 0xffffff;
+
+        assert!(
+            panic::catch_unwind(|| assert_golden(&changed, &golden, Tolerance::EXACT)).is_err()
+        );
+        assert_eq!(DisplayImage::load_png(&actual).unwrap(), changed);
+        assert_eq!(
+            DisplayImage::load_png(&diff).unwrap(),
+            changed.compare(&image, Tolerance::EXACT).unwrap_err().diff
+        );
+
+        // A match removes them
+        assert_golden(&image, &golden, Tolerance::EXACT);
+        assert!(!actual.exists());
+        assert!(!diff.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,387 @@
+use core::cell::RefCell;
+use core::convert::Infallible;
+
+extern crate alloc;
+use alloc::rc::Rc;
+
+use embedded_hal::digital::OutputPin;
+use embedded_hal::spi::{ErrorType, Operation, SpiBus, SpiDevice as SpiDevice1};
+use embedded_hal02::blocking::spi::{Transfer as Transfer02, Write as Write02};
+
+use crate::gpio::{Output, OutputMode, Pin, PinValue, SharedPins};
+
+/// A simulated device model attached to an `Spi` bus.
+///
+/// A device is selected when the output pin it is attached with is driven low.
+/// `select` and `deselect` are called on the first transfer after the chip-select line of the device changed
+/// and when the transaction of an `SpiDevice` is over.
+pub trait SpiDeviceModel {
+    fn select(&mut self) {}
+
+    fn deselect(&mut self) {}
+
+    /// Exchanges data with the device.
+    ///
+    /// `write` contains the bytes sent by the controller and `read` should be filled with the bytes sent back.
+    /// The two slices might have different lengths, with the same semantics as in `SpiBus::transfer`.
+    fn transfer(&mut self, read: &mut [u8], write: &[u8]);
+}
+
+struct SpiState {
+    pins: SharedPins,
+    devices: Vec<(u8, Box<dyn SpiDeviceModel>)>,
+    selected: Vec<u8>,
+}
+
+impl SpiState {
+    fn update_selection(&mut self) {
+        let selected = {
+            let pins = self.pins.lock().unwrap();
+
+            self.devices
+                .iter()
+                .map(|(cs, _)| *cs)
+                .filter(|cs| {
+                    matches!(
+                        pins[*cs as usize].pin().value(),
+                        PinValue::Output(false) | PinValue::InputOutput { output: false, .. }
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        for (cs, device) in &mut self.devices {
+            let was_selected = self.selected.contains(cs);
+            let is_selected = selected.contains(cs);
+
+            if was_selected && !is_selected {
+                device.deselect();
+            } else if !was_selected && is_selected {
+                device.select();
+            }
+        }
+
+        self.selected = selected;
+    }
+
+    fn transfer(&mut self, read: &mut [u8], write: &[u8]) {
+        self.update_selection();
+
+        // Nobody drives MISO, so it reads as pulled-up
+        read.fill(0xff);
+
+        for (cs, device) in &mut self.devices {
+            if self.selected.contains(cs) {
+                device.transfer(read, write);
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Spi {
+    state: Rc<RefCell<SpiState>>,
+}
+
+impl Spi {
+    pub(crate) fn new(pins: SharedPins) -> Self {
+        Self {
+            state: Rc::new(RefCell::new(SpiState {
+                pins,
+                devices: Vec::new(),
+                selected: Vec::new(),
+            })),
+        }
+    }
+
+    /// Attaches a device model to the bus.
+    ///
+    /// The device will be selected whenever `cs` is driven low.
+    pub fn attach<MODE>(&mut self, cs: &Pin<MODE>, device: impl SpiDeviceModel + 'static)
+    where
+        MODE: OutputMode,
+    {
+        self.state
+            .borrow_mut()
+            .devices
+            .push((cs.id(), Box::new(device)));
+    }
+
+    /// Creates an `SpiDevice` sharing this bus, which drives `cs` low for the duration of each transaction.
+    pub fn device(&self, cs: Pin<Output>) -> SpiDevice {
+        SpiDevice {
+            bus: self.clone(),
+            cs,
+        }
+    }
+
+    fn transfer(&mut self, read: &mut [u8], write: &[u8]) {
+        self.state.borrow_mut().transfer(read, write);
+    }
+
+    fn transfer_in_place(&mut self, words: &mut [u8]) {
+        let write = words.to_vec();
+
+        self.transfer(words, &write);
+    }
+
+    fn flush(&mut self) {
+        self.state.borrow_mut().update_selection();
+    }
+}
+
+impl ErrorType for Spi {
+    type Error = Infallible;
+}
+
+impl SpiBus for Spi {
+    fn read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        Spi::transfer(self, words, &[]);
+        Ok(())
+    }
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        Spi::transfer(self, &mut [], words);
+        Ok(())
+    }
+
+    fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
+        Spi::transfer(self, read, write);
+        Ok(())
+    }
+
+    fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        Spi::transfer_in_place(self, words);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Spi::flush(self);
+        Ok(())
+    }
+}
+
+impl embedded_hal_async::spi::SpiBus for Spi {
+    async fn read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        Spi::transfer(self, words, &[]);
+        Ok(())
+    }
+
+    async fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        Spi::transfer(self, &mut [], words);
+        Ok(())
+    }
+
+    async fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
+        Spi::transfer(self, read, write);
+        Ok(())
+    }
+
+    async fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        Spi::transfer_in_place(self, words);
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Spi::flush(self);
+        Ok(())
+    }
+}
+
+impl Transfer02<u8> for Spi {
+    type Error = Infallible;
+
+    fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Self::Error> {
+        Spi::transfer_in_place(self, words);
+        Ok(words)
+    }
+}
+
+impl Write02<u8> for Spi {
+    type Error = Infallible;
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        Spi::transfer(self, &mut [], words);
+        Ok(())
+    }
+}
+
+pub struct SpiDevice {
+    bus: Spi,
+    cs: Pin<Output>,
+}
+
+impl SpiDevice {
+    pub fn release(self) -> (Spi, Pin<Output>) {
+        (self.bus, self.cs)
+    }
+
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) {
+        self.cs.set_low().unwrap();
+
+        for operation in operations {
+            match operation {
+                Operation::Read(words) => self.bus.transfer(words, &[]),
+                Operation::Write(words) => self.bus.transfer(&mut [], words),
+                Operation::Transfer(read, write) => self.bus.transfer(read, write),
+                Operation::TransferInPlace(words) => self.bus.transfer_in_place(words),
+                // Delays are not simulated
+                Operation::DelayNs(_) => (),
+            }
+        }
+
+        self.cs.set_high().unwrap();
+
+        self.bus.flush();
+    }
+}
+
+impl ErrorType for SpiDevice {
+    type Error = Infallible;
+}
+
+impl SpiDevice1 for SpiDevice {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Self::Error> {
+        SpiDevice::transaction(self, operations);
+        Ok(())
+    }
+}
+
+impl embedded_hal_async::spi::SpiDevice for SpiDevice {
+    async fn transaction(
+        &mut self,
+        operations: &mut [Operation<'_, u8>],
+    ) -> Result<(), Self::Error> {
+        SpiDevice::transaction(self, operations);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::simulator::Simulator;
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    enum Event {
+        Select,
+        Transfer(Vec<u8>),
+        Deselect,
+    }
+
+    /// Records the calls of the bus and answers transfers with `reply`
+    struct Recorder {
+        reply: u8,
+        events: Rc<RefCell<Vec<Event>>>,
+    }
+
+    impl SpiDeviceModel for Recorder {
+        fn select(&mut self) {
+            self.events.borrow_mut().push(Event::Select);
+        }
+
+        fn deselect(&mut self) {
+            self.events.borrow_mut().push(Event::Deselect);
+        }
+
+        fn transfer(&mut self, read: &mut [u8], write: &[u8]) {
+            self.events
+                .borrow_mut()
+                .push(Event::Transfer(write.to_vec()));
+            read.fill(self.reply);
+        }
+    }
+
+    fn attach(spi: &mut Spi, cs: &Pin<Output>, reply: u8) -> Rc<RefCell<Vec<Event>>> {
+        let events = Rc::new(RefCell::new(Vec::new()));
+
+        spi.attach(
+            cs,
+            Recorder {
+                reply,
+                events: events.clone(),
+            },
+        );
+
+        events
+    }
+
+    #[test]
+    fn transaction() {
+        let simulator = Simulator::new();
+        let mut peripherals = simulator.take().unwrap();
+
+        let first_cs = peripherals.pins.output("CS0", "Bus", true);
+        let second_cs = peripherals.pins.output("CS1", "Bus", true);
+
+        let mut spi = peripherals.spi0;
+
+        let first = attach(&mut spi, &first_cs, 0x11);
+        let second = attach(&mut spi, &second_cs, 0x22);
+
+        let mut first_device = spi.device(first_cs);
+        let mut second_device = spi.device(second_cs);
+
+        let mut read = [0; 2];
+
+        SpiDevice1::transaction(
+            &mut first_device,
+            &mut [Operation::Write(&[1, 2]), Operation::Read(&mut read)],
+        )
+        .unwrap();
+
+        assert_eq!(read, [0x11; 2]);
+        assert_eq!(
+            *first.borrow(),
+            [
+                Event::Select,
+                Event::Transfer(vec![1, 2]),
+                Event::Transfer(vec![]),
+                Event::Deselect
+            ]
+        );
+        assert!(second.borrow().is_empty());
+
+        first.borrow_mut().clear();
+
+        let mut words = [3, 4, 5];
+
+        SpiDevice1::transaction(
+            &mut second_device,
+            &mut [Operation::TransferInPlace(&mut words)],
+        )
+        .unwrap();
+
+        assert_eq!(words, [0x22; 3]);
+        assert_eq!(
+            *second.borrow(),
+            [
+                Event::Select,
+                Event::Transfer(vec![3, 4, 5]),
+                Event::Deselect
+            ]
+        );
+        assert!(first.borrow().is_empty());
+    }
+
+    #[test]
+    fn nothing_selected() {
+        let simulator = Simulator::new();
+        let mut peripherals = simulator.take().unwrap();
+
+        let cs = peripherals.pins.output("CS", "Bus", true);
+
+        let mut spi = peripherals.spi0;
+
+        let events = attach(&mut spi, &cs, 0x11);
+
+        let mut read = [0; 3];
+
+        SpiBus::transfer(&mut spi, &mut read, &[1, 2, 3]).unwrap();
+
+        // Nobody drives MISO
+        assert_eq!(read, [0xff; 3]);
+        assert!(events.borrow().is_empty());
+    }
+}
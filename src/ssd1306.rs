@@ -0,0 +1,562 @@
+use log::trace;
+
+use embedded_graphics_core::{
+    pixelcolor::Rgb888,
+    prelude::{Dimensions, DrawTarget},
+};
+
+use crate::display::{BinaryPalette, Display};
+use crate::gpio::PinProbe;
+use crate::i2c::{ErrorKind, I2cDevice};
+use crate::spi::SpiDeviceModel;
+
+/// The controllers emulated by `Ssd1306`
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Controller {
+    /// 128x64 pixels of GDDRAM, with page, horizontal and vertical addressing and scrolling
+    Ssd1306,
+    /// 132x64 pixels of GDDRAM, centered on the 128 columns of the panel, with page addressing only
+    Sh1106,
+}
+
+impl Controller {
+    const fn ram_width(&self) -> usize {
+        match self {
+            Self::Ssd1306 => 128,
+            Self::Sh1106 => 132,
+        }
+    }
+}
+
+const RAM_HEIGHT: usize = 64;
+const PAGES: usize = RAM_HEIGHT / 8;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Addressing {
+    Horizontal,
+    Vertical,
+    Page,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+struct Scroll {
+    active: bool,
+    left: bool,
+    pages: (usize, usize),
+    vertical_offset: usize,
+}
+
+/// An SSD1306 or SH1106 monochrome OLED controller, rendering its GDDRAM into a simulated display
+///
+/// The panel has the size of `display` (e.g. 128x64 or 128x32) and is wired as on the common modules, i.e. the
+/// image is upright with the segment remap and the reversed COM scan direction drivers like `ssd1306` and
+/// `sh1106` configure by default.
+///
+/// Over I2C (`I2c::attach`), every write starts with the control byte of the controller. Over SPI (`Spi::attach`),
+/// bytes sent while the data/command pin passed to `with_dc` is low are commands.
+///
+/// All fundamental, addressing, hardware configuration and scrolling commands are decoded. The contrast dims the
+/// pixels, and as the simulator has no notion of time, active scrolling only advances when `scroll_step` is called.
+pub struct Ssd1306 {
+    display: Display<Rgb888>,
+    controller: Controller,
+    dc: Option<PinProbe>,
+    palette: BinaryPalette,
+    /// One byte per column and page, with the topmost pixel of the page in bit 0
+    gddram: Vec<u8>,
+    /// The command being received, with its parameters
+    command: Vec<u8>,
+    addressing: Addressing,
+    columns: (usize, usize),
+    pages: (usize, usize),
+    column: usize,
+    page: usize,
+    start_line: usize,
+    offset: usize,
+    multiplex: usize,
+    segment_remap: bool,
+    com_reversed: bool,
+    contrast: u8,
+    inverted: bool,
+    entire_on: bool,
+    on: bool,
+    scroll: Scroll,
+    /// The vertical scroll offset accumulated by `scroll_step`
+    scrolled: usize,
+    /// The I2C control byte applying to the next bytes of the current frame, if already received
+    control: Option<u8>,
+}
+
+impl Ssd1306 {
+    pub fn new(display: Display<Rgb888>, controller: Controller) -> Self {
+        let mut this = Self {
+            display,
+            controller,
+            dc: None,
+            palette: BinaryPalette::WHITE,
+            gddram: vec![0; controller.ram_width() * PAGES],
+            command: Vec::new(),
+            addressing: Addressing::Page,
+            columns: (0, 0),
+            pages: (0, 0),
+            column: 0,
+            page: 0,
+            start_line: 0,
+            offset: 0,
+            multiplex: RAM_HEIGHT,
+            segment_remap: false,
+            com_reversed: false,
+            contrast: 0x7f,
+            inverted: false,
+            entire_on: false,
+            on: false,
+            scroll: Default::default(),
+            scrolled: 0,
+            control: None,
+        };
+
+        this.reset();
+
+        this
+    }
+
+    /// Sets the data/command pin, which is necessary when the controller is attached to an SPI bus
+    pub fn with_dc(mut self, dc: PinProbe) -> Self {
+        self.dc = Some(dc);
+        self
+    }
+
+    /// Sets the colors of the lit and unlit pixels, e.g. `BinaryPalette::OLED_BLUE`
+    pub fn with_palette(mut self, palette: BinaryPalette) -> Self {
+        self.palette = palette;
+        self.render();
+        self
+    }
+
+    /// Resets the controller, as when pulsing its reset pin
+    pub fn reset(&mut self) {
+        self.command.clear();
+        self.addressing = Addressing::Page;
+        self.columns = (0, self.controller.ram_width() - 1);
+        self.pages = (0, PAGES - 1);
+        self.column = 0;
+        self.page = 0;
+        self.start_line = 0;
+        self.offset = 0;
+        self.multiplex = RAM_HEIGHT;
+        self.segment_remap = false;
+        self.com_reversed = false;
+        self.contrast = 0x7f;
+        self.inverted = false;
+        self.entire_on = false;
+        self.on = false;
+        self.scroll = Default::default();
+        self.scrolled = 0;
+
+        self.render();
+    }
+
+    /// Advances active scrolling by one step, i.e. by one column and by the vertical scrolling offset
+    pub fn scroll_step(&mut self) {
+        if !self.scroll.active {
+            return;
+        }
+
+        let width = self.controller.ram_width();
+
+        for page in self.scroll.pages.0..=self.scroll.pages.1.min(PAGES - 1) {
+            let row = &mut self.gddram[page * width..(page + 1) * width];
+
+            if self.scroll.left {
+                row.rotate_left(1);
+            } else {
+                row.rotate_right(1);
+            }
+        }
+
+        self.scrolled = (self.scrolled + self.scroll.vertical_offset) % RAM_HEIGHT;
+
+        self.render();
+    }
+
+    /// Processes command bytes, which also carry the parameters of the commands
+    pub fn commands(&mut self, commands: &[u8]) {
+        for command in commands {
+            self.command.push(*command);
+
+            if self.command.len() >= Self::command_len(self.command[0]) {
+                let command = core::mem::take(&mut self.command);
+
+                self.execute(&command);
+            }
+        }
+
+        self.render();
+    }
+
+    /// Processes data bytes, which are written to the GDDRAM
+    pub fn data(&mut self, data: &[u8]) {
+        let width = self.controller.ram_width();
+
+        for byte in data {
+            if self.column < width && self.page < PAGES {
+                self.gddram[self.page * width + self.column] = *byte;
+            }
+
+            self.advance();
+        }
+
+        self.render();
+    }
+
+    fn command_len(command: u8) -> usize {
+        match command {
+            0x26 | 0x27 => 7,
+            0x29 | 0x2a => 6,
+            0x21 | 0x22 | 0xa3 => 3,
+            0x20 | 0x81 | 0x8d | 0xa8 | 0xad | 0xd3 | 0xd5 | 0xd9 | 0xda | 0xdb => 2,
+            _ => 1,
+        }
+    }
+
+    fn execute(&mut self, command: &[u8]) {
+        trace!("SSD1306 command {command:02x?}");
+
+        let ssd1306 = self.controller == Controller::Ssd1306;
+
+        match *command {
+            [low @ 0x00..=0x0f] => self.column = (self.column & 0xf0) | low as usize,
+            [high @ 0x10..=0x1f] => {
+                self.column = (self.column & 0x0f) | ((high as usize & 0x0f) << 4)
+            }
+            [0x20, mode] if ssd1306 => {
+                self.addressing = match mode & 0x03 {
+                    0 => Addressing::Horizontal,
+                    1 => Addressing::Vertical,
+                    _ => Addressing::Page,
+                }
+            }
+            [0x21, start, end] if ssd1306 => {
+                self.columns = (start as usize & 0x7f, end as usize & 0x7f);
+                self.column = self.columns.0;
+            }
+            [0x22, start, end] if ssd1306 => {
+                self.pages = (start as usize & 0x07, end as usize & 0x07);
+                self.page = self.pages.0;
+            }
+            [direction @ (0x26 | 0x27), _, start, _, end, ..] if ssd1306 => {
+                self.scroll = Scroll {
+                    active: false,
+                    left: direction == 0x27,
+                    pages: (start as usize & 0x07, end as usize & 0x07),
+                    vertical_offset: 0,
+                }
+            }
+            [direction @ (0x29 | 0x2a), _, start, _, end, offset] if ssd1306 => {
+                self.scroll = Scroll {
+                    active: false,
+                    left: direction == 0x2a,
+                    pages: (start as usize & 0x07, end as usize & 0x07),
+                    vertical_offset: offset as usize & 0x3f,
+                }
+            }
+            [0x2e] if ssd1306 => {
+                self.scroll.active = false;
+                self.scrolled = 0;
+            }
+            [0x2f] if ssd1306 => self.scroll.active = true,
+            [line @ 0x40..=0x7f] => self.start_line = line as usize & 0x3f,
+            [0x81, contrast] => self.contrast = contrast,
+            [remap @ (0xa0 | 0xa1)] => self.segment_remap = remap == 0xa1,
+            [entire @ (0xa4 | 0xa5)] => self.entire_on = entire == 0xa5,
+            [inverse @ (0xa6 | 0xa7)] => self.inverted = inverse == 0xa7,
+            [0xa8, multiplex] => self.multiplex = (multiplex as usize & 0x3f) + 1,
+            [on @ (0xae | 0xaf)] => self.on = on == 0xaf,
+            [page @ 0xb0..=0xb7] => self.page = page as usize & 0x07,
+            [direction @ (0xc0 | 0xc8)] => self.com_reversed = direction == 0xc8,
+            [0xd3, offset] => self.offset = offset as usize & 0x3f,
+            // Clock, precharge, COM pins, VCOMH, charge pump and DC-DC configuration, NOP
+            _ => (),
+        }
+    }
+
+    fn advance(&mut self) {
+        let addressing = if self.controller == Controller::Sh1106 {
+            Addressing::Page
+        } else {
+            self.addressing
+        };
+
+        match addressing {
+            Addressing::Page => self.column = (self.column + 1) % self.controller.ram_width(),
+            Addressing::Horizontal => {
+                if self.column < self.columns.1 {
+                    self.column += 1;
+                } else {
+                    self.column = self.columns.0;
+                    self.page = if self.page < self.pages.1 {
+                        self.page + 1
+                    } else {
+                        self.pages.0
+                    };
+                }
+            }
+            Addressing::Vertical => {
+                if self.page < self.pages.1 {
+                    self.page += 1;
+                } else {
+                    self.page = self.pages.0;
+                    self.column = if self.column < self.columns.1 {
+                        self.column + 1
+                    } else {
+                        self.columns.0
+                    };
+                }
+            }
+        }
+    }
+
+    fn render(&mut self) {
+        let area = self.display.bounding_box();
+
+        let (width, height) = (area.size.width as usize, area.size.height as usize);
+        let ram_width = self.controller.ram_width();
+
+        // The panel is centered on the columns of the GDDRAM
+        let margin = ram_width.saturating_sub(width) / 2;
+
+        let mut colors = Vec::with_capacity(width * height);
+
+        for y in 0..height {
+            let com = if self.com_reversed {
+                y
+            } else {
+                self.multiplex.saturating_sub(1 + y)
+            };
+
+            let line = (com + self.start_line + self.offset + self.scrolled) % RAM_HEIGHT;
+
+            for x in 0..width {
+                let column = if self.segment_remap {
+                    margin + x
+                } else {
+                    ram_width.saturating_sub(1 + margin + x)
+                };
+
+                let lit = y < self.multiplex
+                    && self
+                        .gddram
+                        .get((line / 8) * ram_width + column)
+                        .is_some_and(|byte| byte & (1 << (line % 8)) != 0);
+
+                colors.push(self.color(lit));
+            }
+        }
+
+        self.display.fill_contiguous(&area, colors).unwrap();
+    }
+
+    fn color(&self, lit: bool) -> Rgb888 {
+        let lit = self.on && (self.entire_on || lit != self.inverted);
+
+        let color = if lit {
+            // Even at the lowest contrast, the pixels remain visible
+            let brightness = 64 + self.contrast as u32 * 3 / 4;
+
+            let [_, r, g, b] = self.palette.on.to_be_bytes();
+
+            [r, g, b].map(|channel| (channel as u32 * brightness / 255) as u8)
+        } else {
+            let [_, r, g, b] = self.palette.off.to_be_bytes();
+
+            [r, g, b]
+        };
+
+        Rgb888::new(color[0], color[1], color[2])
+    }
+}
+
+impl I2cDevice for Ssd1306 {
+    fn start(&mut self) {
+        self.control = None;
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), ErrorKind> {
+        let mut bytes = bytes;
+
+        while !bytes.is_empty() {
+            let control = match self.control.take() {
+                Some(control) => control,
+                None => {
+                    let control = bytes[0];
+                    bytes = &bytes[1..];
+
+                    control
+                }
+            };
+
+            if bytes.is_empty() {
+                self.control = Some(control);
+                break;
+            }
+
+            // With the continuation bit, the control byte only applies to the next byte;
+            // otherwise, all remaining bytes of the frame are data or commands
+            let len = if control & 0x80 != 0 {
+                1
+            } else {
+                self.control = Some(control);
+                bytes.len()
+            };
+
+            if control & 0x40 != 0 {
+                self.data(&bytes[..len]);
+            } else {
+                self.commands(&bytes[..len]);
+            }
+
+            bytes = &bytes[len..];
+        }
+
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<(), ErrorKind> {
+        // The status byte, with the display off flag
+        buffer.fill(if self.on { 0x00 } else { 0x40 });
+
+        Ok(())
+    }
+}
+
+impl SpiDeviceModel for Ssd1306 {
+    fn transfer(&mut self, _read: &mut [u8], write: &[u8]) {
+        if self.dc.as_ref().is_some_and(PinProbe::is_high) {
+            self.data(write);
+        } else {
+            self.commands(write);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::simulator::Simulator;
+    use crate::testbench::TestBench;
+
+    use super::*;
+
+    const WIDTH: usize = 128;
+    const HEIGHT: usize = 64;
+
+    /// The commands of the usual initialization sequence: upright panel, display on
+    const INIT: &[u8] = &[0xa1, 0xc8, 0xaf];
+
+    fn controller(controller: Controller) -> (TestBench, Ssd1306) {
+        let simulator = Simulator::new();
+        let mut peripherals = simulator.take().unwrap();
+
+        let display = peripherals
+            .displays
+            .display_for::<Rgb888>("Panel", WIDTH, HEIGHT);
+
+        let mut controller = Ssd1306::new(display, controller);
+
+        controller.commands(INIT);
+
+        (TestBench::new(&simulator), controller)
+    }
+
+    /// The coordinates of the lit pixels
+    fn lit(bench: &TestBench) -> Vec<(usize, usize)> {
+        let image = bench.display_image("Panel").unwrap();
+
+        (0..HEIGHT)
+            .flat_map(|y| (0..WIDTH).map(move |x| (x, y)))
+            .filter(|(x, y)| image.pixel(*x, *y) != BinaryPalette::WHITE.off)
+            .collect()
+    }
+
+    #[test]
+    fn page_addressing() {
+        let (bench, mut controller) = controller(Controller::Ssd1306);
+
+        // Page 2, column 0x15
+        controller.commands(&[0xb2, 0x05, 0x11]);
+        controller.data(&[0x81, 0x01]);
+
+        assert_eq!(lit(&bench), [(21, 16), (22, 16), (21, 23)]);
+    }
+
+    #[test]
+    fn horizontal_addressing() {
+        let (bench, mut controller) = controller(Controller::Ssd1306);
+
+        // Columns 10 to 11 of pages 1 to 2
+        controller.commands(&[0x20, 0x00, 0x21, 10, 11, 0x22, 1, 2]);
+        controller.data(&[0x01; 4]);
+
+        assert_eq!(lit(&bench), [(10, 8), (11, 8), (10, 16), (11, 16)]);
+
+        // The next byte wraps around to the start of the window
+        controller.data(&[0x03]);
+
+        assert_eq!(lit(&bench), [(10, 8), (11, 8), (10, 9), (10, 16), (11, 16)]);
+    }
+
+    #[test]
+    fn vertical_addressing() {
+        let (bench, mut controller) = controller(Controller::Ssd1306);
+
+        controller.commands(&[0x20, 0x01, 0x21, 0, 1, 0x22, 0, 1]);
+        controller.data(&[0x01, 0x01, 0x02]);
+
+        assert_eq!(lit(&bench), [(0, 0), (1, 1), (0, 8)]);
+    }
+
+    #[test]
+    fn sh1106_column_offset() {
+        let (bench, mut controller) = controller(Controller::Sh1106);
+
+        // The panel shows columns 2 to 129 of the GDDRAM
+        controller.commands(&[0xb0, 0x00, 0x10]);
+        controller.data(&[0x01, 0x00, 0x01]);
+
+        controller.commands(&[0xb0, 0x01, 0x18]);
+        controller.data(&[0x01, 0x01]);
+
+        assert_eq!(lit(&bench), [(0, 0), (127, 0)]);
+
+        // Horizontal addressing is not supported, so page addressing wraps within the page
+        controller.commands(&[0x20, 0x00, 0xb7, 0x01, 0x18]);
+        controller.data(&[0x80, 0x00, 0x00, 0x00, 0x00, 0x80]);
+
+        assert_eq!(lit(&bench), [(0, 0), (127, 0), (0, 63), (127, 63)]);
+    }
+
+    #[test]
+    fn i2c() {
+        let (bench, mut controller) = controller(Controller::Ssd1306);
+
+        controller.start();
+
+        // A single command with the continuation bit, then data until the end of the frame
+        controller.write(&[0x80, 0xb7, 0x40, 0x80, 0x80]).unwrap();
+
+        controller.start();
+
+        // Display off, as a stream of commands
+        controller.write(&[0x00, 0xa4, 0xae]).unwrap();
+
+        let mut status = [0];
+        controller.read(&mut status).unwrap();
+
+        assert_eq!(status, [0x40]);
+        assert_eq!(lit(&bench), []);
+
+        controller.start();
+        controller.write(&[0x00, 0xaf]).unwrap();
+
+        assert_eq!(lit(&bench), [(0, 63), (1, 63)]);
+    }
+}
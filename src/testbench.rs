@@ -0,0 +1,388 @@
+use core::fmt;
+use core::time::Duration;
+
+use std::time::Instant;
+
+use crate::display::SharedDisplay;
+use crate::dto::display::TouchPoint;
+use crate::dto::*;
+use crate::gpio::{quadrature_step, EncoderPin, PinType, PinValue};
+use crate::simulator::Simulator;
+
+#[derive(Debug)]
+pub struct TimeoutError;
+
+impl fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Timeout Error")
+    }
+}
+
+impl std::error::Error for TimeoutError {}
+
+/// A snapshot of the framebuffer of a display
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DisplayImage {
+    pub width: usize,
+    pub height: usize,
+    /// Pixels in row-major order, each one encoded as `0x00RRGGBB`
+    pub pixels: Vec<u32>,
+}
+
+impl DisplayImage {
+    pub fn pixel(&self, x: usize, y: usize) -> u32 {
+        self.pixels[y * self.width + x]
+    }
+
+    /// Returns the pixels as a row-major RGB888 byte buffer
+    pub fn to_rgb888(&self) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|pixel| {
+                let bytes = pixel.to_be_bytes();
+                [bytes[1], bytes[2], bytes[3]]
+            })
+            .collect()
+    }
+}
+
+impl From<&SharedDisplay> for DisplayImage {
+    fn from(display: &SharedDisplay) -> Self {
+        Self {
+            width: display.meta().width,
+            height: display.meta().height,
+            pixels: display.buffer().to_vec(),
+        }
+    }
+}
+
+/// Drives the inputs and inspects the outputs of a `Simulator` without a UI
+///
+/// Pins and displays are looked up by name. Methods operating on inputs panic if no pin with the given name exists.
+///
+/// The firmware is expected to run on a separate thread, so that the blocking `wait_*` methods can make progress.
+pub struct TestBench {
+    simulator: Simulator,
+}
+
+impl TestBench {
+    const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+    pub fn new(simulator: &Simulator) -> Self {
+        Self {
+            simulator: simulator.clone(),
+        }
+    }
+
+    pub fn simulator(&self) -> &Simulator {
+        &self.simulator
+    }
+
+    pub fn pin_id(&self, name: &str) -> Option<u8> {
+        let pins = self.simulator.pins().lock().unwrap();
+
+        pins.iter()
+            .position(|state| !state.pin().dropped() && state.pin().meta().name == name)
+            .map(|id| id as _)
+    }
+
+    /// Sets the level of a discrete input pin
+    pub fn set_input(&self, name: &str, high: bool) {
+        let id = self.existing_pin_id(name);
+
+        self.simulator
+            .apply(UpdateRequest::PinInputUpdate(PinInputUpdate::Discrete(
+                id, high,
+            )));
+    }
+
+    /// Presses a button, i.e. drives its input pin high
+    pub fn press(&self, name: &str) {
+        self.set_input(name, true);
+    }
+
+    /// Releases a button, i.e. drives its input pin low
+    pub fn release(&self, name: &str) {
+        self.set_input(name, false);
+    }
+
+    /// Presses and then immediately releases a button
+    pub fn click(&self, name: &str) {
+        self.press(name);
+        self.release(name);
+    }
+
+    pub fn set_adc(&self, name: &str, value: u16) {
+        let id = self.existing_pin_id(name);
+
+        self.simulator
+            .apply(UpdateRequest::PinInputUpdate(PinInputUpdate::Analog(
+                id, value,
+            )));
+    }
+
+    /// Turns the rotary encoder with the given name by `detents`, clockwise if positive
+    ///
+    /// The quadrature steps of every detent are applied one after the other, with a short pause after each one,
+    /// so that the firmware can observe them.
+    pub fn rotate(&self, name: &str, detents: i32) {
+        let a = self.existing_pin_id(&format!("{name} A"));
+        let b = self.existing_pin_id(&format!("{name} B"));
+
+        let steps_per_detent = {
+            let pins = self.simulator.pins().lock().unwrap();
+
+            match pins[a as usize].pin().meta().pin_type {
+                PinType::Encoder(EncoderPin::A { steps_per_detent }) => steps_per_detent,
+                _ => panic!("`{name}` is not a rotary encoder"),
+            }
+        };
+
+        for _ in 0..detents.unsigned_abs() * steps_per_detent as u32 {
+            let levels = {
+                let pins = self.simulator.pins().lock().unwrap();
+
+                [a, b].map(|id| match pins[id as usize].pin().value() {
+                    PinValue::Input(level) => *level,
+                    _ => false,
+                })
+            };
+
+            let (level_a, level_b) = quadrature_step(levels[0], levels[1], detents > 0);
+
+            let update = if level_a != levels[0] {
+                PinInputUpdate::Discrete(a, level_a)
+            } else {
+                PinInputUpdate::Discrete(b, level_b)
+            };
+
+            self.simulator.apply(UpdateRequest::PinInputUpdate(update));
+
+            std::thread::sleep(Self::POLL_INTERVAL);
+        }
+    }
+
+    /// Returns the level of an output pin, or `None` if there is no output pin with that name
+    pub fn output(&self, name: &str) -> Option<bool> {
+        let pins = self.simulator.pins().lock().unwrap();
+
+        pins.iter()
+            .filter(|state| !state.pin().dropped() && state.pin().meta().name == name)
+            .find_map(|state| match state.pin().value() {
+                PinValue::Output(output) | PinValue::InputOutput { output, .. } => Some(*output),
+                _ => None,
+            })
+    }
+
+    /// Waits until the output pin with the given name is driven to `high`
+    ///
+    /// The pin does not need to exist yet when the wait starts.
+    pub fn wait_for_output(
+        &self,
+        name: &str,
+        high: bool,
+        timeout: Duration,
+    ) -> Result<(), TimeoutError> {
+        self.wait_for(|| self.output(name) == Some(high), timeout)
+    }
+
+    /// Waits until `condition` returns `true`, polling it periodically
+    pub fn wait_for(
+        &self,
+        condition: impl Fn() -> bool,
+        timeout: Duration,
+    ) -> Result<(), TimeoutError> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            if condition() {
+                break Ok(());
+            }
+
+            if Instant::now() >= deadline {
+                break Err(TimeoutError);
+            }
+
+            std::thread::sleep(Self::POLL_INTERVAL);
+        }
+    }
+
+    /// Returns a snapshot of the framebuffer of the display with the given name
+    ///
+    /// The snapshot is in the physical orientation of the panel, i.e. without the rotation and mirroring of the
+    /// display; use `DisplayMeta::to_framebuffer` to find the pixels drawn by the firmware.
+    pub fn display_image(&self, name: &str) -> Option<DisplayImage> {
+        let displays = self.simulator.displays().lock().unwrap();
+
+        displays
+            .iter()
+            .map(|state| state.display())
+            .find(|display| !display.dropped() && display.meta().name == name)
+            .map(DisplayImage::from)
+    }
+
+    /// Touches the touch panel of the display with the given name at `points`, in its logical orientation
+    ///
+    /// The points replace the previous contacts, so consecutive calls move the contacts.
+    pub fn touch(&self, name: &str, points: &[(u16, u16)]) {
+        let id = self.existing_display_id(name);
+
+        self.simulator
+            .apply(UpdateRequest::TouchUpdate(TouchUpdate {
+                id,
+                points: points
+                    .iter()
+                    .zip(0..)
+                    .map(|((x, y), id)| TouchPoint { id, x: *x, y: *y })
+                    .take(TOUCH_MAX_POINTS)
+                    .collect(),
+            }));
+    }
+
+    /// Releases all contacts on the touch panel of the display with the given name
+    pub fn release_touch(&self, name: &str) {
+        self.touch(name, &[]);
+    }
+
+    fn existing_display_id(&self, name: &str) -> u8 {
+        let displays = self.simulator.displays().lock().unwrap();
+
+        displays
+            .iter()
+            .position(|state| !state.display().dropped() && state.display().meta().name == name)
+            .map(|id| id as _)
+            .unwrap_or_else(|| panic!("No display named `{name}`"))
+    }
+
+    fn existing_pin_id(&self, name: &str) -> u8 {
+        self.pin_id(name)
+            .unwrap_or_else(|| panic!("No pin named `{name}`"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::thread::{self, JoinHandle};
+
+    use embedded_graphics_core::pixelcolor::{Rgb888, RgbColor};
+    use embedded_graphics_core::prelude::{DrawTarget, Point};
+    use embedded_graphics_core::Pixel;
+    use embedded_hal::digital::{InputPin, OutputPin};
+    use embedded_hal02::adc::OneShot;
+
+    use crate::adc::Adc;
+    use crate::display::Rotation;
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Firmware which mirrors `Button` on `LED`, raises `Alarm` above 2V on `Pot`, reports the direction of
+    /// the `Knob` encoder on `Clockwise` and `Counterclockwise`, and marks the top left pixel of `Screen` in red
+    fn firmware(simulator: &Simulator) -> JoinHandle<()> {
+        let simulator = simulator.clone();
+
+        thread::spawn(move || {
+            let mut peripherals = simulator.take().unwrap();
+            let pins = &mut peripherals.pins;
+
+            let mut quit = pins.input("Quit", "Test", false);
+            let mut button = pins.input("Button", "Test", false);
+            let mut led = pins.output("LED", "Test", false);
+            let mut pot = pins.adc::<Adc<0>>("Pot", "Test", 0);
+            let mut alarm = pins.output("Alarm", "Test", false);
+            let mut clockwise = pins.output("Clockwise", "Test", false);
+            let mut counterclockwise = pins.output("Counterclockwise", "Test", false);
+
+            // Decode the encoder from its pin change callbacks, as interrupt-driven firmware does
+            let mut encoder = pins.rotary_encoder("Knob", "Test");
+            let position = Arc::new(Mutex::new((false, false, 0)));
+
+            let state = position.clone();
+            encoder.a.subscribe(move || {
+                let (a, b, steps) = &mut *state.lock().unwrap();
+
+                *a = !*a;
+                *steps += if *a != *b { 1 } else { -1 };
+            });
+
+            let state = position.clone();
+            encoder.b.subscribe(move || {
+                let (a, b, steps) = &mut *state.lock().unwrap();
+
+                *b = !*b;
+                *steps += if *a == *b { 1 } else { -1 };
+            });
+
+            let mut display = peripherals
+                .displays
+                .display_for::<Rgb888>("Screen", 4, 2)
+                .with_rotation(Rotation::Deg90);
+
+            display
+                .draw_iter([Pixel(Point::new(0, 0), Rgb888::RED)])
+                .unwrap();
+
+            while quit.is_low().unwrap() {
+                led.set_state(button.is_high().unwrap().into()).unwrap();
+                alarm
+                    .set_state((peripherals.adc0.read(&mut pot).unwrap() > 2000).into())
+                    .unwrap();
+
+                let steps = position.lock().unwrap().2;
+
+                clockwise.set_state((steps >= 4).into()).unwrap();
+                counterclockwise.set_state((steps <= -4).into()).unwrap();
+
+                thread::sleep(Duration::from_millis(1));
+            }
+        })
+    }
+
+    #[test]
+    fn firmware_on_thread() {
+        let simulator = Simulator::new();
+        let bench = TestBench::new(&simulator);
+
+        let firmware = firmware(&simulator);
+
+        bench.wait_for_output("LED", false, TIMEOUT).unwrap();
+        assert!(bench
+            .wait_for_output("LED", true, Duration::from_millis(50))
+            .is_err());
+
+        bench.press("Button");
+        bench.wait_for_output("LED", true, TIMEOUT).unwrap();
+
+        bench.release("Button");
+        bench.wait_for_output("LED", false, TIMEOUT).unwrap();
+
+        bench.set_adc("Pot", 2500);
+        bench.wait_for_output("Alarm", true, TIMEOUT).unwrap();
+
+        bench.set_adc("Pot", 1500);
+        bench.wait_for_output("Alarm", false, TIMEOUT).unwrap();
+
+        bench.rotate("Knob", 1);
+        bench.wait_for_output("Clockwise", true, TIMEOUT).unwrap();
+
+        bench.rotate("Knob", -2);
+        bench
+            .wait_for_output("Counterclockwise", true, TIMEOUT)
+            .unwrap();
+        assert_eq!(bench.output("Clockwise"), Some(false));
+
+        // The snapshot is in the physical orientation, where the logical top left pixel is the bottom left one
+        let image = bench.display_image("Screen").unwrap();
+
+        assert_eq!((image.width, image.height), (4, 2));
+        assert_eq!(image.pixel(0, 1), 0xff0000);
+        assert_eq!(image.pixels.iter().filter(|pixel| **pixel != 0).count(), 1);
+
+        assert!(bench.display_image("Missing").is_none());
+
+        bench.press("Quit");
+        firmware.join().unwrap();
+    }
+}
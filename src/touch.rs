@@ -0,0 +1,244 @@
+extern crate alloc;
+use alloc::sync::{Arc, Weak};
+
+use channel_bridge::notification::Notification;
+
+use crate::display::SharedDisplays;
+
+pub use crate::dto::display::TouchPoint;
+pub use crate::dto::TOUCH_MAX_POINTS;
+
+pub type TouchPoints = heapless::Vec<TouchPoint, TOUCH_MAX_POINTS>;
+
+/// The touch panel of a simulated display
+///
+/// Created with `Display::touch`. The UI reports the pointer over the display as contacts, and emulates a second
+/// finger with the modifier keys: a pinch mirrored around the center of the display while Shift is held, and a
+/// two-finger pan while Ctrl is held.
+///
+/// All points are in the logical orientation of the display, i.e. in the coordinates the firmware draws with.
+///
+/// Every `Touch` has its own notification, so several handles of the same panel can wait concurrently.
+pub struct Touch {
+    id: u8,
+    displays: SharedDisplays,
+    notification: Arc<Notification>,
+}
+
+impl Touch {
+    pub(crate) fn new(id: u8, displays: SharedDisplays) -> Self {
+        let notification = Arc::new(Notification::new());
+
+        {
+            let mut guard = displays.lock().unwrap();
+
+            guard[id as usize]
+                .touch_mut()
+                .subscribe(Arc::downgrade(&notification));
+        }
+
+        Self {
+            id,
+            displays,
+            notification,
+        }
+    }
+
+    /// The current contacts, or no contacts when the panel is not touched
+    pub fn points(&self) -> TouchPoints {
+        let guard = self.displays.lock().unwrap();
+
+        guard[self.id as usize].display().touch().points.clone()
+    }
+
+    /// The first contact, as reported by single-touch (e.g. resistive) panels
+    pub fn point(&self) -> Option<TouchPoint> {
+        self.points().first().copied()
+    }
+
+    pub fn is_touched(&self) -> bool {
+        self.point().is_some()
+    }
+
+    /// Waits until the panel is touched and returns the contacts
+    pub async fn wait_for_touch(&mut self) -> TouchPoints {
+        self.wait_for(|points| !points.is_empty()).await
+    }
+
+    /// Waits until all contacts are released
+    pub async fn wait_for_release(&mut self) {
+        self.wait_for(|points| points.is_empty()).await;
+    }
+
+    /// Waits until the contacts change, i.e. until the panel is touched or released, or a contact moves
+    pub async fn wait_for_change(&mut self) -> TouchPoints {
+        let notif = self.notification();
+
+        notif.wait().await;
+
+        self.points()
+    }
+
+    async fn wait_for(&mut self, condition: impl Fn(&[TouchPoint]) -> bool) -> TouchPoints {
+        loop {
+            let notif = self.notification();
+
+            let points = self.points();
+
+            if condition(&points) {
+                break points;
+            }
+
+            notif.wait().await;
+        }
+    }
+
+    fn notification(&self) -> &Notification {
+        self.notification.reset();
+
+        &self.notification
+    }
+}
+
+/// The state of the touch panel of a display
+pub struct SharedTouch {
+    points: TouchPoints,
+    /// The notifications of the `Touch` handles of the panel
+    notifications: Vec<Weak<Notification>>,
+}
+
+impl SharedTouch {
+    pub(crate) fn new() -> Self {
+        Self {
+            points: TouchPoints::new(),
+            notifications: Vec::new(),
+        }
+    }
+
+    pub fn points(&self) -> &[TouchPoint] {
+        &self.points
+    }
+
+    fn subscribe(&mut self, notification: Weak<Notification>) {
+        self.notifications
+            .retain(|notification| notification.strong_count() > 0);
+        self.notifications.push(notification);
+    }
+
+    pub(crate) fn update(&mut self, points: TouchPoints) {
+        self.points = points;
+
+        for notification in self.notifications.iter().filter_map(Weak::upgrade) {
+            notification.notify();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::future::Future;
+    use core::pin::pin;
+    use core::task::{Context, Poll};
+
+    use embedded_graphics_core::pixelcolor::Rgb888;
+
+    use crate::display::Display;
+    use crate::simulator::Simulator;
+    use crate::testbench::TestBench;
+
+    use super::*;
+
+    fn display() -> (TestBench, Display<Rgb888>) {
+        let simulator = Simulator::new();
+        let mut peripherals = simulator.take().unwrap();
+
+        let display = peripherals.displays.display_for::<Rgb888>("Display", 4, 3);
+
+        (TestBench::new(&simulator), display)
+    }
+
+    fn poll<F: Future>(future: core::pin::Pin<&mut F>) -> Poll<F::Output> {
+        future.poll(&mut Context::from_waker(futures::task::noop_waker_ref()))
+    }
+
+    fn positions(points: &[TouchPoint]) -> Vec<(u16, u16)> {
+        points.iter().map(|point| (point.x, point.y)).collect()
+    }
+
+    #[test]
+    fn polling() {
+        let (bench, display) = display();
+        let touch = display.touch();
+
+        assert!(!touch.is_touched());
+        assert_eq!(touch.point(), None);
+
+        // Contacts outside of the display are dropped
+        bench.touch("Display", &[(1, 2), (4, 0), (3, 1)]);
+
+        assert!(touch.is_touched());
+        assert_eq!(positions(&touch.points()), [(1, 2), (3, 1)]);
+        assert_eq!(touch.point().map(|point| (point.x, point.y)), Some((1, 2)));
+
+        bench.release_touch("Display");
+
+        assert!(!touch.is_touched());
+        assert!(touch.points().is_empty());
+    }
+
+    #[test]
+    fn wait() {
+        let (bench, display) = display();
+        let mut touch = display.touch();
+
+        {
+            let mut touched = pin!(touch.wait_for_touch());
+
+            assert!(poll(touched.as_mut()).is_pending());
+
+            bench.touch("Display", &[(2, 2)]);
+
+            assert!(matches!(
+                poll(touched.as_mut()),
+                Poll::Ready(points) if positions(&points) == [(2, 2)]
+            ));
+        }
+
+        // Moving a contact does not release the panel
+        {
+            let mut released = pin!(touch.wait_for_release());
+
+            assert!(poll(released.as_mut()).is_pending());
+
+            bench.touch("Display", &[(3, 2)]);
+            assert!(poll(released.as_mut()).is_pending());
+
+            bench.release_touch("Display");
+            assert!(poll(released.as_mut()).is_ready());
+        }
+
+        // Completes right away when the panel is already touched
+        bench.touch("Display", &[(0, 0)]);
+        assert!(poll(pin!(touch.wait_for_touch())).is_ready());
+    }
+
+    #[test]
+    fn concurrent_waiters() {
+        let (bench, display) = display();
+
+        let mut first = display.touch();
+        let mut second = display.touch();
+
+        let mut first_touched = pin!(first.wait_for_touch());
+        let mut second_touched = pin!(second.wait_for_touch());
+
+        assert!(poll(first_touched.as_mut()).is_pending());
+        assert!(poll(second_touched.as_mut()).is_pending());
+
+        bench.touch("Display", &[(1, 1)]);
+
+        // Neither waiter consumes the wakeup of the other one
+        assert!(poll(first_touched.as_mut()).is_ready());
+        assert!(poll(second_touched.as_mut()).is_ready());
+    }
+}
@@ -0,0 +1,468 @@
+use core::convert::Infallible;
+
+extern crate alloc;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+
+use channel_bridge::notification::Notification;
+
+use std::sync::{Condvar, Mutex};
+
+use embedded_io::{ErrorType, Read, ReadReady, Write, WriteReady};
+
+pub use crate::dto::uart::*;
+
+pub type SharedUarts = Arc<Mutex<Vec<UartState>>>;
+
+pub struct Uarts {
+    id_gen: u8,
+    uarts: SharedUarts,
+    changed: UartsChangedCallback,
+}
+
+impl Uarts {
+    pub(crate) fn new(uarts: SharedUarts, changed: impl Fn() + 'static) -> Self {
+        Self {
+            id_gen: 0,
+            uarts,
+            changed: Arc::new(changed),
+        }
+    }
+
+    pub fn uart(&mut self, name: impl TryInto<UartName>) -> Uart {
+        let id = self.id_gen;
+        self.id_gen += 1;
+
+        let state = UartState::new(name.try_into().map_err(|_| ()).unwrap());
+
+        {
+            let mut states = self.uarts.lock().unwrap();
+            states.push(state);
+        }
+
+        Uart::new(id, self.uarts.clone(), self.changed.clone())
+    }
+}
+
+pub type UartsChangedCallback = Arc<dyn Fn()>;
+
+pub struct Uart {
+    id: u8,
+    uarts: SharedUarts,
+    changed: UartsChangedCallback,
+}
+
+impl Uart {
+    fn new(id: u8, uarts: SharedUarts, changed: UartsChangedCallback) -> Self {
+        Self { id, uarts, changed }
+    }
+
+    /// Queues `data` for the clients of the simulator
+    ///
+    /// The output is kept until a client fetches it, up to `UART_MAX_PENDING_OUTPUT` bytes, so that firmware
+    /// logging without any UI attached does not grow the memory without bounds.
+    fn write(&mut self, data: &[u8]) {
+        if !data.is_empty() {
+            {
+                let mut guard = self.uarts.lock().unwrap();
+
+                guard[self.id as usize].change.append_output(data);
+            }
+
+            (self.changed)();
+        }
+    }
+
+    fn try_read(&mut self, buf: &mut [u8]) -> usize {
+        let mut guard = self.uarts.lock().unwrap();
+
+        guard[self.id as usize].uart.take_input(buf)
+    }
+
+    fn read_ready(&self) -> bool {
+        let guard = self.uarts.lock().unwrap();
+
+        !guard[self.id as usize].uart.input.is_empty()
+    }
+
+    fn read_blocking(&mut self, buf: &mut [u8]) -> usize {
+        if buf.is_empty() {
+            return 0;
+        }
+
+        let mut guard = self.uarts.lock().unwrap();
+
+        let received = guard[self.id as usize].uart.received.clone();
+
+        loop {
+            let len = guard[self.id as usize].uart.take_input(buf);
+
+            if len > 0 {
+                break len;
+            }
+
+            guard = received.wait(guard).unwrap();
+        }
+    }
+
+    async fn read_async(&mut self, buf: &mut [u8]) -> usize {
+        if buf.is_empty() {
+            return 0;
+        }
+
+        loop {
+            let notif = {
+                let guard = self.uarts.lock().unwrap();
+
+                let notif = guard[self.id as usize].uart.notification();
+
+                notif.reset();
+
+                notif
+            };
+
+            let len = self.try_read(buf);
+
+            if len > 0 {
+                break len;
+            }
+
+            notif.wait().await;
+        }
+    }
+}
+
+impl Drop for Uart {
+    fn drop(&mut self) {
+        {
+            let mut guard = self.uarts.lock().unwrap();
+            let state = &mut guard[self.id as usize];
+
+            state.uart.dropped = true;
+            state.change.dropped = true;
+        }
+
+        (self.changed)();
+    }
+}
+
+impl ErrorType for Uart {
+    type Error = Infallible;
+}
+
+impl Read for Uart {
+    /// Blocks until at least one byte is received
+    ///
+    /// Note that blocking reads wait for another thread to deliver the input and are therefore not usable
+    /// when the simulator runs single-threaded, as in the browser. Use the async `Read` trait there instead.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        Ok(self.read_blocking(buf))
+    }
+}
+
+impl ReadReady for Uart {
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(Uart::read_ready(self))
+    }
+}
+
+impl Write for Uart {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        Uart::write(self, buf);
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl WriteReady for Uart {
+    fn write_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+}
+
+impl embedded_io_async::Read for Uart {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        Ok(self.read_async(buf).await)
+    }
+}
+
+impl embedded_io_async::Write for Uart {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        Uart::write(self, buf);
+
+        Ok(buf.len())
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl embedded_hal02::serial::Read<u8> for Uart {
+    type Error = Infallible;
+
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        let mut buf = [0];
+
+        if self.try_read(&mut buf) > 0 {
+            Ok(buf[0])
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+impl embedded_hal02::serial::Write<u8> for Uart {
+    type Error = Infallible;
+
+    fn write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+        Uart::write(self, &[word]);
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl embedded_hal02::blocking::serial::write::Default<u8> for Uart {}
+
+pub struct UartState {
+    uart: SharedUart,
+    change: Change,
+}
+
+impl UartState {
+    fn new(name: UartName) -> Self {
+        Self {
+            uart: SharedUart::new(name),
+            change: Change {
+                created: true,
+                dropped: false,
+                output: Vec::new(),
+            },
+        }
+    }
+
+    pub fn change(&self) -> &Change {
+        &self.change
+    }
+
+    pub fn uart(&self) -> &SharedUart {
+        &self.uart
+    }
+
+    pub fn uart_mut(&mut self) -> &mut SharedUart {
+        &mut self.uart
+    }
+
+    pub fn split(&mut self) -> (&SharedUart, &mut Change) {
+        (&self.uart, &mut self.change)
+    }
+}
+
+pub struct SharedUart {
+    meta: UartMeta,
+    dropped: bool,
+    input: VecDeque<u8>,
+    notification: Arc<Notification>,
+    /// Signalled with the lock of the UARTs held whenever input is received, for blocking reads
+    received: Arc<Condvar>,
+}
+
+impl SharedUart {
+    fn new(name: UartName) -> Self {
+        Self {
+            meta: UartMeta { name },
+            dropped: false,
+            input: VecDeque::new(),
+            notification: Arc::new(Notification::new()),
+            received: Arc::new(Condvar::new()),
+        }
+    }
+
+    pub fn meta(&self) -> &UartMeta {
+        &self.meta
+    }
+
+    pub fn dropped(&self) -> bool {
+        self.dropped
+    }
+
+    pub fn notification(&self) -> Arc<Notification> {
+        self.notification.clone()
+    }
+
+    pub fn receive(&mut self, data: &[u8]) {
+        if !self.dropped && !data.is_empty() {
+            self.input.extend(data);
+
+            self.notification.notify();
+            self.received.notify_all();
+        }
+    }
+
+    fn take_input(&mut self, buf: &mut [u8]) -> usize {
+        let len = self.input.len().min(buf.len());
+
+        for (dst, src) in buf.iter_mut().zip(self.input.drain(..len)) {
+            *dst = src;
+        }
+
+        len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::future::Future;
+    use core::pin::pin;
+    use core::task::{Context, Poll};
+
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::simulator::{
+        Client, Simulator, UartInputUpdate, UpdateEvent, UpdateRequest, UART_MAX_CHUNK_LEN,
+        UART_MAX_PENDING_OUTPUT,
+    };
+
+    use super::*;
+
+    fn uart() -> (Simulator, Uart) {
+        let simulator = Simulator::new();
+        let mut peripherals = simulator.take().unwrap();
+
+        let uart = peripherals.uarts.uart("UART");
+
+        (simulator, uart)
+    }
+
+    fn receive(simulator: &Simulator, data: &[u8]) {
+        simulator.apply(UpdateRequest::UartInputUpdate(UartInputUpdate {
+            id: 0,
+            data: heapless::Vec::from_slice(data).unwrap(),
+        }));
+    }
+
+    fn poll<F: Future>(future: core::pin::Pin<&mut F>) -> Poll<F::Output> {
+        future.poll(&mut Context::from_waker(futures::task::noop_waker_ref()))
+    }
+
+    /// The data of the UART events fetched by `client`
+    fn fetch_output(client: &Client) -> Vec<Vec<u8>> {
+        core::iter::from_fn(|| client.fetch())
+            .filter_map(|event| match event {
+                UpdateEvent::UartUpdate(update) => Some(update.data.to_vec()),
+                _ => None,
+            })
+            .filter(|data| !data.is_empty())
+            .collect()
+    }
+
+    #[test]
+    fn read_ready() {
+        let (simulator, mut uart) = uart();
+
+        let mut buf = [0; 4];
+
+        assert!(!ReadReady::read_ready(&mut uart).unwrap());
+        assert!(matches!(
+            embedded_hal02::serial::Read::read(&mut uart),
+            Err(nb::Error::WouldBlock)
+        ));
+
+        receive(&simulator, b"abcdef");
+
+        assert!(ReadReady::read_ready(&mut uart).unwrap());
+        assert_eq!(Read::read(&mut uart, &mut buf).unwrap(), 4);
+        assert_eq!(&buf, b"abcd");
+        assert_eq!(embedded_hal02::serial::Read::read(&mut uart).unwrap(), b'e');
+        assert_eq!(Read::read(&mut uart, &mut buf).unwrap(), 1);
+        assert_eq!(buf[0], b'f');
+        assert!(!ReadReady::read_ready(&mut uart).unwrap());
+    }
+
+    #[test]
+    fn read_blocking() {
+        let (simulator, mut uart) = uart();
+
+        let sender = thread::spawn(move || {
+            // Give the reader the time to block, which does not matter for the outcome
+            thread::sleep(Duration::from_millis(10));
+
+            receive(&simulator, b"hi");
+        });
+
+        let mut buf = [0; 8];
+
+        assert_eq!(Read::read(&mut uart, &mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"hi");
+
+        sender.join().unwrap();
+    }
+
+    #[test]
+    fn read_async() {
+        let (simulator, mut uart) = uart();
+
+        let mut buf = [0; 8];
+
+        {
+            let mut read = pin!(embedded_io_async::Read::read(&mut uart, &mut buf));
+
+            assert!(poll(read.as_mut()).is_pending());
+
+            receive(&simulator, b"hi");
+
+            assert!(matches!(poll(read.as_mut()), Poll::Ready(Ok(2))));
+        }
+
+        assert_eq!(&buf[..2], b"hi");
+    }
+
+    #[test]
+    fn write_chunks() {
+        let (simulator, mut uart) = uart();
+
+        let client = simulator.client();
+
+        let data = (0..UART_MAX_CHUNK_LEN * 2 + 10)
+            .map(|byte| byte as u8)
+            .collect::<Vec<_>>();
+
+        Write::write_all(&mut uart, &data).unwrap();
+
+        let chunks = fetch_output(&client);
+
+        assert_eq!(
+            chunks.iter().map(Vec::len).collect::<Vec<_>>(),
+            [UART_MAX_CHUNK_LEN, UART_MAX_CHUNK_LEN, 10]
+        );
+        assert_eq!(chunks.concat(), data);
+    }
+
+    #[test]
+    fn output_without_clients() {
+        let (simulator, mut uart) = uart();
+
+        let chunk = [b'x'; 1024];
+
+        for _ in 0..UART_MAX_PENDING_OUTPUT / chunk.len() + 4 {
+            Write::write_all(&mut uart, &chunk).unwrap();
+        }
+
+        Write::write_all(&mut uart, b"end").unwrap();
+
+        // Only the newest output is kept until a client fetches it
+        let output = fetch_output(&simulator.client()).concat();
+
+        assert_eq!(output.len(), UART_MAX_PENDING_OUTPUT);
+        assert!(output.ends_with(b"xend"));
+    }
+}
@@ -2,6 +2,7 @@ use yew::prelude::*;
 
 pub use displays::*;
 pub use pins::*;
+pub use uarts::*;
 use yewdux_middleware::use_mcx;
 
 use self::fb::FrameBuffer;
@@ -10,6 +11,7 @@ mod displays;
 mod fb;
 pub mod middleware;
 mod pins;
+mod uarts;
 
 #[derive(Properties, Clone, PartialEq)]
 pub struct HalProps {
@@ -38,6 +40,7 @@ pub fn hal(props: &HalProps) -> Html {
             </div>
             <div class="column">
                 <Pins/>
+                <Uarts/>
             </div>
         </div>
     };
@@ -3,35 +3,47 @@ use core::fmt::Debug;
 extern crate alloc;
 use alloc::rc::Rc;
 
-use log::trace;
+use log::{trace, warn};
 
 use yew::prelude::*;
 use yewdux::use_store_value;
 use yewdux_middleware::*;
 
-use wasm_bindgen::JsCast;
-use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement};
+use gloo_timers::callback::Timeout;
+
+use wasm_bindgen::{Clamped, JsCast, JsValue};
+use web_sys::{
+    Blob, BlobPropertyBag, CanvasRenderingContext2d, Element, HtmlAnchorElement, HtmlCanvasElement,
+    ImageData, Url,
+};
 
 use crate::dto::display::*;
 use crate::dto::*;
 
 use super::fb::{FrameBuffer, FrameBufferStore};
 
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
-pub struct DisplayMsg(pub DisplayUpdate);
+pub enum DisplayMsg {
+    Update(DisplayUpdate),
+    TouchUpdate(TouchUpdate),
+}
 
 impl DisplayMsg {
     pub fn from_event(event: &UpdateEvent) -> Option<Self> {
         match event {
-            UpdateEvent::DisplayUpdate(update) => Some(Self(update.clone())),
+            UpdateEvent::DisplayUpdate(update) => Some(Self::Update(update.clone())),
             _ => None,
         }
     }
 }
 
 impl<'a> From<&'a DisplayMsg> for Option<UpdateRequest> {
-    fn from(_value: &'a DisplayMsg) -> Self {
-        None
+    fn from(value: &'a DisplayMsg) -> Self {
+        match value {
+            DisplayMsg::TouchUpdate(update) => Some(UpdateRequest::TouchUpdate(update.clone())),
+            _ => None,
+        }
     }
 }
 
@@ -40,20 +52,31 @@ impl Reducer<DisplaysStore> for DisplayMsg {
         let state = Rc::make_mut(&mut store);
         let vec = &mut state.0;
 
-        if let Self(DisplayUpdate::MetaUpdate { id, meta, dropped }) = self {
-            while vec.len() <= id as _ {
-                vec.push(DisplayState {
-                    meta: Rc::new(Default::default()),
-                    dropped: false,
-                });
-            }
+        match self {
+            Self::Update(DisplayUpdate::MetaUpdate { id, meta, dropped }) => {
+                while vec.len() <= id as _ {
+                    vec.push(DisplayState {
+                        meta: Rc::new(Default::default()),
+                        dropped: false,
+                        refresh: None,
+                        refreshes: 0,
+                    });
+                }
 
-            let display: &mut DisplayState = &mut vec[id as usize];
-            if let Some(meta) = meta {
-                display.meta = Rc::new(meta.clone());
-            }
+                let display: &mut DisplayState = &mut vec[id as usize];
+                if let Some(meta) = meta {
+                    display.meta = Rc::new(meta.clone());
+                }
 
-            display.dropped = dropped;
+                display.dropped = dropped;
+            }
+            Self::Update(DisplayUpdate::RefreshUpdate { id, refresh }) => {
+                if let Some(display) = vec.get_mut(id as usize) {
+                    display.refresh = Some(refresh);
+                    display.refreshes = display.refreshes.wrapping_add(1);
+                }
+            }
+            _ => (),
         }
 
         store
@@ -67,6 +90,10 @@ pub struct DisplaysStore(Vec<DisplayState>);
 pub struct DisplayState {
     pub meta: Rc<DisplayMeta>,
     pub dropped: bool,
+    /// The last refresh of an e-paper display
+    pub refresh: Option<Refresh>,
+    /// The number of refreshes so far, so that each one is animated
+    pub refreshes: u32,
 }
 
 #[function_component(Displays)]
@@ -95,34 +122,172 @@ pub fn display(props: &DisplayProps) -> Html {
     let displays = use_store_value::<DisplaysStore>();
     let display = &displays.0[props.id as usize];
 
+    let zoom = use_state(|| display.meta.zoom.max(1));
+
+    {
+        let zoom = zoom.clone();
+
+        // Follow the zoom of the firmware when its meta changes, e.g. with `with_zoom` or after a reconnect
+        use_effect_with(display.meta.zoom, move |initial| {
+            let initial = (*initial).max(1);
+
+            if *zoom != initial {
+                zoom.set(initial);
+            }
+
+            move || ()
+        });
+    }
+
+    let zoom_out = {
+        let zoom = zoom.clone();
+        Callback::from(move |_| zoom.set((*zoom - 1).max(1)))
+    };
+
+    let zoom_in = {
+        let zoom = zoom.clone();
+        Callback::from(move |_| zoom.set((*zoom + 1).min(MAX_ZOOM)))
+    };
+
+    let canvas_ref = use_node_ref();
+    let recording = use_state(|| FrameBuffer::is_recording(props.id));
+
+    let screenshot = {
+        let canvas_ref = canvas_ref.clone();
+        let meta = display.meta.clone();
+        let name = display.meta.name.clone();
+
+        Callback::from(move |_| {
+            if let Some(canvas) = canvas_ref.cast::<HtmlCanvasElement>() {
+                match screenshot_url(&canvas, &meta) {
+                    Ok(url) => download(&url, &format!("{name}.png")),
+                    Err(err) => warn!("Cannot take a screenshot of display {name}: {err:?}"),
+                }
+            }
+        })
+    };
+
+    let record = {
+        let id = props.id;
+        let recording = recording.clone();
+        let meta = display.meta.clone();
+        let name = display.meta.name.clone();
+
+        Callback::from(move |_| {
+            if *recording {
+                if let Some(gif) = FrameBuffer::stop_recording(id, &meta) {
+                    download_data(&gif, "image/gif", &format!("{name}.gif"));
+                }
+
+                recording.set(false);
+            } else {
+                FrameBuffer::start_recording(id);
+                recording.set(true);
+            }
+        })
+    };
+
     html! {
         <article class="panel is-primary is-size-7">
-            <p class="panel-heading">{ display.meta.name.clone() }{" "}{ display.meta.width }{"x"}{ display.meta.height }</p>
+            <p class="panel-heading">
+                { display.meta.name.clone() }{" "}{ display.meta.width }{"x"}{ display.meta.height }
+                <span class="is-pulled-right">
+                    <button class="button is-small" onclick={zoom_out} disabled={*zoom <= 1}>{"-"}</button>
+                    {" "}{ *zoom }{"x "}
+                    <button class="button is-small" onclick={zoom_in} disabled={*zoom >= MAX_ZOOM}>{"+"}</button>
+                    {" "}
+                    <button class="button is-small" onclick={screenshot}>{"PNG"}</button>
+                    {" "}
+                    <button class={classes!("button", "is-small", recording.then_some("is-danger"))} onclick={record}>
+                        { if *recording { "Stop" } else { "Record" } }
+                    </button>
+                </span>
+            </p>
             <div class="panel-block">
                 <DisplayCanvas
                     id={props.id}
+                    canvas_ref={canvas_ref}
                     width={display.meta.width}
                     height={display.meta.height}
+                    epaper={display.meta.epaper}
+                    refresh={display.refresh}
+                    refreshes={display.refreshes}
+                    rotation={display.meta.rotation}
+                    mirrored={display.meta.mirrored}
+                    zoom={*zoom}
+                    lcd={display.meta.lcd}
                 />
             </div>
         </article>
     }
 }
 
+const MAX_ZOOM: u8 = 16;
+
 #[derive(Properties, Clone, PartialEq)]
 pub struct DisplayCanvasProps {
     pub id: u8,
+    /// Refers to the canvas of the display, e.g. to take screenshots from it
+    pub canvas_ref: NodeRef,
     pub width: usize,
     pub height: usize,
+    #[prop_or_default]
+    pub epaper: Option<EpaperLatency>,
+    #[prop_or_default]
+    pub refresh: Option<Refresh>,
+    #[prop_or_default]
+    pub refreshes: u32,
+    #[prop_or_default]
+    pub rotation: Rotation,
+    #[prop_or_default]
+    pub mirrored: bool,
+    #[prop_or(1)]
+    pub zoom: u8,
+    #[prop_or_default]
+    pub lcd: Option<LcdEffect>,
 }
 
 #[function_component(DisplayCanvas)]
 pub fn display_canvas(props: &DisplayCanvasProps) -> Html {
     let _fbs = use_store_value::<FrameBufferStore>(); // To receive change notifications
 
-    let node_ref = use_node_ref();
+    let node_ref = props.canvas_ref.clone();
     let ctx_ref = use_mut_ref(|| None);
 
+    // The e-paper refresh in progress, during which the canvas is not updated
+    let refreshing = use_mut_ref(|| None::<Refresh>);
+    let rerender = use_force_update();
+
+    {
+        let refreshing = refreshing.clone();
+
+        let epaper = props.epaper;
+        let refresh = props.refresh;
+
+        use_effect_with(props.refreshes, move |_| {
+            let timeout = epaper.zip(refresh).map(|(latency, refresh)| {
+                let ms = match refresh {
+                    Refresh::Full => latency.full_ms,
+                    Refresh::Partial => latency.partial_ms,
+                };
+
+                *refreshing.borrow_mut() = Some(refresh);
+                rerender.force_update();
+
+                Timeout::new(ms, move || {
+                    *refreshing.borrow_mut() = None;
+                    rerender.force_update();
+                })
+            });
+
+            move || {
+                if let Some(timeout) = timeout {
+                    timeout.cancel();
+                }
+            }
+        });
+    }
+
     {
         let node_ref = node_ref.clone();
         let ctx_ref = ctx_ref.clone();
@@ -154,9 +319,12 @@ pub fn display_canvas(props: &DisplayCanvasProps) -> Html {
 
     {
         let id = props.id;
+        let refreshing = refreshing.clone();
 
         use_effect(move || {
-            if let Some(mcx) = ctx_ref.borrow().as_ref() {
+            if refreshing.borrow().is_some() {
+                trace!("[FB DRAW] SCREEN BLIT DEFERRED UNTIL THE REFRESH IS OVER");
+            } else if let Some(mcx) = ctx_ref.borrow().as_ref() {
                 trace!("[FB DRAW] SCREEN BLIT START");
 
                 FrameBuffer::blit(id, false, |image_data, x, y| {
@@ -176,8 +344,298 @@ pub fn display_canvas(props: &DisplayCanvasProps) -> Html {
         });
     }
 
+    let zoom = props.zoom.max(1) as usize;
+
+    let (width, height) = if props.rotation.swaps_axes() {
+        (props.height, props.width)
+    } else {
+        (props.width, props.height)
+    };
+
+    // The contacts on the touch panel, shown over the display while it is touched
+    let touch_ref = use_node_ref();
+    let touch_points = use_state(heapless::Vec::<TouchPoint, TOUCH_MAX_POINTS>::new);
+
+    let touch = {
+        let mcx = use_mcx();
+        let touch_ref = touch_ref.clone();
+        let touch_points = touch_points.clone();
+
+        let id = props.id;
+
+        Rc::new(move |event: Option<&PointerEvent>| {
+            let points = event
+                .zip(touch_ref.cast::<Element>())
+                .map(|(event, element)| to_touch_points(event, &element, width, height, zoom))
+                .unwrap_or_default();
+
+            touch_points.set(points.clone());
+
+            mcx.invoke(DisplayMsg::TouchUpdate(TouchUpdate { id, points }));
+        })
+    };
+
+    let onpointerdown = {
+        let touch = touch.clone();
+        let touch_ref = touch_ref.clone();
+
+        Callback::from(move |event: PointerEvent| {
+            if event.button() == 0 {
+                event.prevent_default();
+
+                // Keep receiving the moves of the pointer when it leaves the display
+                if let Some(element) = touch_ref.cast::<Element>() {
+                    let _ = element.set_pointer_capture(event.pointer_id());
+                }
+
+                touch(Some(&event));
+            }
+        })
+    };
+
+    let onpointermove = {
+        let touch = touch.clone();
+        let touched = !touch_points.is_empty();
+
+        Callback::from(move |event: PointerEvent| {
+            if touched {
+                touch(Some(&event));
+            }
+        })
+    };
+
+    let onpointerup = {
+        let touch = touch.clone();
+        let touched = !touch_points.is_empty();
+
+        Callback::from(move |_| {
+            if touched {
+                touch(None);
+            }
+        })
+    };
+
+    // The canvas keeps the size and the orientation of the framebuffer, and is scaled (with nearest-neighbour
+    // interpolation), rotated and mirrored by CSS, inside a box of the size of the logical orientation
+    let panel_style = format!(
+        "position: relative; flex-shrink: 0; width: {}px; height: {}px; transform: {}rotate({}deg);",
+        props.width * zoom,
+        props.height * zoom,
+        if props.mirrored { "scaleX(-1) " } else { "" },
+        props.rotation.degrees()
+    );
+
+    let filter_id = format!("hal-sim-lcd-{}", props.id);
+
+    let mut style =
+        String::from("display: block; width: 100%; height: 100%; image-rendering: pixelated;");
+
+    // The backlight and the gamma of LCD panels are applied by an SVG filter
+    if props.lcd.is_some() {
+        style.push_str(&format!(" filter: url(#{filter_id});"));
+    }
+
+    // Full e-paper refreshes flash the display a few times, by inverting its colors
+    if let (Some(Refresh::Full), Some(latency)) = (*refreshing.borrow(), props.epaper) {
+        style.push_str(&format!(
+            " animation: hal-sim-epaper-flash {}ms steps(1) {};",
+            latency.full_ms / EPAPER_FLASHES,
+            EPAPER_FLASHES
+        ));
+    }
+
+    // The pixel grid of LCD panels is drawn over the canvas, as the dead space at the top and left of every pixel
+    let grid_style = props.lcd.and_then(|lcd| {
+        let dead_space = lcd.dead_space(zoom);
+
+        (dead_space > 0).then(|| {
+            let color = format!("#{:06x}", lcd.grid_color & 0xffffff);
+
+            format!(
+                "position: absolute; inset: 0; pointer-events: none; background-size: {zoom}px {zoom}px; \
+                 background-image: linear-gradient(to right, {color} {dead_space}px, transparent {dead_space}px), \
+                 linear-gradient(to bottom, {color} {dead_space}px, transparent {dead_space}px);"
+            )
+        })
+    });
+
     html! {
-        <canvas ref={node_ref} width={props.width.to_string()} height={props.height.to_string()}/>
+        <>
+            if props.epaper.is_some() {
+                <style>{ "@keyframes hal-sim-epaper-flash { 0% { filter: invert(1); } 50% { filter: none; } }" }</style>
+            }
+            if let Some(lcd) = props.lcd {
+                <svg width="0" height="0" style="position: absolute;">
+                    // SVG tags are case-sensitive, and literal tags are lowercased by `html!`.
+                    // The levels are those of the framebuffer, as with `LcdEffect::level`, rather than linearized ones
+                    <filter id={filter_id} color-interpolation-filters="sRGB">
+                        <@{"feComponentTransfer"}>
+                            <@{"feFuncR"} type="gamma" amplitude={lcd.brightness().to_string()} exponent={lcd.gamma_exponent().to_string()} offset="0"/>
+                            <@{"feFuncG"} type="gamma" amplitude={lcd.brightness().to_string()} exponent={lcd.gamma_exponent().to_string()} offset="0"/>
+                            <@{"feFuncB"} type="gamma" amplitude={lcd.brightness().to_string()} exponent={lcd.gamma_exponent().to_string()} offset="0"/>
+                        </@>
+                    </filter>
+                </svg>
+            }
+            <div
+                ref={touch_ref}
+                style={format!("position: relative; display: flex; align-items: center; justify-content: center; width: {}px; height: {}px; touch-action: none;", width * zoom, height * zoom)}
+                {onpointerdown}
+                {onpointermove}
+                onpointerup={onpointerup.clone()}
+                onpointercancel={onpointerup}
+            >
+                <div style={panel_style}>
+                    <canvas ref={node_ref} width={props.width.to_string()} height={props.height.to_string()} {style}/>
+                    if let Some(grid_style) = grid_style {
+                        <div style={grid_style}/>
+                    }
+                </div>
+                {
+                    for touch_points.iter().map(|point| html! {
+                        <div style={format!(
+                            "position: absolute; left: {}px; top: {}px; width: {TOUCH_MARKER_SIZE}px; height: {TOUCH_MARKER_SIZE}px; \
+                             margin: -{}px; border-radius: 50%; background: rgba(255, 255, 255, 0.4); pointer-events: none;",
+                            point.x as usize * zoom + zoom / 2,
+                            point.y as usize * zoom + zoom / 2,
+                            TOUCH_MARKER_SIZE / 2
+                        )}/>
+                    })
+                }
+            </div>
+        </>
+    }
+}
+
+const EPAPER_FLASHES: u32 = 3;
+
+const TOUCH_MARKER_SIZE: usize = 16;
+
+/// The distance between the two fingers of an emulated two-finger pan, in display pixels
+const PAN_FINGERS_DISTANCE: u16 = 20;
+
+/// Maps the pointer to contacts on the touch panel of a display of the logical size `width` x `height`, shown
+/// zoomed by `zoom` in `element`
+///
+/// A second finger is emulated with the modifier keys: mirrored around the center of the display while Shift is
+/// held (i.e. a pinch), and next to the first one while Ctrl is held (i.e. a two-finger pan).
+fn to_touch_points(
+    event: &PointerEvent,
+    element: &Element,
+    width: usize,
+    height: usize,
+    zoom: usize,
+) -> heapless::Vec<TouchPoint, TOUCH_MAX_POINTS> {
+    let rect = element.get_bounding_client_rect();
+
+    let coord = |client: i32, start: f64, size: usize| {
+        ((client as f64 - start) / zoom as f64)
+            .floor()
+            .clamp(0.0, size.saturating_sub(1) as f64) as u16
+    };
+
+    let x = coord(event.client_x(), rect.left(), width);
+    let y = coord(event.client_y(), rect.top(), height);
+
+    let (max_x, max_y) = (
+        width.saturating_sub(1) as u16,
+        height.saturating_sub(1) as u16,
+    );
+
+    let mut points = heapless::Vec::new();
+
+    let _ = points.push(TouchPoint { id: 0, x, y });
+
+    if event.shift_key() {
+        let _ = points.push(TouchPoint {
+            id: 1,
+            x: max_x - x,
+            y: max_y - y,
+        });
+    } else if event.ctrl_key() {
+        let _ = points.push(TouchPoint {
+            id: 1,
+            x: (x + PAN_FINGERS_DISTANCE).min(max_x),
+            y,
+        });
+    }
+
+    points
+}
+
+/// Returns the content of the canvas of a display as a PNG data URL, in the orientation the display is shown in
+///
+/// Like the recordings, screenshots have one pixel per pixel of the display, i.e. they are neither zoomed nor
+/// rendered with the LCD effect.
+fn screenshot_url(canvas: &HtmlCanvasElement, meta: &DisplayMeta) -> Result<String, JsValue> {
+    if !meta.is_transformed() {
+        return canvas.to_data_url();
+    }
+
+    let context_2d = |canvas: &HtmlCanvasElement| {
+        canvas
+            .get_context("2d")?
+            .ok_or(JsValue::NULL)?
+            .dyn_into::<CanvasRenderingContext2d>()
+            .map_err(JsValue::from)
+    };
+
+    let image = context_2d(canvas)?.get_image_data(0.0, 0.0, meta.width as _, meta.height as _)?;
+    let pixels = FrameBuffer::orient(meta, &image.data());
+
+    let (width, height) = meta.logical_size();
+
+    let oriented = web_sys::window()
+        .and_then(|window| window.document())
+        .ok_or(JsValue::NULL)?
+        .create_element("canvas")?
+        .dyn_into::<HtmlCanvasElement>()?;
+
+    oriented.set_width(width as _);
+    oriented.set_height(height as _);
+
+    let image =
+        ImageData::new_with_u8_clamped_array_and_sh(Clamped(&pixels), width as _, height as _)?;
+    context_2d(&oriented)?.put_image_data(&image, 0.0, 0.0)?;
+
+    oriented.to_data_url()
+}
+
+/// Makes the browser download `data` as a file named `file_name`
+fn download_data(data: &[u8], mime_type: &str, file_name: &str) {
+    let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(data));
+
+    let options = BlobPropertyBag::new();
+    options.set_type(mime_type);
+
+    let url = Blob::new_with_u8_array_sequence_and_options(&parts, &options)
+        .and_then(|blob| Url::create_object_url_with_blob(&blob));
+
+    match url {
+        Ok(url) => {
+            download(&url, file_name);
+
+            // Revoking the URL right away might abort the download in some browsers
+            Timeout::new(1000, move || {
+                let _ = Url::revoke_object_url(&url);
+            })
+            .forget();
+        }
+        Err(err) => warn!("Cannot download {file_name}: {err:?}"),
+    }
+}
+
+/// Makes the browser download the resource at `url` as a file named `file_name`
+fn download(url: &str, file_name: &str) {
+    let anchor = web_sys::window()
+        .and_then(|window| window.document())
+        .and_then(|document| document.create_element("a").ok())
+        .and_then(|element| element.dyn_into::<HtmlAnchorElement>().ok());
+
+    if let Some(anchor) = anchor {
+        anchor.set_href(url);
+        anchor.set_download(file_name);
+        anchor.click();
     }
 }
 
@@ -1,6 +1,6 @@
 use core::cell::RefCell;
 
-use log::trace;
+use log::{trace, warn};
 
 use wasm_bindgen::Clamped;
 use web_sys::ImageData;
@@ -10,7 +10,7 @@ use gloo_timers::callback::Timeout;
 use yewdux::Context;
 use yewdux_middleware::Store;
 
-use crate::dto::display::*;
+use crate::dto::display::DisplayMeta;
 use crate::dto::*;
 
 use super::displays::DisplayMsg;
@@ -18,38 +18,52 @@ use super::displays::DisplayMsg;
 #[derive(Debug, Default, PartialEq, Eq, Clone, Store)]
 pub struct FrameBufferStore(u32);
 
+/// A dirty rectangle of the frame buffer, as `(x, y, width, height)`
+type Rect = (usize, usize, usize, usize);
+
 pub struct FrameBuffer {
     width: usize,
     height: usize,
-    change: Change,
+    dirty: Vec<Rect>,
     screen_fb: Vec<u8>,
+    recording: Option<Recording>,
+}
+
+/// The frames captured since the recording of a frame buffer was started
+struct Recording {
+    /// The screen frame buffers, with the time (in milliseconds) when they were blitted
+    frames: Vec<(f64, Vec<u8>)>,
+    size: usize,
 }
 
 impl FrameBuffer {
     const PIXEL_SIZE: usize = 4;
 
+    // Beyond that many dirty rectangles, blitting their bounding box is cheaper
+    const MAX_DIRTY_RECTS: usize = 64;
+
+    // Frames blitted after a recording has grown that large are dropped
+    const MAX_RECORDING_SIZE: usize = 64 * 1024 * 1024;
+
+    // The GIF frame quantization speed, from 1 (best quality) to 30 (fastest)
+    const GIF_SPEED: i32 = 10;
+
     fn new(width: usize, height: usize) -> Self {
         let mut screen = Vec::new();
         screen.reserve_exact(width * height * Self::PIXEL_SIZE);
 
-        let mut screen_updates = Vec::new();
-        screen_updates.reserve_exact(height);
-
         Self {
             width,
             height,
-            change: Change {
-                created: false,
-                dropped: false,
-                screen_updates,
-            },
+            dirty: Vec::new(),
             screen_fb: screen,
+            recording: None,
         }
     }
 
     pub fn update(mcx: &Context, msg: &DisplayMsg) {
         match msg {
-            DisplayMsg(DisplayUpdate::MetaUpdate { id, meta, .. }) => {
+            DisplayMsg::Update(DisplayUpdate::MetaUpdate { id, meta, .. }) => {
                 if let Some(meta) = meta.as_ref() {
                     FBS.with(|fbs| {
                         let mut fbs = fbs.borrow_mut();
@@ -60,13 +74,22 @@ impl FrameBuffer {
                     });
                 }
             }
-            DisplayMsg(DisplayUpdate::StripeUpdate(update)) => {
+            DisplayMsg::Update(DisplayUpdate::RefreshUpdate { .. }) => (),
+            DisplayMsg::Update(DisplayUpdate::StripeUpdate(update)) => {
                 FBS.with(|fbs| {
                     let mut fbs = fbs.borrow_mut();
 
                     fbs[update.id as usize].update_changes(update);
                 });
             }
+            DisplayMsg::Update(DisplayUpdate::RectUpdate(update)) => {
+                FBS.with(|fbs| {
+                    let mut fbs = fbs.borrow_mut();
+
+                    fbs[update.id as usize].update_rect(update);
+                });
+            }
+            DisplayMsg::TouchUpdate(_) => return,
         }
 
         // Use a timeout to accuulate bursts of icoming screen updates
@@ -82,6 +105,11 @@ impl FrameBuffer {
         })
     }
 
+    /// Drops the frame buffers of all displays
+    pub fn reset() {
+        FBS.with(|fbs| fbs.borrow_mut().clear());
+    }
+
     pub fn blit<F>(id: u8, full: bool, f: F)
     where
         F: FnMut(&ImageData, usize, usize),
@@ -93,14 +121,55 @@ impl FrameBuffer {
         });
     }
 
+    /// Starts recording the frames blitted from the frame buffer of display `id`
+    ///
+    /// The current content of the frame buffer is the first frame of the recording.
+    pub fn start_recording(id: u8) {
+        FBS.with(|fbs| {
+            if let Some(fb) = fbs.borrow_mut().get_mut(id as usize) {
+                fb.extend_screen_fb(fb.width * fb.height * Self::PIXEL_SIZE);
+
+                fb.recording = Some(Recording {
+                    frames: Vec::new(),
+                    size: 0,
+                });
+
+                fb.capture();
+            }
+        });
+    }
+
+    /// Stops the recording of the frame buffer of display `id` and returns it as an animated GIF
+    ///
+    /// The frames are turned into the orientation the display is shown in, as described by `meta`.
+    pub fn stop_recording(id: u8, meta: &DisplayMeta) -> Option<Vec<u8>> {
+        FBS.with(|fbs| {
+            let fb = &mut fbs.borrow_mut()[id as usize];
+            let recording = fb.recording.take()?;
+
+            match Self::encode_gif(meta, recording, js_sys::Date::now()) {
+                Ok(gif) => Some(gif),
+                Err(err) => {
+                    warn!("Encoding the recording of display {id} failed: {err}");
+                    None
+                }
+            }
+        })
+    }
+
+    pub fn is_recording(id: u8) -> bool {
+        FBS.with(|fbs| {
+            fbs.borrow()
+                .get(id as usize)
+                .map(|fb| fb.recording.is_some())
+                .unwrap_or(false)
+        })
+    }
+
     fn update_changes(&mut self, update: &StripeUpdate) {
         let pixel_len = update.data.len() / STRIPE_PIXEL_SIZE;
 
-        self.change.update_row(
-            update.row as _,
-            update.start as _,
-            update.start as usize + pixel_len,
-        );
+        self.mark_dirty((update.start as _, update.row as _, pixel_len, 1));
 
         let mut offset =
             (self.width * update.row as usize + update.start as usize) * Self::PIXEL_SIZE;
@@ -119,17 +188,64 @@ impl FrameBuffer {
         }
     }
 
+    fn update_rect(&mut self, update: &RectUpdate) {
+        self.mark_dirty((
+            update.x as _,
+            update.y as _,
+            update.width as _,
+            update.height as _,
+        ));
+
+        self.extend_screen_fb(self.width * self.height * Self::PIXEL_SIZE);
+
+        for (x, y, pixel) in update.pixels() {
+            let offset = (self.width * y + x) * Self::PIXEL_SIZE;
+
+            if let Some(dst) = self.screen_fb.get_mut(offset..offset + Self::PIXEL_SIZE) {
+                dst[..STRIPE_PIXEL_SIZE].copy_from_slice(pixel);
+                dst[STRIPE_PIXEL_SIZE] = 255; // Transparency
+            }
+        }
+    }
+
+    fn mark_dirty(&mut self, rect: Rect) {
+        let (x, y, width, height) = rect;
+
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        // Merge with the previous rectangle if this one continues it downwards, as is the case with stripes
+        if let Some(last) = self.dirty.last_mut() {
+            if last.0 == x && last.2 == width && last.1 + last.3 == y {
+                last.3 += height;
+                return;
+            }
+        }
+
+        self.dirty.push(rect);
+
+        if self.dirty.len() > Self::MAX_DIRTY_RECTS {
+            let x0 = self.dirty.iter().map(|r| r.0).min().unwrap();
+            let y0 = self.dirty.iter().map(|r| r.1).min().unwrap();
+            let x1 = self.dirty.iter().map(|r| r.0 + r.2).max().unwrap();
+            let y1 = self.dirty.iter().map(|r| r.1 + r.3).max().unwrap();
+
+            self.dirty.clear();
+            self.dirty.push((x0, y0, x1 - x0, y1 - y0));
+        }
+    }
+
     fn blit_fb<F>(&mut self, full: bool, mut f: F)
     where
         F: FnMut(&ImageData, usize, usize),
     {
-        if full {
-            self.extend_screen_fb(self.width * self.height * Self::PIXEL_SIZE);
+        self.extend_screen_fb(self.width * self.height * Self::PIXEL_SIZE);
 
-            for change in self.change.screen_updates.iter_mut() {
-                change.0 = 0;
-                change.1 = 0;
-            }
+        let changed = full || !self.dirty.is_empty();
+
+        if full {
+            self.dirty.clear();
 
             trace!("FB FULL BLIT");
 
@@ -142,33 +258,122 @@ impl FrameBuffer {
 
             f(&image_data, 0, 0);
         } else {
-            for (row, change) in self.change.screen_updates.iter_mut().enumerate() {
-                if change.0 < change.1 {
-                    let offset_start = (self.width * row + change.0) * Self::PIXEL_SIZE;
-                    let offset_end = offset_start + (change.1 - change.0) * Self::PIXEL_SIZE;
-
-                    trace!(
-                        "FB PARTIAL BLIT: x={}, y={}, w={} h={}",
-                        change.0,
-                        row,
-                        change.1 - change.0,
-                        1
-                    );
+            let mut rect_fb = Vec::new();
 
-                    let image_data = ImageData::new_with_u8_clamped_array_and_sh(
-                        Clamped(&self.screen_fb[offset_start..offset_end]),
-                        (change.1 - change.0) as _,
-                        1,
-                    )
-                    .unwrap();
+            for (x, y, width, height) in self.dirty.drain(..) {
+                let width = width.min(self.width.saturating_sub(x));
+                let height = height.min(self.height.saturating_sub(y));
 
-                    f(&image_data, change.0 as _, row as _);
+                if width == 0 || height == 0 {
+                    continue;
+                }
 
-                    change.0 = 0;
-                    change.1 = 0;
+                trace!(
+                    "FB PARTIAL BLIT: x={}, y={}, w={} h={}",
+                    x,
+                    y,
+                    width,
+                    height
+                );
+
+                rect_fb.clear();
+
+                for row in y..y + height {
+                    let offset = (self.width * row + x) * Self::PIXEL_SIZE;
+
+                    rect_fb.extend_from_slice(
+                        &self.screen_fb[offset..offset + width * Self::PIXEL_SIZE],
+                    );
                 }
+
+                let image_data = ImageData::new_with_u8_clamped_array_and_sh(
+                    Clamped(&rect_fb),
+                    width as _,
+                    height as _,
+                )
+                .unwrap();
+
+                f(&image_data, x, y);
             }
         }
+
+        if changed {
+            self.capture();
+        }
+    }
+
+    fn capture(&mut self) {
+        let Some(recording) = self.recording.as_mut() else {
+            return;
+        };
+
+        if recording.size + self.screen_fb.len() > Self::MAX_RECORDING_SIZE {
+            trace!("FB RECORDING FULL, FRAME DROPPED");
+            return;
+        }
+
+        recording.size += self.screen_fb.len();
+        recording
+            .frames
+            .push((js_sys::Date::now(), self.screen_fb.clone()));
+    }
+
+    /// Turns the RGBA pixels of a frame buffer into the orientation the display described by `meta` is shown in
+    pub fn orient(meta: &DisplayMeta, pixels: &[u8]) -> Vec<u8> {
+        if !meta.is_transformed() {
+            return pixels.to_vec();
+        }
+
+        let (width, height) = meta.logical_size();
+
+        let mut oriented = Vec::with_capacity(width * height * Self::PIXEL_SIZE);
+
+        for y in 0..height {
+            for x in 0..width {
+                let pixel = meta
+                    .to_framebuffer(x as _, y as _)
+                    .map(|(x, y)| (meta.width * y + x) * Self::PIXEL_SIZE)
+                    .and_then(|offset| pixels.get(offset..offset + Self::PIXEL_SIZE))
+                    .unwrap_or(&[0, 0, 0, 255]);
+
+                oriented.extend_from_slice(pixel);
+            }
+        }
+
+        oriented
+    }
+
+    fn encode_gif(
+        meta: &DisplayMeta,
+        recording: Recording,
+        end: f64,
+    ) -> Result<Vec<u8>, gif::EncodingError> {
+        let (width, height) = meta.logical_size();
+
+        let mut encoder = gif::Encoder::new(Vec::new(), width as _, height as _, &[])?;
+        encoder.set_repeat(gif::Repeat::Infinite)?;
+
+        let times = recording
+            .frames
+            .iter()
+            .skip(1)
+            .map(|(time, _)| *time)
+            .chain(core::iter::once(end))
+            .collect::<Vec<_>>();
+
+        for ((time, pixels), next) in recording.frames.into_iter().zip(times) {
+            let mut pixels = Self::orient(meta, &pixels);
+
+            let mut frame =
+                gif::Frame::from_rgba_speed(width as _, height as _, &mut pixels, Self::GIF_SPEED);
+
+            // GIF delays are in hundredths of a second, and most viewers do not honor delays shorter than 2
+            frame.delay = ((next - time) / 10.0).round().clamp(2.0, u16::MAX as _) as _;
+
+            encoder.write_frame(&frame)?;
+        }
+
+        Ok(encoder.into_inner()?)
     }
 
     fn extend_screen_fb(&mut self, end: usize) {
@@ -47,24 +47,27 @@ where
     // Dispatch UpdateRequest messages => send to backend
     mcx.register(send);
 
-    // Dispatch UpdateEvent messages => redispatch as PinMsg or DisplayMsg messages
+    // Dispatch UpdateEvent messages => redispatch as PinMsg, DisplayMsg or UartMsg messages
     mcx.register::<UpdateEvent, _>(|mcx: &MiddlewareContext, event| {
         if let Some(msg) = PinMsg::from_event(&event) {
             mcx.invoke(msg);
         } else if let Some(msg) = DisplayMsg::from_event(&event) {
             FrameBuffer::update(mcx.context(), &msg);
             mcx.invoke(msg);
+        } else if let Some(msg) = UartMsg::from_event(&event) {
+            mcx.invoke(msg);
         }
     });
 
     mcx.register(store_dispatch::<PinsStore, PinMsg>());
     mcx.register(store_dispatch::<DisplaysStore, DisplayMsg>());
+    mcx.register(store_dispatch::<UartsStore, UartMsg>());
 
     // Receive from backend => dispatch UpdateEvent messages
     receive();
 }
 
-// Set the middleware for each store type (PinsState & DisplaysState)
+// Set the middleware for each store type (PinsState, DisplaysState & UartsState)
 fn store_dispatch<S, M>() -> impl MiddlewareDispatch<M> + Clone
 where
     S: Store + Debug,
@@ -98,10 +101,11 @@ mod io {
     use core::cell::RefCell;
     use core::fmt::Debug;
 
-    use channel_bridge::asynch::ws::{WsWebReceiver, WsWebSender};
     use channel_bridge::asynch::{Receiver, Sender};
 
-    use log::trace;
+    use core::pin::pin;
+
+    use log::{trace, warn};
 
     use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel};
 
@@ -112,24 +116,39 @@ mod io {
 
     use super::*;
 
-    use gloo_net::websocket::futures::WebSocket;
+    use gloo_net::websocket::{futures::WebSocket, Message, WebSocketError};
+
+    use futures::channel::mpsc;
+    use futures::future::{select, Either};
+    use futures::stream::{SplitSink, SplitStream};
+    use futures::{SinkExt, StreamExt};
 
-    use futures::StreamExt;
+    use gloo_timers::future::TimeoutFuture;
+
+    use crate::dto::wire::{self, WireError};
 
     pub fn init(mcx: &MiddlewareContext, endpoint: Option<&str>) {
         if let Some(endpoint) = endpoint {
-            let (sender, receiver) = WebSocket::open(&format!(
+            let url = format!(
                 "ws://{}{}",
                 web_sys::window().unwrap().location().host().unwrap(),
                 endpoint,
-            ))
-            .unwrap_or_else(|_| panic!("Failed to open websocket"))
-            .split();
-
-            hook(mcx, send(WsWebSender::new(sender)), {
-                let mcx = mcx.clone();
-                move || receive(&mcx, WsWebReceiver::<UpdateEvent>::new(receiver))
-            });
+            );
+
+            let (sender, receiver) = mpsc::unbounded();
+
+            hook(
+                mcx,
+                move |_ctx, request| {
+                    trace!("Sending request: {:?}", request);
+
+                    let _ = sender.unbounded_send(request);
+                },
+                {
+                    let mcx = mcx.clone();
+                    move || connect(&mcx, url, receiver)
+                },
+            );
         } else {
             pub(crate) static REQUEST_QUEUE: channel::Channel<
                 CriticalSectionRawMutex,
@@ -189,6 +208,85 @@ mod io {
         });
     }
 
+    /// Keeps a WebSocket connection to the server at `url`, reconnecting whenever it is closed (e.g. when the server
+    /// restarts)
+    ///
+    /// The server resends its complete state to every new connection, so the state of the UI is reset when a
+    /// connection which has received events is lost.
+    fn connect(
+        mcx: &MiddlewareContext,
+        url: String,
+        mut requests: mpsc::UnboundedReceiver<UpdateRequest>,
+    ) {
+        const RECONNECT_DELAY_MS: u32 = 1000;
+
+        let mcx = mcx.clone();
+
+        spawn_local(async move {
+            loop {
+                match WebSocket::open(&url) {
+                    Ok(ws) => {
+                        // Requests made while disconnected refer to the state of the previous connection
+                        while requests.try_recv().is_ok() {}
+
+                        let (sender, receiver) = ws.split();
+
+                        let mut sender = WsSender(sender);
+                        let mut receiver = WsReceiver {
+                            receiver,
+                            events: Default::default(),
+                        };
+
+                        let mut received = false;
+
+                        let outgoing = async {
+                            while let Some(request) = requests.next().await {
+                                sender.send(request).await?;
+                            }
+
+                            Ok::<_, WsError>(())
+                        };
+
+                        let incoming = async {
+                            loop {
+                                let event = receiver.recv().await?;
+                                trace!("Received event: {:?}", event);
+
+                                received = true;
+                                mcx.invoke(event);
+                            }
+                        };
+
+                        let (Either::Left((result, _)) | Either::Right((result, _))) =
+                            select(pin!(outgoing), pin!(incoming)).await;
+
+                        if let Err(err) = result {
+                            warn!("Connection to {url} lost: {err}");
+                        }
+
+                        if received {
+                            reset(&mcx);
+                        }
+                    }
+                    Err(err) => warn!("Connecting to {url} failed: {err}"),
+                }
+
+                TimeoutFuture::new(RECONNECT_DELAY_MS).await;
+            }
+        });
+    }
+
+    /// Forgets all pins, displays and UARTs, before they are resent by the server
+    fn reset(mcx: &MiddlewareContext) {
+        let context = mcx.context();
+
+        context.set(PinsStore::default());
+        context.set(DisplaysStore::default());
+        context.set(UartsStore::default());
+
+        FrameBuffer::reset();
+    }
+
     fn process_local<S, R>(sender: S, receiver: R)
     where
         S: Sender<Data = UpdateEvent> + 'static,
@@ -198,4 +296,69 @@ mod io {
             crate::io::process(sender, receiver).await;
         });
     }
+
+    #[derive(Debug)]
+    pub enum WsError {
+        Socket(WebSocketError),
+        Wire(WireError),
+        UnexpectedMessage,
+        Closed,
+    }
+
+    impl core::fmt::Display for WsError {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            match self {
+                Self::Socket(err) => write!(f, "WebSocket error: {err}"),
+                Self::Wire(err) => write!(f, "Wire error: {err}"),
+                Self::UnexpectedMessage => write!(f, "Unexpected text message"),
+                Self::Closed => write!(f, "WebSocket closed"),
+            }
+        }
+    }
+
+    /// Sends requests encoded in the `dto::wire` format
+    struct WsSender(SplitSink<WebSocket, Message>);
+
+    impl Sender for WsSender {
+        type Error = WsError;
+
+        type Data = UpdateRequest;
+
+        async fn send(&mut self, data: Self::Data) -> Result<(), Self::Error> {
+            self.0
+                .send(Message::Bytes(wire::encode_request(&data)))
+                .await
+                .map_err(WsError::Socket)
+        }
+    }
+
+    /// Receives the batches of events encoded in the `dto::wire` format, one event at a time
+    struct WsReceiver {
+        receiver: SplitStream<WebSocket>,
+        events: alloc::collections::VecDeque<UpdateEvent>,
+    }
+
+    impl Receiver for WsReceiver {
+        type Error = WsError;
+
+        type Data = UpdateEvent;
+
+        async fn recv(&mut self) -> Result<Self::Data, Self::Error> {
+            loop {
+                if let Some(event) = self.events.pop_front() {
+                    break Ok(event);
+                }
+
+                match self.receiver.next().await {
+                    Some(Ok(Message::Bytes(frame))) => {
+                        self.events
+                            .extend(wire::decode_events(&frame).map_err(WsError::Wire)?);
+                    }
+                    Some(Ok(Message::Text(_))) => break Err(WsError::UnexpectedMessage),
+                    Some(Err(err)) => break Err(WsError::Socket(err)),
+                    None => break Err(WsError::Closed),
+                }
+            }
+        }
+    }
 }
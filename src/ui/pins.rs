@@ -1,8 +1,16 @@
+use core::cell::RefCell;
+
 extern crate alloc;
 use alloc::rc::Rc;
 
+use std::collections::HashSet;
+
 use itertools::Itertools;
-use web_sys::HtmlInputElement;
+
+use wasm_bindgen::{closure::Closure, JsCast};
+use web_sys::{Element, HtmlInputElement};
+
+use gloo_timers::callback::Timeout;
 
 use yew::prelude::*;
 use yewdux::use_store_value;
@@ -84,8 +92,63 @@ pub struct PinState {
 
 #[function_component(Pins)]
 pub fn pins() -> Html {
+    let mcx = use_mcx();
+
     let pins = use_store_value::<PinsStore>();
 
+    // The key listeners are registered once, and see the pins as of the last render
+    let pins_ref = use_mut_ref(|| pins.clone());
+    *pins_ref.borrow_mut() = pins.clone();
+
+    use_effect_with((), move |_| {
+        let held = Rc::new(RefCell::new(HashSet::new()));
+
+        let listener = |down: bool| {
+            let mcx = mcx.clone();
+            let pins_ref = pins_ref.clone();
+            let held = held.clone();
+
+            Closure::<dyn Fn(KeyboardEvent)>::new(move |event: KeyboardEvent| {
+                let pins = pins_ref.borrow().clone();
+
+                if on_key(&mcx, &pins, &mut held.borrow_mut(), &event, down) {
+                    event.prevent_default();
+                }
+            })
+        };
+
+        let keydown = listener(true);
+        let keyup = listener(false);
+
+        // The key up events are lost when the window loses the focus, so release the held pins right away
+        let blur = Closure::<dyn Fn()>::new(move || {
+            for id in held.borrow_mut().drain() {
+                mcx.invoke(PinMsg::InputUpdate(PinInputUpdate::Discrete(id, false)));
+            }
+        });
+
+        let window = web_sys::window().unwrap();
+
+        window
+            .add_event_listener_with_callback("keydown", keydown.as_ref().unchecked_ref())
+            .unwrap();
+        window
+            .add_event_listener_with_callback("keyup", keyup.as_ref().unchecked_ref())
+            .unwrap();
+        window
+            .add_event_listener_with_callback("blur", blur.as_ref().unchecked_ref())
+            .unwrap();
+
+        move || {
+            let _ = window
+                .remove_event_listener_with_callback("keydown", keydown.as_ref().unchecked_ref());
+            let _ =
+                window.remove_event_listener_with_callback("keyup", keyup.as_ref().unchecked_ref());
+            let _ =
+                window.remove_event_listener_with_callback("blur", blur.as_ref().unchecked_ref());
+        }
+    });
+
     let pins = &*pins;
 
     html! {
@@ -93,6 +156,8 @@ pub fn pins() -> Html {
             for pins.0
                 .iter()
                 .enumerate()
+                // The B pins of rotary encoders are turned by the knobs of their A pins
+                .filter(|(_, state)| state.meta.pin_type != PinType::Encoder(EncoderPin::B))
                 .map(|(index, state)| (index, state.meta.category.as_str()))
                 .group_by(|(_, category)| *category)
                 .into_iter()
@@ -109,6 +174,75 @@ pub fn pins() -> Html {
     }
 }
 
+/// Presses or releases the input pins bound to the key of `event`, returning whether there are any
+///
+/// `held` are the click pins pressed with their keys.
+fn on_key(
+    mcx: &MiddlewareContext,
+    pins: &PinsStore,
+    held: &mut HashSet<u8>,
+    event: &KeyboardEvent,
+    down: bool,
+) -> bool {
+    if event.ctrl_key() || event.alt_key() || event.meta_key() {
+        return false;
+    }
+
+    // Keys typed into UART terminals and text fields are not for the pins
+    let typing = event
+        .target()
+        .and_then(|target| target.dyn_into::<Element>().ok())
+        .and_then(|element| element.closest("pre, textarea, input[type=text]").ok())
+        .flatten()
+        .is_some();
+
+    let key = event.key();
+    let mut chars = key.chars();
+
+    let key = match (chars.next(), chars.next()) {
+        (Some(key), None) if !typing => key,
+        _ => return false,
+    };
+
+    let mut bound = false;
+
+    for (id, pin) in pins.0.iter().enumerate() {
+        let id = id as u8;
+
+        let bound_to_key = pin
+            .meta
+            .key
+            .is_some_and(|pin_key| pin_key.to_lowercase().eq(key.to_lowercase()));
+
+        if !bound_to_key || pin.dropped {
+            continue;
+        }
+
+        let input = match pin.value {
+            PinValue::Input(input) | PinValue::InputOutput { input, .. } => input,
+            _ => continue,
+        };
+
+        bound = true;
+
+        if pin.meta.pin_type.is_click() {
+            let changed = if down {
+                held.insert(id)
+            } else {
+                held.remove(&id)
+            };
+
+            if changed {
+                mcx.invoke(PinMsg::InputUpdate(PinInputUpdate::Discrete(id, down)));
+            }
+        } else if down && !event.repeat() {
+            mcx.invoke(PinMsg::InputUpdate(PinInputUpdate::Discrete(id, !input)));
+        }
+    }
+
+    bound
+}
+
 #[derive(Properties, Clone, PartialEq)]
 pub struct PinsPanelProps {
     pub category: String,
@@ -117,17 +251,61 @@ pub struct PinsPanelProps {
 
 #[function_component(PinsPanel)]
 pub fn pins_panel(props: &PinsPanelProps) -> Html {
+    let pins = use_store_value::<PinsStore>();
+
+    let key = |id: &u8| match pins.0[*id as usize].meta.pin_type {
+        PinType::Keypad(KeypadPin::Key { row, column }) => Some((row, column)),
+        _ => None,
+    };
+
+    // The keys of a keypad have consecutive ids, so every run of them is a separate keypad
+    let keypads = props.pins.iter().filter(|id| key(id).is_some()).fold(
+        Vec::<Vec<u8>>::new(),
+        |mut keypads, id| {
+            match keypads.last_mut() {
+                Some(keypad) if keypad.last() == Some(&(id - 1)) => keypad.push(*id),
+                _ => keypads.push(vec![*id]),
+            }
+
+            keypads
+        },
+    );
+
     html! {
         <article class="panel is-primary is-size-7">
             <p class="panel-heading">{ props.category.clone() }</p>
 
             {
-                for props.pins.iter().map(|id| html! {
+                for props.pins.iter().filter(|id| key(id).is_none()).map(|id| html! {
                     <div class="panel-block is-flex">
                         <Pin id={*id}/>
                     </div>
                 })
             }
+
+            {
+                for keypads.into_iter().map(|keys| {
+                    let columns = keys.iter().filter_map(key).map(|(_, column)| column + 1).max().unwrap_or(1);
+
+                    html! {
+                        <div class="panel-block">
+                            <div style={format!("display: grid; grid-template-columns: repeat({columns}, 40px); gap: 4px;")}>
+                                {
+                                    for keys.iter().map(|id| {
+                                        let (row, column) = key(id).unwrap();
+
+                                        html! {
+                                            <div style={format!("grid-row: {}; grid-column: {};", row + 1, column + 1)}>
+                                                <KeypadKey id={*id}/>
+                                            </div>
+                                        }
+                                    })
+                                }
+                            </div>
+                        </div>
+                    }
+                })
+            }
         </article>
     }
 }
@@ -145,6 +323,12 @@ pub fn pin(props: &PinProps) -> Html {
 
     let pin: &PinState = &pins.0[props.id as usize];
 
+    // The knob of a rotary encoder stands for the whole encoder
+    let name = match pin.meta.pin_type {
+        PinType::Encoder(EncoderPin::A { .. }) => pin.meta.name.trim_end_matches(" A"),
+        _ => pin.meta.name.as_str(),
+    };
+
     let (pin_output_high, pin_output_html) = match pin.value {
         PinValue::Output(output) | PinValue::InputOutput { output, .. } => (
             output,
@@ -158,6 +342,28 @@ pub fn pin(props: &PinProps) -> Html {
                 }
             },
         ),
+        PinValue::Pwm { duty, enabled } => {
+            let max_duty = match pin.meta.pin_type {
+                PinType::Pwm(max_duty) => max_duty,
+                _ => unreachable!(),
+            };
+
+            let duty = if enabled && max_duty > 0 {
+                duty as f32 / max_duty as f32
+            } else {
+                0_f32
+            };
+
+            (
+                duty > 0_f32,
+                html! {
+                    <span
+                        class="mr-2"
+                        style={format!("height: 15px; width: 15px; border: 1px solid #bbb; background-color: hsla(348, 100%, 61%, {duty}); border-radius: 50%; display: inline-block;")}
+                    />
+                },
+            )
+        }
         _ => (false, {
             html! {
                 <span class="mr-2" style="height: 15px; width: 15px; display: inline-block;"/>
@@ -166,6 +372,21 @@ pub fn pin(props: &PinProps) -> Html {
     };
 
     let (pin_input_high, pin_input_html) = match pin.value {
+        PinValue::Input(_)
+            if matches!(pin.meta.pin_type, PinType::Encoder(EncoderPin::A { .. })) =>
+        {
+            let steps_per_detent = match pin.meta.pin_type {
+                PinType::Encoder(EncoderPin::A { steps_per_detent }) => steps_per_detent,
+                _ => unreachable!(),
+            };
+
+            (
+                false,
+                html! {
+                    <Knob a={props.id} {steps_per_detent}/>
+                },
+            )
+        }
         PinValue::Input(input) | PinValue::InputOutput { input, .. } => (input, {
             let id = props.id;
             let pins = pins.clone();
@@ -270,6 +491,30 @@ pub fn pin(props: &PinProps) -> Html {
                 </>
             }
         }),
+        PinValue::Pwm { duty, enabled } => (false, {
+            let max_duty = match pin.meta.pin_type {
+                PinType::Pwm(max_duty) => max_duty,
+                _ => unreachable!(),
+            };
+
+            let percent = if enabled && max_duty > 0 {
+                duty as u32 * 100 / max_duty as u32
+            } else {
+                0
+            };
+
+            html! {
+                <>
+                    <input class="input ml-4 is-small py-0" type="text" style="width: 50px;" disabled={true} value={format!("{percent}%")}/>
+                    <progress
+                        class="progress is-small is-danger ml-2 mr-0 my-0"
+                        style="width: 70px;"
+                        value={percent.to_string()}
+                        max="100"
+                    />
+                </>
+            }
+        }),
         _ => (false, {
             html! {
                 <></>
@@ -287,9 +532,182 @@ pub fn pin(props: &PinProps) -> Html {
                     pin_input_high.then_some("has-text-weight-bold"),
                 )}
             >
-                { pin.meta.name.as_str() }
+                { name }
+                if let Some(key) = pin.meta.key {
+                    <span class="tag is-light ml-1" title="Key binding">{ key.to_uppercase().to_string() }</span>
+                }
             </span>
             { pin_input_html }
         </>
     }
 }
+
+#[derive(Properties, Clone, PartialEq)]
+pub struct KnobProps {
+    /// The A pin of the rotary encoder, followed by its B pin
+    pub a: u8,
+    pub steps_per_detent: u8,
+}
+
+/// The knob of a rotary encoder, turned by detents with the mouse wheel or the buttons next to it
+#[function_component(Knob)]
+pub fn knob(props: &KnobProps) -> Html {
+    let mcx = use_mcx();
+
+    // The detents turned so far, for the position of the knob
+    let position = use_mut_ref(|| 0_i32);
+    let rerender = use_force_update();
+
+    let angle = position.borrow().rem_euclid(KNOB_DETENTS_PER_TURN) * 360 / KNOB_DETENTS_PER_TURN;
+
+    // The quadrature steps not applied yet, and whether they are being applied
+    let pending = use_mut_ref(|| (0_i32, false));
+
+    let turn = {
+        let a = props.a;
+        let steps_per_detent = props.steps_per_detent.max(1) as i32;
+
+        Rc::new(move |detents: i32| {
+            *position.borrow_mut() += detents;
+            rerender.force_update();
+
+            let running = {
+                let mut pending = pending.borrow_mut();
+
+                pending.0 += detents * steps_per_detent;
+
+                core::mem::replace(&mut pending.1, true)
+            };
+
+            if !running {
+                step_encoder(mcx.clone(), a, pending.clone());
+            }
+        })
+    };
+
+    let onwheel = {
+        let turn = turn.clone();
+
+        Callback::from(move |event: WheelEvent| {
+            event.prevent_default();
+
+            // Scrolling up turns clockwise
+            match event.delta_y() {
+                delta if delta < 0.0 => turn(1),
+                delta if delta > 0.0 => turn(-1),
+                _ => (),
+            }
+        })
+    };
+
+    let counterclockwise = {
+        let turn = turn.clone();
+        Callback::from(move |_| turn(-1))
+    };
+
+    let clockwise = Callback::from(move |_| turn(1));
+
+    html! {
+        <span class="is-flex is-align-items-center">
+            <button class="button is-small" onclick={counterclockwise}>{"<"}</button>
+            <span
+                class="mx-2"
+                title="Scroll to turn"
+                style={format!(
+                    "position: relative; height: 24px; width: 24px; border: 2px solid hsl(171, 100%, 41%); \
+                     border-radius: 50%; display: inline-block; transform: rotate({angle}deg);"
+                )}
+                {onwheel}
+            >
+                <span style="position: absolute; left: 9px; top: 1px; width: 2px; height: 8px; background-color: hsl(171, 100%, 41%);"/>
+            </span>
+            <button class="button is-small" onclick={clockwise}>{">"}</button>
+        </span>
+    }
+}
+
+/// The detents of a full turn of the knob, as shown
+const KNOB_DETENTS_PER_TURN: i32 = 20;
+
+/// The pause after each quadrature step, so that the firmware can observe it
+const ENCODER_STEP_MS: u32 = 5;
+
+/// Applies the next of the `pending` quadrature steps of the rotary encoder with the A pin `a`, and schedules
+/// the following one
+fn step_encoder(mcx: MiddlewareContext, a: u8, pending: Rc<RefCell<(i32, bool)>>) {
+    let steps = pending.borrow().0;
+
+    // The B pin is created right after the A pin
+    let b = match a.checked_add(1) {
+        Some(b) if steps != 0 => b,
+        _ => {
+            *pending.borrow_mut() = (0, false);
+            return;
+        }
+    };
+
+    pending.borrow_mut().0 -= steps.signum();
+
+    let pins = mcx.context().get::<PinsStore>();
+
+    let level = |id: u8| match pins.0.get(id as usize).map(|pin| pin.value) {
+        Some(PinValue::Input(level)) => level,
+        _ => false,
+    };
+
+    let (level_a, level_b) = (level(a), level(b));
+    let (new_a, new_b) = quadrature_step(level_a, level_b, steps > 0);
+
+    let update = if new_a != level_a {
+        PinInputUpdate::Discrete(a, new_a)
+    } else {
+        PinInputUpdate::Discrete(b, new_b)
+    };
+
+    mcx.invoke(PinMsg::InputUpdate(update));
+
+    Timeout::new(ENCODER_STEP_MS, move || step_encoder(mcx, a, pending)).forget();
+}
+
+#[derive(Properties, Clone, PartialEq)]
+pub struct KeypadKeyProps {
+    pub id: u8,
+}
+
+/// A key of a keypad, pressed while the mouse button is held down over it
+#[function_component(KeypadKey)]
+pub fn keypad_key(props: &KeypadKeyProps) -> Html {
+    let mcx = use_mcx();
+
+    let pins = use_store_value::<PinsStore>();
+
+    let pin: &PinState = &pins.0[props.id as usize];
+
+    let pressed = matches!(pin.value, PinValue::Input(true));
+
+    let press = |pressed: bool| {
+        let mcx = mcx.clone();
+        let id = props.id;
+
+        Callback::from(move |_| {
+            mcx.invoke(PinMsg::InputUpdate(PinInputUpdate::Discrete(id, pressed)));
+        })
+    };
+
+    let title = pin
+        .meta
+        .key
+        .map(|key| format!("Key binding: {}", key.to_uppercase()));
+
+    html! {
+        <button
+            class={classes!("button", "is-small", "is-fullwidth", "is-primary", (!pressed).then_some("is-outlined"))}
+            {title}
+            onmousedown={press(true)}
+            onmouseup={press(false)}
+            onmouseleave={press(false)}
+        >
+            { pin.meta.name.as_str() }
+        </button>
+    }
+}
@@ -0,0 +1,185 @@
+extern crate alloc;
+use alloc::rc::Rc;
+
+use web_sys::Element;
+
+use yew::prelude::*;
+use yewdux::use_store_value;
+use yewdux_middleware::*;
+
+use crate::dto::uart::*;
+use crate::dto::*;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UartMsg {
+    Update(UartUpdate),
+    InputUpdate(UartInputUpdate),
+}
+
+impl UartMsg {
+    pub fn from_event(event: &UpdateEvent) -> Option<Self> {
+        match event {
+            UpdateEvent::UartUpdate(update) => Some(Self::Update(update.clone())),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> From<&'a UartMsg> for Option<UpdateRequest> {
+    fn from(value: &'a UartMsg) -> Self {
+        match value {
+            UartMsg::InputUpdate(update) => Some(UpdateRequest::UartInputUpdate(update.clone())),
+            _ => None,
+        }
+    }
+}
+
+impl Reducer<UartsStore> for UartMsg {
+    fn apply(self, mut store: Rc<UartsStore>) -> Rc<UartsStore> {
+        let state = Rc::make_mut(&mut store);
+        let vec = &mut state.0;
+
+        if let Self::Update(update) = self {
+            while vec.len() <= update.id as _ {
+                vec.push(UartState {
+                    meta: Rc::new(Default::default()),
+                    dropped: false,
+                    output: Rc::new(String::new()),
+                });
+            }
+
+            let state: &mut UartState = &mut vec[update.id as usize];
+
+            if let Some(meta) = &update.meta {
+                state.meta = Rc::new(meta.clone());
+            }
+
+            state.dropped = update.dropped;
+
+            if !update.data.is_empty() {
+                let output = Rc::make_mut(&mut state.output);
+
+                for ch in String::from_utf8_lossy(&update.data).chars() {
+                    match ch {
+                        '\r' => (),
+                        '\x08' | '\x7f' => {
+                            output.pop();
+                        }
+                        ch => output.push(ch),
+                    }
+                }
+
+                if output.len() > UartState::MAX_OUTPUT_LEN {
+                    let mut start = output.len() - UartState::MAX_OUTPUT_LEN;
+                    while !output.is_char_boundary(start) {
+                        start += 1;
+                    }
+
+                    output.drain(..start);
+                }
+            }
+        }
+
+        store
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Store)]
+pub struct UartsStore(Vec<UartState>);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UartState {
+    pub meta: Rc<UartMeta>,
+    pub dropped: bool,
+    pub output: Rc<String>,
+}
+
+impl UartState {
+    const MAX_OUTPUT_LEN: usize = 16384;
+}
+
+#[function_component(Uarts)]
+pub fn uarts() -> Html {
+    let uarts = use_store_value::<UartsStore>();
+    let uarts = &*uarts;
+
+    html! {
+        {
+            for uarts.0.iter().enumerate().map(|(index, _)| {
+                html! {
+                    <Uart id={index as u8} key={index}/>
+                }
+            })
+        }
+    }
+}
+
+#[derive(Properties, Clone, PartialEq)]
+pub struct UartProps {
+    pub id: u8,
+}
+
+#[function_component(Uart)]
+pub fn uart(props: &UartProps) -> Html {
+    let mcx = use_mcx();
+
+    let uarts = use_store_value::<UartsStore>();
+    let uart = &uarts.0[props.id as usize];
+
+    let node_ref = use_node_ref();
+
+    {
+        let node_ref = node_ref.clone();
+
+        // Keep the terminal scrolled to the most recent output
+        use_effect_with(uart.output.clone(), move |_| {
+            if let Some(element) = node_ref.cast::<Element>() {
+                element.set_scroll_top(element.scroll_height());
+            }
+
+            move || ()
+        });
+    }
+
+    let onkeydown = {
+        let id = props.id;
+
+        Callback::from(move |event: KeyboardEvent| {
+            let key = event.key();
+
+            let data: Option<heapless::Vec<u8, UART_MAX_CHUNK_LEN>> = match key.as_str() {
+                "Enter" => Some(heapless::Vec::from_slice(b"\r").unwrap()),
+                "Backspace" => Some(heapless::Vec::from_slice(b"\x08").unwrap()),
+                "Tab" => Some(heapless::Vec::from_slice(b"\t").unwrap()),
+                "Escape" => Some(heapless::Vec::from_slice(b"\x1b").unwrap()),
+                key if key.chars().count() == 1 && !event.ctrl_key() && !event.meta_key() => {
+                    heapless::Vec::from_slice(key.as_bytes()).ok()
+                }
+                _ => None,
+            };
+
+            if let Some(data) = data {
+                event.prevent_default();
+
+                mcx.invoke(UartMsg::InputUpdate(UartInputUpdate { id, data }));
+            }
+        })
+    };
+
+    html! {
+        <article class="panel is-primary is-size-7">
+            <p class="panel-heading">{ uart.meta.name.as_str() }</p>
+            <div class="panel-block">
+                <pre
+                    ref={node_ref}
+                    tabindex="0"
+                    class="is-flex-grow-1 p-2"
+                    style="height: 200px; overflow-y: auto; white-space: pre-wrap; word-break: break-all;"
+                    {onkeydown}
+                >
+                    { uart.output.as_str() }
+                </pre>
+            </div>
+        </article>
+    }
+}